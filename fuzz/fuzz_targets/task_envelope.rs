@@ -0,0 +1,12 @@
+//! Feed arbitrary bytes to the deserialization of the task envelopes received from the gateway.
+//!
+//! Run with `cargo fuzz run task_envelope` from the `fuzz` directory.
+#![no_main]
+
+use lgn_messages::types::MessageEnvelope;
+use lgn_messages::types::TaskType;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = serde_json::from_slice::<MessageEnvelope<TaskType>>(data);
+});
@@ -1,6 +1,9 @@
 use std::fmt::Display;
 use std::fmt::Formatter;
 
+use anyhow::Context;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
 use derive_debug_plus::Dbg;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
@@ -40,6 +43,21 @@ pub enum ReplyType {
     V1Preprocessing(WorkerReply),
     V1Query(WorkerReply),
     V1Groth16(WorkerReply),
+    /// The task is of a class the worker does not prove, for the gateway to route it to another
+    /// worker rather than count it as failed.
+    Unsupported(ProverType),
+}
+
+impl ReplyType {
+    /// Returns the [`WorkerReply`] of the V1 replies.
+    pub fn worker_reply_mut(&mut self) -> Option<&mut WorkerReply> {
+        match self {
+            ReplyType::V1Preprocessing(reply)
+            | ReplyType::V1Query(reply)
+            | ReplyType::V1Groth16(reply) => Some(reply),
+            ReplyType::TxTrie(_) | ReplyType::RecProof(_) | ReplyType::Unsupported(_) => None,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Deserialize, Serialize)]
@@ -71,6 +89,28 @@ pub struct MessageEnvelope<T> {
 
     /// The proving system version
     pub version: String,
+
+    /// Dispatch priority of the task, higher first, ties being broken by arrival order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+
+    /// Unix time, in milliseconds, after which the result of the task is useless to the gateway.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+
+    /// Hex-encoded blake3 hash of the JSON serialization of `inner`, byte for byte as it appears
+    /// in the envelope, for the worker to detect corruption in transit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_checksum: Option<String>,
+
+    /// The tenant the task is proven for, in multi-tenant deployments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+
+    /// Seeds the randomized steps of proving, for the task to be reproduced byte for byte; see
+    /// `lgn_provers::seed` for the provers honoring it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
 }
 impl<T> std::fmt::Debug for MessageEnvelope<T> {
     fn fmt(
@@ -104,9 +144,20 @@ impl<T> MessageEnvelope<T> {
             task_id,
             db_task_id: None,
             version,
+            priority: None,
+            deadline_ms: None,
+            task_checksum: None,
+            tenant_id: None,
+            seed: None,
         }
     }
 
+    /// The time after which the result of the task is useless, if any.
+    pub fn deadline(&self) -> Option<std::time::SystemTime> {
+        self.deadline_ms
+            .map(|ms| std::time::UNIX_EPOCH + std::time::Duration::from_millis(ms))
+    }
+
     pub fn query_id(&self) -> &str {
         &self.query_id
     }
@@ -128,6 +179,24 @@ impl<T> MessageEnvelope<T> {
     }
 }
 
+/// Identifies the prover implementation and the `verifiable_db` version which produced a reply.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProverStamp {
+    /// The prover implementation, e.g. `euclid-query`.
+    pub prover: String,
+    /// The `verifiable_db` version the prover parameters belong to.
+    pub mp2_version: String,
+}
+
+/// The resources consumed by proving a task, for per-task cost accounting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResourceUsage {
+    /// The CPU time spent by the prover, over all its threads, in milliseconds.
+    pub cpu_time_ms: u64,
+    /// The peak resident memory of the prover while proving, in bytes.
+    pub peak_rss_bytes: u64,
+}
+
 #[derive(Clone, PartialEq, Deserialize, Serialize)]
 pub struct MessageReplyEnvelope<T> {
     /// Query id is unique for each query and shared between all its tasks
@@ -139,6 +208,23 @@ pub struct MessageReplyEnvelope<T> {
     inner: T,
 
     error: Option<WorkerError>,
+
+    /// The prover which produced the reply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prover: Option<ProverStamp>,
+
+    /// How many milliseconds the task spent in the worker, from its receipt to its reply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    worker_dwell_ms: Option<u64>,
+
+    /// The resources consumed by proving the task, if measured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resource_usage: Option<ResourceUsage>,
+
+    /// Hex-encoded Blake3 hash of the canonical form of the reply content, for consumers to dedupe
+    /// identical proofs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_hash: Option<String>,
 }
 impl<T> std::fmt::Debug for MessageReplyEnvelope<T> {
     fn fmt(
@@ -160,9 +246,75 @@ impl<T> MessageReplyEnvelope<T> {
             task_id,
             inner,
             error: None,
+            prover: None,
+            worker_dwell_ms: None,
+            resource_usage: None,
+            content_hash: None,
         }
     }
 
+    /// Record the error which prevented producing the reply.
+    pub fn with_error(
+        mut self,
+        error: WorkerError,
+    ) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    /// Record the prover which produced the reply.
+    pub fn with_prover(
+        mut self,
+        prover: ProverStamp,
+    ) -> Self {
+        self.prover = Some(prover);
+        self
+    }
+
+    /// The prover which produced the reply, if recorded.
+    pub fn prover(&self) -> Option<&ProverStamp> {
+        self.prover.as_ref()
+    }
+
+    /// Record the time the task spent in the worker, from its receipt to its reply.
+    pub fn set_worker_dwell_time(
+        &mut self,
+        dwell_time: std::time::Duration,
+    ) {
+        self.worker_dwell_ms = Some(dwell_time.as_millis().try_into().unwrap_or(u64::MAX));
+    }
+
+    /// The time the task spent in the worker, if recorded.
+    pub fn worker_dwell_time(&self) -> Option<std::time::Duration> {
+        self.worker_dwell_ms.map(std::time::Duration::from_millis)
+    }
+
+    /// Record the resources consumed by proving the task.
+    pub fn set_resource_usage(
+        &mut self,
+        resource_usage: ResourceUsage,
+    ) {
+        self.resource_usage = Some(resource_usage);
+    }
+
+    /// The resources consumed by proving the task, if measured.
+    pub fn resource_usage(&self) -> Option<&ResourceUsage> {
+        self.resource_usage.as_ref()
+    }
+
+    /// Record the hex-encoded Blake3 hash of the canonical form of the reply content.
+    pub fn set_content_hash(
+        &mut self,
+        content_hash: String,
+    ) {
+        self.content_hash = Some(content_hash);
+    }
+
+    /// The hash of the canonical form of the reply content, if computed.
+    pub fn content_hash(&self) -> Option<&str> {
+        self.content_hash.as_deref()
+    }
+
     pub fn id(&self) -> String {
         format!("{}-{}", self.query_id, self.task_id)
     }
@@ -188,6 +340,11 @@ impl<T> MessageReplyEnvelope<T> {
     pub fn task_id(&self) -> &str {
         &self.task_id
     }
+
+    /// Mutable access to the proof in this envelope, be it successful or not.
+    pub fn content_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
 }
 
 #[derive(Copy, Clone, Dbg, PartialEq, Eq, Deserialize, Serialize)]
@@ -204,6 +361,24 @@ pub struct WorkerReply {
     pub proof: Option<KeyedPayload>,
 
     pub proof_type: ProofCategory,
+
+    /// Where the proof has been stored when it was too large to be sent inline, in which case
+    /// `proof` is empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof_reference: Option<ProofReference>,
+
+    /// The proof as text when an encoding other than [`ProofEncoding::Raw`] has been requested,
+    /// in which case `proof` is empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoded_proof: Option<EncodedProof>,
+
+    /// How the proof is compressed, before any encoding.
+    #[serde(default, skip_serializing_if = "ProofCompression::is_none")]
+    pub compression: ProofCompression,
+
+    /// The public inputs of a query revelation proof.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_inputs: Option<v1::query::QueryPublicInputs>,
 }
 
 impl WorkerReply {
@@ -217,10 +392,152 @@ impl WorkerReply {
             chain_id,
             proof,
             proof_type,
+            proof_reference: None,
+            encoded_proof: None,
+            compression: ProofCompression::None,
+            public_inputs: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_public_inputs(
+        mut self,
+        public_inputs: Option<v1::query::QueryPublicInputs>,
+    ) -> Self {
+        self.public_inputs = public_inputs;
+        self
+    }
+
+    /// Compress the proof with `compression` at `level`, unless it is shorter than `min_size`
+    /// bytes.
+    ///
+    /// Returns the compression ratio if the proof has been compressed.
+    pub fn compress_proof(
+        &mut self,
+        compression: ProofCompression,
+        level: i32,
+        min_size: usize,
+    ) -> anyhow::Result<Option<f64>> {
+        let Some((_, proof)) = self.proof.as_mut() else {
+            return Ok(None);
+        };
+        if proof.len() < min_size {
+            return Ok(None);
         }
+
+        let compressed = match compression {
+            ProofCompression::None => return Ok(None),
+            ProofCompression::Zstd => {
+                zstd::bulk::compress(proof, level).context("compressing proof with zstd")?
+            },
+        };
+        let ratio = proof.len() as f64 / compressed.len().max(1) as f64;
+        *proof = compressed;
+        self.compression = compression;
+        Ok(Some(ratio))
+    }
+
+    /// Move the proof to `encoded_proof`, encoded with `encoding`.
+    ///
+    /// This is a no-op for [`ProofEncoding::Raw`], the proof staying in `proof`.
+    pub fn encode_proof(
+        &mut self,
+        encoding: ProofEncoding,
+    ) {
+        let encode: fn(&[u8]) -> String = match encoding {
+            ProofEncoding::Raw => return,
+            ProofEncoding::Hex => |proof| hex::encode(proof),
+            ProofEncoding::Base64 => |proof| BASE64_STANDARD.encode(proof),
+        };
+
+        if let Some((key, proof)) = self.proof.take() {
+            self.encoded_proof = Some(EncodedProof {
+                key,
+                encoding,
+                data: encode(&proof),
+            });
+        }
+    }
+
+    /// Move the proof back from `encoded_proof` to `proof`, undoing [`Self::encode_proof`].
+    pub fn decode_proof(&mut self) -> anyhow::Result<()> {
+        let Some(encoded) = self.encoded_proof.take() else {
+            return Ok(());
+        };
+
+        let proof = match encoded.encoding {
+            ProofEncoding::Raw => anyhow::bail!("proof {} is encoded as raw", encoded.key),
+            ProofEncoding::Hex => hex::decode(&encoded.data).context("decoding hex proof")?,
+            ProofEncoding::Base64 => {
+                BASE64_STANDARD
+                    .decode(&encoded.data)
+                    .context("decoding base64 proof")?
+            },
+        };
+        self.proof = Some((encoded.key, proof));
+        Ok(())
+    }
+}
+
+/// How the proof bytes are laid out in a reply.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofEncoding {
+    /// The proof is sent as a JSON array of bytes in `proof`.
+    #[default]
+    Raw,
+
+    /// The proof is sent as a hex string in `encoded_proof`.
+    Hex,
+
+    /// The proof is sent as a standard base64 string in `encoded_proof`.
+    Base64,
+}
+
+/// How the proof bytes are compressed in a reply.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofCompression {
+    /// The proof is sent as is.
+    #[default]
+    None,
+
+    /// The proof is compressed with Zstandard.
+    Zstd,
+}
+
+impl ProofCompression {
+    pub fn is_none(&self) -> bool {
+        *self == ProofCompression::None
     }
 }
 
+/// A proof encoded as text.
+#[derive(Clone, Dbg, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EncodedProof {
+    /// The storage key of the proof.
+    pub key: String,
+
+    /// How `data` is encoded.
+    pub encoding: ProofEncoding,
+
+    #[dbg(placeholder = "...")]
+    pub data: String,
+}
+
+/// Location of a proof stored out-of-band, sent in place of the proof itself.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProofReference {
+    /// The storage key of the proof.
+    pub key: String,
+
+    /// Where the proof can be fetched from.
+    pub url: String,
+
+    /// Hex-encoded Blake3 hash of the proof.
+    pub hash: String,
+}
+
 #[derive(Error, Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum WorkerError {
     // Start with general error to introduce the errors to replies
@@ -386,7 +703,7 @@ pub fn kp_pretty(kp: &Option<KeyedPayload>) -> String {
         .unwrap_or("empty".to_string())
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum ProverType {
     /// V0 query preprocessing handler.
     Query2Preprocess,
@@ -404,6 +721,12 @@ pub enum ProverType {
     V1Query,
 
     V1Groth16,
+
+    /// Experimental transaction trie handler.
+    TxTrie,
+
+    /// Experimental recursive proof handler.
+    RecProof,
 }
 
 impl Display for ProverType {
@@ -422,6 +745,8 @@ impl Display for ProverType {
                 ProverType::V1Preprocessing => "V1Preprocessing",
                 ProverType::V1Query => "V1Query",
                 ProverType::V1Groth16 => "V1Groth16",
+                ProverType::TxTrie => "TxTrie",
+                ProverType::RecProof => "RecProof",
             }
         )
     }
@@ -429,6 +754,22 @@ impl Display for ProverType {
 
 pub trait ToProverType {
     fn to_prover_type(&self) -> ProverType;
+
+    /// The kind of final extraction performed by the task, if any, as it weighs on its proving
+    /// cost.
+    fn extraction_type(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl TaskType {
+    /// The table the task belongs to, if it is tied to a table ID.
+    pub fn table_id(&self) -> Option<crate::TableId> {
+        match self {
+            TaskType::V1Preprocessing(task) => task.table_id(),
+            _ => None,
+        }
+    }
 }
 
 impl ToProverType for TaskType {
@@ -437,9 +778,43 @@ impl ToProverType for TaskType {
             TaskType::V1Preprocessing(_) => ProverType::V1Preprocessing,
             TaskType::V1Query(_) => ProverType::V1Query,
             TaskType::V1Groth16(_) => ProverType::V1Groth16,
-            _ => {
-                panic!("Unsupported task type: {:?}", self)
+            TaskType::TxTrie(_) => ProverType::TxTrie,
+            TaskType::RecProof(_) => ProverType::RecProof,
+        }
+    }
+
+    fn extraction_type(&self) -> Option<&'static str> {
+        match self {
+            TaskType::V1Preprocessing(v1::preprocessing::WorkerTask {
+                task_type:
+                    v1::preprocessing::WorkerTaskType::Extraction(
+                        v1::preprocessing::ext_tasks::ExtractionType::FinalExtraction(
+                            final_extraction,
+                        ),
+                    ),
+                ..
+            }) => {
+                match final_extraction.as_ref() {
+                    v1::preprocessing::ext_tasks::FinalExtraction::Single(extraction) => {
+                        Some(extraction.extraction_type.as_label())
+                    },
+                    v1::preprocessing::ext_tasks::FinalExtraction::Merge(_) => None,
+                }
             },
+            _ => None,
+        }
+    }
+}
+
+impl ToProverType for ReplyType {
+    fn to_prover_type(&self) -> ProverType {
+        match self {
+            ReplyType::V1Preprocessing(_) => ProverType::V1Preprocessing,
+            ReplyType::V1Query(_) => ProverType::V1Query,
+            ReplyType::V1Groth16(_) => ProverType::V1Groth16,
+            ReplyType::TxTrie(_) => ProverType::TxTrie,
+            ReplyType::RecProof(_) => ProverType::RecProof,
+            ReplyType::Unsupported(prover_type) => *prover_type,
         }
     }
 }
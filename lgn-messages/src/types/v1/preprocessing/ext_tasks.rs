@@ -1,6 +1,10 @@
 use alloy_primitives::Address;
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
 use derive_debug_plus::Dbg;
 use ethers::types::H256;
+use ethers::utils::keccak256;
 use ethers::utils::rlp;
 use mp2_common::digest::TableDimension;
 use serde_derive::Deserialize;
@@ -9,6 +13,7 @@ use serde_derive::Serialize;
 use crate::types::v1::preprocessing::ext_keys::ProofKey;
 use crate::types::v1::preprocessing::WorkerTask;
 use crate::types::v1::preprocessing::WorkerTaskType;
+use crate::types::ProofReference;
 use crate::BlockNr;
 use crate::TableHash;
 use crate::TableId;
@@ -57,6 +62,35 @@ impl Mpt {
             mpt_type,
         }
     }
+
+    /// Ensure the node to prove is the one designated by `node_hash`, and of the kind its task
+    /// proves, before handing it to the prover.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let node = self.mpt_type.node();
+        ensure!(!node.is_empty(), "missing node");
+        let hash = H256::from(keccak256(node));
+        ensure!(
+            hash == self.node_hash,
+            "node hashes to {hash:?} rather than node_hash {:?}",
+            self.node_hash
+        );
+        let node_type = node_type(node).context("invalid node")?;
+        match &self.mpt_type {
+            MptType::MappingLeaf(_) | MptType::VariableLeaf(_) => {
+                ensure!(
+                    node_type == MPTExtractionType::Leaf,
+                    "expected a leaf node, got {node_type:?}"
+                );
+            },
+            MptType::MappingBranch(_) | MptType::VariableBranch(_) => {
+                ensure!(
+                    node_type != MPTExtractionType::Leaf,
+                    "expected a branch or extension node, got {node_type:?}"
+                );
+            },
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -74,6 +108,36 @@ pub enum MptType {
     VariableBranch(VariableBranchInput),
 }
 
+impl MptType {
+    /// The RLP-encoded MPT node to prove.
+    pub fn node(&self) -> &[u8] {
+        match self {
+            MptType::MappingLeaf(leaf) => &leaf.node,
+            MptType::MappingBranch(branch) => &branch.node,
+            MptType::VariableLeaf(leaf) => &leaf.node,
+            MptType::VariableBranch(branch) => &branch.node,
+        }
+    }
+
+    /// Returns the references to the children proofs stored out-of-band of a branch, along with
+    /// the inline children proofs they stand for.
+    pub fn children_proofs_refs_mut(&mut self) -> Option<(&[ProofReference], &mut Vec<Vec<u8>>)> {
+        match self {
+            MptType::MappingBranch(MappingBranchInput {
+                children_proofs_refs,
+                children_proofs,
+                ..
+            })
+            | MptType::VariableBranch(VariableBranchInput {
+                children_proofs_refs,
+                children_proofs,
+                ..
+            }) => Some((children_proofs_refs, children_proofs)),
+            MptType::MappingLeaf(_) | MptType::VariableLeaf(_) => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct MappingLeafInput {
     pub key: Vec<u8>,
@@ -99,8 +163,32 @@ impl MappingLeafInput {
             value_id,
         }
     }
+
+    /// Ensure the leaf is well-formed before handing it to the prover.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(!self.node.is_empty(), "missing node");
+        let node_type = node_type(&self.node).context("invalid node")?;
+        ensure!(
+            node_type == MPTExtractionType::Leaf,
+            "expected a leaf node, got {node_type:?}"
+        );
+        ensure!(
+            (1..=MAX_MAPPING_KEY_LEN).contains(&self.key.len()),
+            "invalid key length {}, expected 1 to {MAX_MAPPING_KEY_LEN} bytes",
+            self.key.len()
+        );
+        ensure!(
+            self.key_id != self.value_id,
+            "key_id and value_id must differ, both are {}",
+            self.key_id
+        );
+        Ok(())
+    }
 }
 
+/// Mapping keys are at most a storage word.
+const MAX_MAPPING_KEY_LEN: usize = 32;
+
 #[derive(Dbg, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct MappingBranchInput {
     pub node: Vec<u8>,
@@ -109,6 +197,11 @@ pub struct MappingBranchInput {
 
     #[dbg(placeholder = "...")]
     pub children_proofs: Vec<Vec<u8>>,
+
+    /// Where to fetch the children proofs from, in the order of `children`, when they are not
+    /// sent inline in `children_proofs`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children_proofs_refs: Vec<ProofReference>,
 }
 
 impl MappingBranchInput {
@@ -120,8 +213,24 @@ impl MappingBranchInput {
             node,
             children,
             children_proofs: vec![],
+            children_proofs_refs: vec![],
         }
     }
+
+    /// Set the proofs of the children, in the order of `children`.
+    pub fn set_children_proofs(
+        &mut self,
+        proofs: Vec<Vec<u8>>,
+    ) -> anyhow::Result<()> {
+        check_children_proofs_count(&self.children, &proofs)?;
+        self.children_proofs = proofs;
+        Ok(())
+    }
+
+    /// Ensure there is a proof for every child before handing the branch to the prover.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        check_children_proofs_count(&self.children, &self.children_proofs)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -153,6 +262,11 @@ pub struct VariableBranchInput {
 
     #[dbg(placeholder = "...")]
     pub children_proofs: Vec<Vec<u8>>,
+
+    /// Where to fetch the children proofs from, in the order of `children`, when they are not
+    /// sent inline in `children_proofs`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children_proofs_refs: Vec<ProofReference>,
 }
 
 impl VariableBranchInput {
@@ -166,8 +280,38 @@ impl VariableBranchInput {
             node,
             children,
             children_proofs: vec![],
+            children_proofs_refs: vec![],
         }
     }
+
+    /// Set the proofs of the children, in the order of `children`.
+    pub fn set_children_proofs(
+        &mut self,
+        proofs: Vec<Vec<u8>>,
+    ) -> anyhow::Result<()> {
+        check_children_proofs_count(&self.children, &proofs)?;
+        self.children_proofs = proofs;
+        Ok(())
+    }
+
+    /// Ensure there is a proof for every child before handing the branch to the prover.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        check_children_proofs_count(&self.children, &self.children_proofs)
+    }
+}
+
+/// The circuits of the branches index the children proofs by the position of the children.
+fn check_children_proofs_count(
+    children: &[MptNodeVersion],
+    children_proofs: &[Vec<u8>],
+) -> anyhow::Result<()> {
+    ensure!(
+        children_proofs.len() == children.len(),
+        "{} children proofs for {} children",
+        children_proofs.len(),
+        children.len()
+    );
+    Ok(())
 }
 
 #[derive(Clone, Dbg, PartialEq, Deserialize, Serialize)]
@@ -185,6 +329,7 @@ pub struct Length {
 ///
 /// This is used to associate the gas / time to the proof to generate, especially for tasks where
 /// there are many proofs to generate inside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MPTExtractionType {
     Branch,
     Extension,
@@ -207,6 +352,46 @@ impl MPTExtractionType {
     }
 }
 
+/// Decode the type of an RLP-encoded MPT node, telling leaves and extensions apart by the flag of
+/// their hex-prefix encoded path.
+pub fn node_type(node: &[u8]) -> anyhow::Result<MPTExtractionType> {
+    let rlp = rlp::Rlp::new(node);
+    let items = rlp.item_count().context("node is not an RLP list")?;
+    match items {
+        17 => Ok(MPTExtractionType::Branch),
+        2 => {
+            let path: Vec<u8> = rlp.val_at(0).context("invalid node path")?;
+            match path.first().map(|b| b >> 4) {
+                Some(0 | 1) => Ok(MPTExtractionType::Extension),
+                Some(2 | 3) => Ok(MPTExtractionType::Leaf),
+                _ => bail!("invalid node path prefix"),
+            }
+        },
+        n => bail!("invalid node with {n} items"),
+    }
+}
+
+/// Ensure `nodes` is a path of MPT nodes from a leaf up, as proven one after the other, the first
+/// one as a leaf and the others as branches or extensions.
+fn check_mpt_path(nodes: &[Vec<u8>]) -> anyhow::Result<()> {
+    ensure!(!nodes.is_empty(), "missing nodes");
+    for (i, node) in nodes.iter().enumerate() {
+        let node_type = node_type(node).with_context(|| format!("invalid node {i}"))?;
+        if i == 0 {
+            ensure!(
+                node_type == MPTExtractionType::Leaf,
+                "node 0 is not a leaf node, found {node_type:?}"
+            );
+        } else {
+            ensure!(
+                node_type != MPTExtractionType::Leaf,
+                "node {i} is a leaf node, expected a branch or extension node"
+            );
+        }
+    }
+    Ok(())
+}
+
 impl Length {
     pub fn extraction_types(&self) -> Vec<MPTExtractionType> {
         self.nodes
@@ -215,6 +400,11 @@ impl Length {
             .map(|(i, n)| MPTExtractionType::from_rlp_node(n, i))
             .collect()
     }
+
+    /// Ensure the nodes are a path from a leaf up before handing them to the prover.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        check_mpt_path(&self.nodes)
+    }
 }
 
 #[derive(Dbg, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -235,6 +425,11 @@ impl Contract {
             .map(|(i, n)| MPTExtractionType::from_rlp_node(n, i))
             .collect()
     }
+
+    /// Ensure the nodes are a path from a leaf up before handing them to the prover.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        check_mpt_path(&self.nodes)
+    }
 }
 
 #[derive(Clone, Dbg, PartialEq, Deserialize, Serialize)]
@@ -257,7 +452,7 @@ pub enum FinalExtraction {
 }
 
 impl FinalExtraction {
-    fn table_id(&self) -> BlockNr {
+    pub(crate) fn table_id(&self) -> TableId {
         match self {
             FinalExtraction::Single(single_table_extraction) => single_table_extraction.table_id,
             FinalExtraction::Merge(merge_table_extraction) => merge_table_extraction.table_id,
@@ -271,6 +466,23 @@ impl FinalExtraction {
         }
     }
 
+    /// Returns the reference to the contract proof stored out-of-band, if any, along with the
+    /// inline contract proof it stands for.
+    pub fn contract_proof_ref_mut(&mut self) -> (Option<&ProofReference>, &mut Vec<u8>) {
+        match self {
+            FinalExtraction::Single(SingleTableExtraction {
+                contract_proof_ref,
+                contract_proof,
+                ..
+            })
+            | FinalExtraction::Merge(MergeTableExtraction {
+                contract_proof_ref,
+                contract_proof,
+                ..
+            }) => (contract_proof_ref.as_ref(), contract_proof),
+        }
+    }
+
     pub fn new_single_table(
         table_id: TableId,
         table_hash: TableHash,
@@ -329,6 +541,11 @@ pub struct SingleTableExtraction {
     #[dbg(placeholder = "...")]
     pub contract_proof: Vec<u8>,
 
+    /// Where to fetch the contract proof from, e.g. the output of a contract extraction proven as
+    /// a task of its own, when it is not sent inline in `contract_proof`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contract_proof_ref: Option<ProofReference>,
+
     #[dbg(placeholder = "...")]
     pub value_proof: Vec<u8>,
 
@@ -359,10 +576,22 @@ impl SingleTableExtraction {
             extraction_type,
             block_proof: vec![],
             contract_proof: vec![],
+            contract_proof_ref: None,
             value_proof: vec![],
             length_proof: vec![],
         }
     }
+
+    /// Ensure all the proofs required by the extraction type have been set.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(!self.block_proof.is_empty(), "missing block_proof");
+        ensure!(!self.contract_proof.is_empty(), "missing contract_proof");
+        ensure!(!self.value_proof.is_empty(), "missing value_proof");
+        if self.extraction_type == FinalExtractionType::Lengthed {
+            ensure!(!self.length_proof.is_empty(), "missing length_proof");
+        }
+        Ok(())
+    }
 }
 
 /// Inputs for a merge table proof.
@@ -392,6 +621,11 @@ pub struct MergeTableExtraction {
     #[dbg(placeholder = "...")]
     pub contract_proof: Vec<u8>,
 
+    /// Where to fetch the contract proof from, e.g. the output of a contract extraction proven as
+    /// a task of its own, when it is not sent inline in `contract_proof`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contract_proof_ref: Option<ProofReference>,
+
     #[dbg(placeholder = "...")]
     pub simple_table_proof: Vec<u8>,
 
@@ -417,10 +651,26 @@ impl MergeTableExtraction {
             value_proof_version,
             block_proof: vec![],
             contract_proof: vec![],
+            contract_proof_ref: None,
             simple_table_proof: vec![],
             mapping_table_proof: vec![],
         }
     }
+
+    /// Ensure all the proofs required by the merge have been set.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(!self.block_proof.is_empty(), "missing block_proof");
+        ensure!(!self.contract_proof.is_empty(), "missing contract_proof");
+        ensure!(
+            !self.simple_table_proof.is_empty(),
+            "missing simple_table_proof"
+        );
+        ensure!(
+            !self.mapping_table_proof.is_empty(),
+            "missing mapping_table_proof"
+        );
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -429,6 +679,16 @@ pub enum FinalExtractionType {
     Lengthed,
 }
 
+impl FinalExtractionType {
+    /// How the extraction type is reported in logs and metrics.
+    pub fn as_label(&self) -> &'static str {
+        match self {
+            FinalExtractionType::Simple(_) => "simple",
+            FinalExtractionType::Lengthed => "lengthed",
+        }
+    }
+}
+
 impl From<&WorkerTask> for ProofKey {
     fn from(task: &WorkerTask) -> Self {
         match &task.task_type {
@@ -492,3 +752,294 @@ impl From<&WorkerTask> for ProofKey {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use mp2_common::digest::TableDimension;
+
+    use super::*;
+
+    fn single_table(extraction_type: FinalExtractionType) -> SingleTableExtraction {
+        SingleTableExtraction {
+            extraction_type,
+            block_proof: vec![1],
+            contract_proof: vec![1],
+            value_proof: vec![1],
+            length_proof: vec![1],
+            ..SingleTableExtraction::new(1, 2, 3, Address::ZERO, None, (3, H256::zero()))
+        }
+    }
+
+    fn merge_table() -> MergeTableExtraction {
+        MergeTableExtraction {
+            block_proof: vec![1],
+            contract_proof: vec![1],
+            simple_table_proof: vec![1],
+            mapping_table_proof: vec![1],
+            ..MergeTableExtraction::new(1, 2, 3, 4, Address::ZERO, (4, H256::zero()))
+        }
+    }
+
+    fn assert_missing<T>(
+        result: anyhow::Result<T>,
+        field: &str,
+    ) {
+        let err = result.err().expect("validation should fail");
+        assert_eq!(err.to_string(), format!("missing {field}"));
+    }
+
+    #[test]
+    fn test_single_table_validation() {
+        for extraction_type in [
+            FinalExtractionType::Simple(TableDimension::Single),
+            FinalExtractionType::Lengthed,
+        ] {
+            let extraction = single_table(extraction_type.clone());
+            extraction.validate().unwrap();
+
+            let mut e = extraction.clone();
+            e.block_proof.clear();
+            assert_missing(e.validate(), "block_proof");
+
+            let mut e = extraction.clone();
+            e.contract_proof.clear();
+            assert_missing(e.validate(), "contract_proof");
+
+            let mut e = extraction.clone();
+            e.value_proof.clear();
+            assert_missing(e.validate(), "value_proof");
+
+            let mut e = extraction.clone();
+            e.length_proof.clear();
+            if extraction_type == FinalExtractionType::Lengthed {
+                assert_missing(e.validate(), "length_proof");
+            } else {
+                e.validate().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_single_table_serde() {
+        for (compound, extraction_type) in [
+            (
+                Some(TableDimension::Single),
+                FinalExtractionType::Simple(TableDimension::Single),
+            ),
+            (None, FinalExtractionType::Lengthed),
+        ] {
+            let extraction = FinalExtraction::new_single_table(
+                1,
+                2,
+                3,
+                Address::ZERO,
+                compound,
+                (3, H256::zero()),
+            );
+            let json = serde_json::to_string(&extraction).unwrap();
+            let decoded: FinalExtraction = serde_json::from_str(&json).unwrap();
+            assert_eq!(decoded, extraction);
+
+            let FinalExtraction::Single(single) = decoded else {
+                panic!("expected a single table extraction");
+            };
+            assert_eq!(single.extraction_type, extraction_type);
+        }
+    }
+
+    #[test]
+    fn test_merge_table_validation() {
+        let extraction = merge_table();
+        extraction.validate().unwrap();
+
+        let mut e = extraction.clone();
+        e.block_proof.clear();
+        assert_missing(e.validate(), "block_proof");
+
+        let mut e = extraction.clone();
+        e.contract_proof.clear();
+        assert_missing(e.validate(), "contract_proof");
+
+        let mut e = extraction.clone();
+        e.simple_table_proof.clear();
+        assert_missing(e.validate(), "simple_table_proof");
+
+        let mut e = extraction.clone();
+        e.mapping_table_proof.clear();
+        assert_missing(e.validate(), "mapping_table_proof");
+    }
+
+    fn mpt_node(
+        path: &[u8],
+        value: &[u8],
+    ) -> Vec<u8> {
+        let mut stream = rlp::RlpStream::new_list(2);
+        stream.append(&path.to_vec()).append(&value.to_vec());
+        stream.out().to_vec()
+    }
+
+    fn mapping_leaf() -> MappingLeafInput {
+        MappingLeafInput::new(vec![1; 32], mpt_node(&[0x20, 0xAB], &[0x2A]), 3, 1, 2)
+    }
+
+    fn assert_invalid(
+        result: anyhow::Result<()>,
+        message: &str,
+    ) {
+        let err = result.err().expect("validation should fail");
+        assert!(
+            err.to_string().contains(message),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[test]
+    fn test_mapping_leaf_validation() {
+        let leaf = mapping_leaf();
+        leaf.validate().unwrap();
+
+        let mut e = leaf.clone();
+        e.node.clear();
+        assert_missing(e.validate(), "node");
+
+        let mut e = leaf.clone();
+        e.node = vec![0x01, 0x02];
+        assert_invalid(e.validate(), "invalid node");
+
+        let mut e = leaf.clone();
+        e.node = mpt_node(&[0x00, 0xAB], &[0x2A]);
+        assert_invalid(e.validate(), "expected a leaf node, got Extension");
+
+        let mut e = leaf.clone();
+        e.key.clear();
+        assert_invalid(e.validate(), "invalid key length 0");
+
+        let mut e = leaf.clone();
+        e.key = vec![1; 33];
+        assert_invalid(e.validate(), "invalid key length 33");
+
+        let mut e = leaf.clone();
+        e.value_id = e.key_id;
+        assert_invalid(e.validate(), "key_id and value_id must differ");
+    }
+
+    #[test]
+    fn test_branch_children_proofs() {
+        let children = vec![(1, H256::repeat_byte(1)), (2, H256::repeat_byte(2))];
+
+        let mut mapping = MappingBranchInput::new(vec![0x80], children.clone());
+        assert_invalid(mapping.validate(), "0 children proofs for 2 children");
+        assert_invalid(
+            mapping.set_children_proofs(vec![vec![1]]),
+            "1 children proofs for 2 children",
+        );
+        assert!(mapping.children_proofs.is_empty());
+        mapping.set_children_proofs(vec![vec![1], vec![2]]).unwrap();
+        mapping.validate().unwrap();
+
+        let mut variable = VariableBranchInput::new(1, vec![0x80], children);
+        assert_invalid(
+            variable.set_children_proofs(vec![vec![1]; 3]),
+            "3 children proofs for 2 children",
+        );
+        variable
+            .set_children_proofs(vec![vec![1], vec![2]])
+            .unwrap();
+        variable.validate().unwrap();
+
+        // Proofs set directly are only checked when validating.
+        variable.children_proofs.pop();
+        assert_invalid(variable.validate(), "1 children proofs for 2 children");
+    }
+
+    #[test]
+    fn test_mpt_path_validation() {
+        let leaf = mpt_node(&[0x20, 0xAB], &[0x2A]);
+        let extension = mpt_node(&[0x00, 0xAB], &[0x2A; 32]);
+        let length = |nodes: Vec<Vec<u8>>| {
+            Length {
+                table_hash: 1,
+                block_nr: 2,
+                length_slot: 3,
+                variable_slot: 4,
+                nodes,
+            }
+        };
+        let contract = |nodes: Vec<Vec<u8>>| {
+            Contract {
+                block_nr: 2,
+                storage_root: vec![0; 32],
+                contract: Address::ZERO,
+                nodes,
+            }
+        };
+
+        length(vec![leaf.clone(), extension.clone()])
+            .validate()
+            .unwrap();
+        contract(vec![leaf.clone()]).validate().unwrap();
+
+        assert_missing(length(vec![]).validate(), "nodes");
+        assert_missing(contract(vec![]).validate(), "nodes");
+
+        // Malformed RLP.
+        let err = contract(vec![leaf.clone(), vec![0x2A]])
+            .validate()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invalid node 1");
+
+        assert_invalid(
+            length(vec![extension.clone(), leaf.clone()]).validate(),
+            "node 0 is not a leaf node, found Extension",
+        );
+        assert_invalid(
+            contract(vec![leaf.clone(), leaf]).validate(),
+            "node 1 is a leaf node, expected a branch or extension node",
+        );
+    }
+
+    #[test]
+    fn test_mpt_validation() {
+        let leaf = mpt_node(&[0x20, 0xAB], &[0x2A]);
+        let extension = mpt_node(&[0x00, 0xAB], &[0x2A; 32]);
+        let mpt = |node: &[u8], mpt_type| Mpt::new(1, 2, H256::from(keccak256(node)), mpt_type);
+
+        mpt(
+            &leaf,
+            MptType::VariableLeaf(VariableLeafInput::new(leaf.clone(), 3, 4)),
+        )
+        .validate()
+        .unwrap();
+        mpt(
+            &extension,
+            MptType::MappingBranch(MappingBranchInput::new(extension.clone(), vec![])),
+        )
+        .validate()
+        .unwrap();
+
+        let mut e = mpt(&leaf, MptType::MappingLeaf(mapping_leaf()));
+        e.node_hash = H256::zero();
+        assert_invalid(e.validate(), "rather than node_hash 0x0000");
+
+        let e = mpt(
+            &leaf,
+            MptType::VariableBranch(VariableBranchInput::new(1, leaf.clone(), vec![])),
+        );
+        assert_invalid(
+            e.validate(),
+            "expected a branch or extension node, got Leaf",
+        );
+
+        let e = mpt(
+            &extension,
+            MptType::VariableLeaf(VariableLeafInput::new(extension.clone(), 3, 4)),
+        );
+        assert_invalid(e.validate(), "expected a leaf node, got Extension");
+
+        let e = mpt(
+            &[],
+            MptType::VariableLeaf(VariableLeafInput::new(vec![], 3, 4)),
+        );
+        assert_missing(e.validate(), "node");
+    }
+}
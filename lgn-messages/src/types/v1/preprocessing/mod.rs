@@ -9,6 +9,8 @@ use crate::types::v1::preprocessing::db_tasks::CellFullInput;
 use crate::types::v1::preprocessing::db_tasks::CellLeafInput;
 use crate::types::v1::preprocessing::db_tasks::CellPartialInput;
 use crate::types::v1::preprocessing::db_tasks::DatabaseType;
+use crate::types::v1::preprocessing::db_tasks::DbCellType;
+use crate::types::v1::preprocessing::db_tasks::DbRowType;
 use crate::types::v1::preprocessing::db_tasks::IvcInput;
 use crate::types::v1::preprocessing::db_tasks::RowLeafInput;
 use crate::types::v1::preprocessing::ext_tasks::BlockExtractionInput;
@@ -61,6 +63,36 @@ impl WorkerTask {
             task_type,
         }
     }
+
+    /// The table the task belongs to, if it is tied to a table ID.
+    pub fn table_id(&self) -> Option<TableId> {
+        match &self.task_type {
+            WorkerTaskType::Extraction(extraction) => {
+                match extraction {
+                    ExtractionType::MptExtraction(Mpt {
+                        mpt_type: MptType::VariableBranch(branch),
+                        ..
+                    }) => Some(branch.table_id),
+                    ExtractionType::FinalExtraction(final_extraction) => {
+                        Some(final_extraction.table_id())
+                    },
+                    _ => None,
+                }
+            },
+            WorkerTaskType::Database(database) => {
+                Some(match database {
+                    DatabaseType::Cell(DbCellType::Leaf(cell)) => cell.table_id,
+                    DatabaseType::Cell(DbCellType::Partial(cell)) => cell.table_id,
+                    DatabaseType::Cell(DbCellType::Full(cell)) => cell.table_id,
+                    DatabaseType::Row(DbRowType::Leaf(row)) => row.table_id,
+                    DatabaseType::Row(DbRowType::Partial(row)) => row.table_id,
+                    DatabaseType::Row(DbRowType::Full(row)) => row.table_id,
+                    DatabaseType::Index(index) => index.table_id,
+                    DatabaseType::IVC(ivc) => ivc.table_id,
+                })
+            },
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
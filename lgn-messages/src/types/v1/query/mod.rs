@@ -1,6 +1,9 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 
 use alloy_primitives::U256;
+use anyhow::bail;
+use anyhow::Context;
 use derive_debug_plus::Dbg;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
@@ -53,6 +56,63 @@ pub enum WorkerTaskType {
 #[derive(Dbg, Clone, PartialEq, Deserialize, Serialize)]
 pub struct PlaceHolderLgn(HashMap<String, U256>);
 
+/// The public inputs a revelation proof has been generated for, for consumers to verify it
+/// without reconstructing them from the query.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct QueryPublicInputs {
+    /// The first block of the queried range.
+    pub min_block: U256,
+    /// The last block of the queried range.
+    pub max_block: U256,
+    /// The values of the generic placeholders, by index.
+    pub placeholders: BTreeMap<usize, U256>,
+    /// The maximum number of rows returned by a tabular query.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    /// The number of rows skipped by a tabular query.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+}
+
+impl QueryPublicInputs {
+    pub fn new(placeholders: &PlaceHolderLgn) -> anyhow::Result<Self> {
+        let mut min_block = None;
+        let mut max_block = None;
+        let mut generic = BTreeMap::new();
+        for (key, value) in &placeholders.0 {
+            // Numbered as in `From<Placeholders> for PlaceHolderLgn`.
+            match key.parse::<usize>() {
+                Ok(0) => min_block = Some(*value),
+                Ok(1) => max_block = Some(*value),
+                Ok(index) => {
+                    generic.insert(index - 1, *value);
+                },
+                Err(_) => bail!("invalid placeholder `{key}`"),
+            }
+        }
+
+        Ok(Self {
+            min_block: min_block.context("missing min block placeholder")?,
+            max_block: max_block.context("missing max block placeholder")?,
+            placeholders: generic,
+            limit: None,
+            offset: None,
+        })
+    }
+
+    /// Set the pagination of a tabular query.
+    #[must_use]
+    pub fn with_pagination(
+        mut self,
+        limit: u32,
+        offset: u32,
+    ) -> Self {
+        self.limit = Some(limit);
+        self.offset = Some(offset);
+        self
+    }
+}
+
 impl From<PlaceHolderLgn> for Placeholders {
     fn from(ph: PlaceHolderLgn) -> Self {
         let min_block = ph.0.get("0").cloned().unwrap();
@@ -87,3 +147,37 @@ impl From<Placeholders> for PlaceHolderLgn {
         PlaceHolderLgn(map)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_public_inputs() {
+        let mut placeholders = Placeholders::new_empty(U256::from(10), U256::from(20));
+        placeholders.insert(PlaceholderIdentifier::Generic(1), U256::from(42));
+        placeholders.insert(PlaceholderIdentifier::Generic(2), U256::from(7));
+
+        let public_inputs = QueryPublicInputs::new(&placeholders.into())
+            .unwrap()
+            .with_pagination(5, 15);
+        assert_eq!(
+            public_inputs,
+            QueryPublicInputs {
+                min_block: U256::from(10),
+                max_block: U256::from(20),
+                placeholders: BTreeMap::from([(1, U256::from(42)), (2, U256::from(7))]),
+                limit: Some(5),
+                offset: Some(15),
+            }
+        );
+
+        let missing_bounds = PlaceHolderLgn(HashMap::from([("2".to_string(), U256::from(1))]));
+        assert_eq!(
+            QueryPublicInputs::new(&missing_bounds)
+                .unwrap_err()
+                .to_string(),
+            "missing min block placeholder"
+        );
+    }
+}
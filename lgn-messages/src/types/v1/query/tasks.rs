@@ -48,7 +48,11 @@ pub enum QueryStep {
     #[serde(rename = "2")]
     Aggregation(AggregationInput),
 
-    /// Revelation step, we only handle aggregation revelation for now, next step is Groth16
+    /// Revelation step, next step is Groth16.
+    ///
+    /// For tabular queries, this only proves the revelation of the matching rows proven by
+    /// previous tasks, their proofs being hydrated in the input, which lets the gateway split the
+    /// rows of a large query across workers.
     #[serde(rename = "3")]
     Revelation(RevelationInput),
 }
@@ -135,6 +139,11 @@ impl<K: Clone + std::fmt::Debug> Hydratable<K> {
         Hydratable::Dehydrated(k)
     }
 
+    /// Whether the proof itself is embedded.
+    pub fn is_hydrated(&self) -> bool {
+        matches!(self, Hydratable::Hydrated(_))
+    }
+
     /// Consume a `Hydrated` variant into its embedded proof; panic if it is
     /// not hydrated.
     pub fn proof(&self) -> Arc<Vec<u8>> {
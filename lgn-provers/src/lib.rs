@@ -1,12 +1,15 @@
 #![feature(generic_const_exprs)]
 pub mod params;
 pub mod provers;
+pub mod seed;
 
 #[cfg(feature = "dummy-prover")]
 mod dummy_utils {
-    /// Generates random data to be used as a dummy proof.
+    /// Generates random data to be used as a dummy proof, drawn from the seed of the task if it
+    /// has one.
     pub fn dummy_proof(proof_size: usize) -> Vec<u8> {
-        let data: Vec<_> = (0..proof_size).map(|_| rand::random::<u8>()).collect();
+        let data = crate::seed::seeded_bytes(proof_size)
+            .unwrap_or_else(|| (0..proof_size).map(|_| rand::random::<u8>()).collect());
         bincode::serialize(&data).unwrap()
     }
 }
@@ -1,24 +1,224 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::PoisonError;
 
 use anyhow::anyhow;
-use anyhow::bail;
 use anyhow::ensure;
 use anyhow::Context;
 use bytes::Bytes;
+use metrics::counter;
+use metrics::gauge;
+use serde::de::DeserializeOwned;
 use tracing::info;
+use tracing::warn;
 
 /// The filename of params checksum hashes
 pub const PARAMS_CHECKSUM_FILENAME: &str = "public_params.hash";
 
+/// The suffix of the sidecar file of a param file, holding the `verifiable_db` version it has been
+/// generated by, e.g. `1.2.3`.
+pub const PARAMS_VERSION_SUFFIX: &str = ".mp2_version";
+
+/// The suffix of the manifest of params split across multiple files, listing the parts to be
+/// concatenated in order, one `<part file name> <Blake3 hash>` per line as in
+/// [`PARAMS_CHECKSUM_FILENAME`]. The parts are named relative to the params root, as the manifest.
+pub const PARAMS_MANIFEST_SUFFIX: &str = ".manifest";
+
+/// How long fetching a version sidecar file may take, in seconds.
+const VERSION_HTTP_TIMEOUT: u64 = 10;
+
 /// Could make configurable but 3600 should be enough
 const HTTP_TIMEOUT: u64 = 3600;
 
 /// How many times param download should be retried.
 const DOWNLOAD_MAX_RETRIES: u8 = 3;
 
+/// The longest `Retry-After` delay honored, in seconds.
+const MAX_RETRY_AFTER: u64 = 60;
+
+/// How many param files may be downloaded at the same time from a single host, unless set with
+/// [`set_max_downloads_per_host`].
+const DEFAULT_MAX_DOWNLOADS_PER_HOST: usize = 2;
+
+static DOWNLOAD_LIMITER: HostLimiter = HostLimiter::new(DEFAULT_MAX_DOWNLOADS_PER_HOST);
+
+static PARAMS_MEMORY: ParamsMemory = ParamsMemory::new();
+
+/// Cap the number of param files downloaded at the same time from any single host, e.g. by
+/// provers initializing in parallel.
+pub fn set_max_downloads_per_host(limit: usize) {
+    DOWNLOAD_LIMITER
+        .limit
+        .store(limit.max(1), Ordering::Relaxed);
+}
+
+/// Cap the memory held by the loaded params, approximated by the size of their files: loading
+/// params which would bring it over `limit` bytes fails, before they are deserialized.
+pub fn set_params_memory_limit(limit: Option<u64>) {
+    *PARAMS_MEMORY
+        .limit
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = limit;
+}
+
+/// The memory held by the loaded params, as approximated by the size of their files, along with
+/// its limit if any, in bytes.
+pub fn params_memory() -> (u64, Option<u64>) {
+    (
+        PARAMS_MEMORY.usage(),
+        *PARAMS_MEMORY
+            .limit
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner),
+    )
+}
+
+/// Accounts for the memory held by the loaded params.
+///
+/// Params are accounted for by file name, loading the same file again, e.g. when the provers are
+/// rebuilt, replacing its previous size rather than adding to it.
+struct ParamsMemory {
+    /// The most bytes the loaded params may hold, if capped.
+    limit: Mutex<Option<u64>>,
+    /// The size of each loaded param file.
+    loaded: Mutex<BTreeMap<String, u64>>,
+}
+
+impl ParamsMemory {
+    const fn new() -> Self {
+        Self {
+            limit: Mutex::new(None),
+            loaded: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Account for the `size` bytes of `file_name`, unless they would exceed the limit.
+    fn reserve(
+        &self,
+        file_name: &str,
+        size: u64,
+    ) -> anyhow::Result<()> {
+        let mut loaded = self.loaded.lock().unwrap_or_else(PoisonError::into_inner);
+        let total = loaded
+            .iter()
+            .filter(|(file, _)| *file != file_name)
+            .map(|(_, size)| size)
+            .sum::<u64>()
+            + size;
+        if let Some(limit) = *self.limit.lock().unwrap_or_else(PoisonError::into_inner) {
+            ensure!(
+                total <= limit,
+                "loading `{file_name}` ({}MiB) would bring the params in memory to {}MiB, over \
+                 their {}MiB limit",
+                size / (1024 * 1024),
+                total / (1024 * 1024),
+                limit / (1024 * 1024)
+            );
+        }
+        loaded.insert(file_name.to_string(), size);
+        gauge!("zkmr_worker_params_memory_bytes").set(total as f64);
+        Ok(())
+    }
+
+    fn usage(&self) -> u64 {
+        self.loaded
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .sum()
+    }
+}
+
+/// Limits the number of concurrent operations on each host.
+struct HostLimiter {
+    limit: AtomicUsize,
+    active: Mutex<BTreeMap<String, usize>>,
+    released: Condvar,
+}
+
+impl HostLimiter {
+    const fn new(limit: usize) -> Self {
+        Self {
+            limit: AtomicUsize::new(limit),
+            active: Mutex::new(BTreeMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until an operation on `host` is allowed; it lasts as long as the returned permit.
+    fn acquire(
+        &self,
+        host: &str,
+    ) -> HostPermit<'_> {
+        let mut active = self.active.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            let count = active.entry(host.to_string()).or_default();
+            if *count < self.limit.load(Ordering::Relaxed) {
+                *count += 1;
+                break;
+            }
+            active = self
+                .released
+                .wait(active)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        HostPermit {
+            limiter: self,
+            host: host.to_string(),
+        }
+    }
+}
+
+struct HostPermit<'a> {
+    limiter: &'a HostLimiter,
+    host: String,
+}
+
+impl Drop for HostPermit<'_> {
+    fn drop(&mut self) {
+        let mut active = self
+            .limiter
+            .active
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(count) = active.get_mut(&self.host) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.host);
+            }
+        }
+        self.limiter.released.notify_all();
+    }
+}
+
+/// Why downloading a param file failed.
+#[derive(Debug)]
+enum DownloadError {
+    /// The failure may be transient, and the server may have asked to wait before retrying.
+    Transient {
+        error: anyhow::Error,
+        retry_after: Option<std::time::Duration>,
+    },
+    /// Retrying would not help, e.g. the file does not exist.
+    Permanent(anyhow::Error),
+}
+
+impl From<anyhow::Error> for DownloadError {
+    fn from(error: anyhow::Error) -> Self {
+        DownloadError::Transient {
+            error,
+            retry_after: None,
+        }
+    }
+}
+
 /// Read the given file `f`, and returns its content as well as its Blake3 checksum.
 fn read_file_and_checksum(f: &Path) -> anyhow::Result<(Bytes, blake3::Hash)> {
     let bytes = std::fs::read(f).with_context(|| anyhow!("reading `{}`", f.display()))?;
@@ -28,11 +228,124 @@ fn read_file_and_checksum(f: &Path) -> anyhow::Result<(Bytes, blake3::Hash)> {
     Ok((bytes.into(), hash))
 }
 
+/// Load the parameters stored in `file_name` like [`prepare_raw`], and deserialize them.
+///
+/// As the checksum guarantees that the file is the expected one, a deserialization failure
+/// points to the parameters having been generated by an incompatible `verifiable_db` version.
+pub fn prepare<T: DeserializeOwned>(
+    base_url: &str,
+    param_dir: &str,
+    file_name: &str,
+    checksums: &HashMap<String, blake3::Hash>,
+) -> anyhow::Result<T> {
+    let bytes = prepare_raw(base_url, param_dir, file_name, checksums)?;
+    deserialize_params(file_name, &bytes)
+}
+
+fn deserialize_params<T: DeserializeOwned>(
+    file_name: &str,
+    bytes: &[u8],
+) -> anyhow::Result<T> {
+    bincode::deserialize_from(std::io::BufReader::new(bytes)).map_err(|e| {
+        counter!("zkmr_worker_params_deserialization_failures_total", "file" => file_name.to_string())
+            .increment(1);
+        anyhow!(
+            "deserializing `{file_name}` failed although its checksum matches, it was likely \
+             generated by a `verifiable_db` version incompatible with {}: {e}",
+            verifiable_db::version()
+        )
+    })
+}
+
+/// Load the parameters stored in `file_name` under `param_dir`, downloading them from `base_url`
+/// if they are missing locally or do not match their checksum.
+///
+/// Parameters without a checksum of their own in `checksums` are looked for as split across
+/// multiple files, listed by the `<file_name>.manifest` file which has one instead. Only the parts
+/// missing or mismatching locally are then downloaded, concurrently, before being assembled.
 pub fn prepare_raw(
     base_url: &str,
     param_dir: &str,
     file_name: &str,
     checksums: &HashMap<String, blake3::Hash>,
+) -> anyhow::Result<Bytes> {
+    let parts = manifest(base_url, param_dir, file_name, checksums)?;
+    let mut parts = std::thread::scope(|s| {
+        parts
+            .iter()
+            .map(|(part, checksum)| {
+                s.spawn(move || prepare_file(base_url, param_dir, part, checksum))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    })?;
+
+    let bytes = if parts.len() == 1 {
+        parts.remove(0)
+    } else {
+        parts.concat().into()
+    };
+
+    PARAMS_MEMORY.reserve(file_name, bytes.len() as u64)?;
+    info!("params loaded, size = {}MiB", bytes.len() / (1024 * 1024));
+
+    Ok(bytes)
+}
+
+/// The files making up the parameters `file_name`, with their checksum: `file_name` alone unless
+/// it is only listed by its manifest, which is then loaded like a param file.
+fn manifest(
+    base_url: &str,
+    param_dir: &str,
+    file_name: &str,
+    checksums: &HashMap<String, blake3::Hash>,
+) -> anyhow::Result<Vec<(String, blake3::Hash)>> {
+    if let Some(checksum) = checksums.get(file_name) {
+        return Ok(vec![(file_name.to_string(), *checksum)]);
+    }
+
+    let manifest_name = format!("{file_name}{PARAMS_MANIFEST_SUFFIX}");
+    let checksum = checksums
+        .get(&manifest_name)
+        .with_context(|| anyhow!("no expected checksum for `{file_name}` nor `{manifest_name}`"))?;
+    let bytes = prepare_file(base_url, param_dir, &manifest_name, checksum)?;
+    parse_manifest(&manifest_name, &bytes)
+}
+
+/// The parts listed by the manifest `manifest_name`, with their checksum.
+fn parse_manifest(
+    manifest_name: &str,
+    bytes: &[u8],
+) -> anyhow::Result<Vec<(String, blake3::Hash)>> {
+    let text = std::str::from_utf8(bytes).with_context(|| anyhow!("reading `{manifest_name}`"))?;
+    let mut parts = vec![];
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let mut line = line.split_whitespace();
+        let part = line.next().context("no filename found")?;
+        let hash = line
+            .next()
+            .with_context(|| anyhow!("no hash found for `{part}` in `{manifest_name}`"))?;
+        let hash = blake3::Hash::from_hex(hash)
+            .with_context(|| anyhow!("invalid hash `{hash}` for `{part}` in `{manifest_name}`"))?;
+        parts.push((part.to_string(), hash));
+    }
+    ensure!(!parts.is_empty(), "`{manifest_name}` lists no part");
+    Ok(parts)
+}
+
+/// Load the single file `file_name` under `param_dir`, downloading it from `base_url` if it is
+/// missing locally or does not match `expected_checksum`.
+fn prepare_file(
+    base_url: &str,
+    param_dir: &str,
+    file_name: &str,
+    expected_checksum: &blake3::Hash,
 ) -> anyhow::Result<Bytes> {
     let mut local_param_filename = PathBuf::from(param_dir);
     local_param_filename.push(file_name);
@@ -51,10 +364,6 @@ pub fn prepare_raw(
         )
     })?;
 
-    let expected_checksum = checksums
-        .get(file_name)
-        .with_context(|| anyhow!("no expected checksum for `{file_name}`"))?;
-
     // A file must be re-downloaded if the local file does not exist or if its checksum
     // mismatches.
     let mut local_file_bytes = None;
@@ -68,10 +377,10 @@ pub fn prepare_raw(
             local_file_bytes = Some(bytes);
             if *expected_checksum != found {
                 info!(
-                    "local file `{}` hash is {} ≠ {}",
+                    "local file `{}` hash is {}, expected {}",
                     local_param_filename.display(),
-                    expected_checksum.to_hex(),
-                    found.to_hex()
+                    found.to_hex(),
+                    expected_checksum.to_hex()
                 );
             }
             *expected_checksum != found
@@ -94,10 +403,21 @@ pub fn prepare_raw(
                     bytes = content;
                     break;
                 },
-                err @ Err(_) => {
+                Err(DownloadError::Permanent(error)) => {
+                    return Err(error).with_context(|| anyhow!("downloading `{}`", file_name));
+                },
+                Err(DownloadError::Transient { error, retry_after }) => {
                     match duration {
-                        Some(duration) => std::thread::sleep(duration),
-                        None => return err.with_context(|| anyhow!("downloading `{}`", file_name)),
+                        Some(duration) => {
+                            let duration = retry_after
+                                .map_or(duration, |retry_after| retry_after.max(duration));
+                            warn!("downloading `{file_name}` failed, retrying in {duration:?}: {error:?}");
+                            std::thread::sleep(duration)
+                        },
+                        None => {
+                            return Err(error)
+                                .with_context(|| anyhow!("downloading `{}`", file_name))
+                        },
                     }
                 },
             }
@@ -114,19 +434,81 @@ pub fn prepare_raw(
         local_file_bytes.unwrap()
     };
 
-    info!("params loaded, size = {}MiB", bytes.len() / (1024 * 1024));
-
     Ok(bytes)
 }
 
+/// The `verifiable_db` version the param file `file_name` has been generated by, read from its
+/// sidecar file, without loading the params themselves.
+///
+/// The sidecar is looked for next to the local param file, then downloaded along with it. `None`
+/// if there is none, as for the params published before sidecars.
+pub fn params_mp2_version(
+    base_url: &str,
+    param_dir: &str,
+    file_name: &str,
+) -> anyhow::Result<Option<String>> {
+    let sidecar_name = format!("{file_name}{PARAMS_VERSION_SUFFIX}");
+    let local_sidecar = PathBuf::from(param_dir).join(&sidecar_name);
+    if local_sidecar.exists() {
+        let text = std::fs::read_to_string(&local_sidecar)
+            .with_context(|| anyhow!("reading `{}`", local_sidecar.display()))?;
+        return Ok(parse_version_sidecar(&text));
+    }
+
+    let client = reqwest::blocking::Client::builder()
+        .timeout(std::time::Duration::from_secs(VERSION_HTTP_TIMEOUT))
+        .build()
+        .context("building reqwest client")?;
+    let response = client
+        .get(format!("{base_url}/{sidecar_name}"))
+        .send()
+        .with_context(|| anyhow!("downloading `{sidecar_name}`"))?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    ensure!(
+        response.status().is_success(),
+        "downloading `{sidecar_name}`: status = {}",
+        response.status()
+    );
+    let text = response
+        .text()
+        .with_context(|| anyhow!("fetching `{sidecar_name}`"))?;
+
+    // Only a cache, the version is fetched again if it cannot be written.
+    if let Some(parent) = local_sidecar.parent() {
+        if let Err(e) =
+            std::fs::create_dir_all(parent).and_then(|()| std::fs::write(&local_sidecar, &text))
+        {
+            warn!("writing `{}`: {e}", local_sidecar.display());
+        }
+    }
+
+    Ok(parse_version_sidecar(&text))
+}
+
+/// The version held by a sidecar file, on its first line.
+fn parse_version_sidecar(text: &str) -> Option<String> {
+    text.lines()
+        .next()
+        .map(str::trim)
+        .filter(|version| !version.is_empty())
+        .map(str::to_string)
+}
+
 /// Download the content from `file_name` under `base_url`, ensuring that its checksum matches
 /// the provided `expected_checksum`.
 fn download_file(
     base_url: &str,
     file_name: &str,
     expected_checksum: &blake3::Hash,
-) -> anyhow::Result<Bytes> {
+) -> Result<Bytes, DownloadError> {
     let file_url = format!("{base_url}/{file_name}");
+    let host = reqwest::Url::parse(&file_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default();
+    let _permit = DOWNLOAD_LIMITER.acquire(&host);
     info!("downloading params from {}", file_url);
 
     let client = reqwest::blocking::Client::builder()
@@ -139,22 +521,287 @@ fn download_file(
         .send()
         .context("downloading params from remote")?;
 
-    if !response.status().is_success() {
-        bail!(
-            "downloading params from remote: status = {}",
-            response.status()
-        );
+    let status = response.status();
+    if !status.is_success() {
+        let error = anyhow!("downloading params from remote: status = {status}");
+        return Err(if is_retryable(status) {
+            DownloadError::Transient {
+                error,
+                retry_after: response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_retry_after),
+            }
+        } else {
+            DownloadError::Permanent(error)
+        });
     }
 
     let bytes = response.bytes().context("fetching params bytes")?;
+    check_checksum(file_name, &bytes, expected_checksum)?;
+    Ok(bytes)
+}
+
+/// Whether a download failing with `status` may succeed if retried, as opposed to client errors
+/// such as a missing file.
+fn is_retryable(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::REQUEST_TIMEOUT
+        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+/// Parse a `Retry-After` header expressed in seconds; HTTP dates are not supported.
+fn parse_retry_after(value: &str) -> Option<std::time::Duration> {
+    let seconds = value.trim().parse::<u64>().ok()?;
+    Some(std::time::Duration::from_secs(seconds.min(MAX_RETRY_AFTER)))
+}
+
+/// Ensure that the checksum of `bytes`, the content of `file_name`, is `expected_checksum`.
+fn check_checksum(
+    file_name: &str,
+    bytes: &[u8],
+    expected_checksum: &blake3::Hash,
+) -> anyhow::Result<()> {
     let mut hasher = blake3::Hasher::new();
-    hasher.update_rayon(&bytes);
+    hasher.update_rayon(bytes);
     let found_checksum = hasher.finalize();
+    if found_checksum != *expected_checksum {
+        counter!("zkmr_worker_params_checksum_mismatches_total", "file" => file_name.to_string())
+            .increment(1);
+    }
     ensure!(
         found_checksum == *expected_checksum,
-        "param checksum mismatch: {} ≠ {}",
-        found_checksum.to_hex(),
-        expected_checksum.to_hex()
+        "checksum mismatch for `{file_name}`, the file is corrupted or not the expected one: \
+         expected {}, found {}",
+        expected_checksum.to_hex(),
+        found_checksum.to_hex()
     );
-    Ok(bytes)
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_retryable() {
+        for status in [408, 429, 500, 502, 503, 504] {
+            assert!(is_retryable(reqwest::StatusCode::from_u16(status).unwrap()));
+        }
+        for status in [400, 401, 403, 404, 410] {
+            assert!(!is_retryable(
+                reqwest::StatusCode::from_u16(status).unwrap()
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(
+            parse_retry_after("5"),
+            Some(std::time::Duration::from_secs(5))
+        );
+        assert_eq!(
+            parse_retry_after("3600"),
+            Some(std::time::Duration::from_secs(MAX_RETRY_AFTER))
+        );
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[test]
+    fn test_parse_version_sidecar() {
+        assert_eq!(parse_version_sidecar("1.2.3\n"), Some("1.2.3".to_string()));
+        assert_eq!(
+            parse_version_sidecar(" 1.2.3 \ngenerated on 2025-01-01\n"),
+            Some("1.2.3".to_string())
+        );
+        assert_eq!(parse_version_sidecar(""), None);
+        assert_eq!(parse_version_sidecar("\n1.2.3"), None);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let expected = blake3::hash(b"expected");
+        check_checksum("params.bin", b"expected", &expected).unwrap();
+
+        let err = check_checksum("params.bin", b"corrupted", &expected).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "checksum mismatch for `params.bin`, the file is corrupted or not the expected \
+                 one: expected {}, found {}",
+                expected.to_hex(),
+                blake3::hash(b"corrupted").to_hex()
+            )
+        );
+    }
+
+    #[test]
+    fn test_downloads_per_host_limit() {
+        let limiter = HostLimiter::new(2);
+        let running = Mutex::new(BTreeMap::<&str, (usize, usize)>::new());
+
+        // Mock downloads, recording the peak number running at once for each host.
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                let host = if i % 2 == 0 { "a.example" } else { "b.example" };
+                let (limiter, running) = (&limiter, &running);
+                s.spawn(move || {
+                    let _permit = limiter.acquire(host);
+                    {
+                        let mut running = running.lock().unwrap();
+                        let (current, peak) = running.entry(host).or_default();
+                        *current += 1;
+                        *peak = (*peak).max(*current);
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                    running.lock().unwrap().get_mut(host).unwrap().0 -= 1;
+                });
+            }
+        });
+        for (host, (_, peak)) in running.into_inner().unwrap() {
+            assert!(peak <= 2, "{peak} concurrent downloads from {host}");
+        }
+
+        // A busy host does not hold back the others.
+        let _a1 = limiter.acquire("a.example");
+        let _a2 = limiter.acquire("a.example");
+        let _b = limiter.acquire("b.example");
+    }
+
+    /// A directory of param files named after `test`, holding `files`.
+    fn param_dir(
+        test: &str,
+        files: &[(&str, &[u8])],
+    ) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("params-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, bytes) in files {
+            std::fs::write(dir.join(file), bytes).unwrap();
+        }
+        dir
+    }
+
+    /// No param file can be downloaded from there.
+    const UNREACHABLE_URL: &str = "http://127.0.0.1:9";
+
+    #[test]
+    fn test_manifest_assembly() {
+        let manifest = format!(
+            "params.bin.0 {}\nparams.bin.1 {}\n",
+            blake3::hash(b"abc").to_hex(),
+            blake3::hash(b"def").to_hex()
+        );
+        let dir = param_dir(
+            "manifest-assembly",
+            &[
+                ("params.bin.manifest", manifest.as_bytes()),
+                ("params.bin.0", b"abc"),
+                ("params.bin.1", b"def"),
+                ("single.bin", b"ghi"),
+            ],
+        );
+        let checksums = HashMap::from([
+            (
+                "params.bin.manifest".to_string(),
+                blake3::hash(manifest.as_bytes()),
+            ),
+            ("single.bin".to_string(), blake3::hash(b"ghi")),
+        ]);
+
+        let bytes = prepare_raw(
+            UNREACHABLE_URL,
+            dir.to_str().unwrap(),
+            "params.bin",
+            &checksums,
+        )
+        .unwrap();
+        assert_eq!(&bytes[..], b"abcdef");
+
+        // Params in a single file are a one-part manifest.
+        let bytes = prepare_raw(
+            UNREACHABLE_URL,
+            dir.to_str().unwrap(),
+            "single.bin",
+            &checksums,
+        )
+        .unwrap();
+        assert_eq!(&bytes[..], b"ghi");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_manifest_part_mismatch() {
+        let manifest = format!(
+            "params.bin.0 {}\nparams.bin.1 {}\n",
+            blake3::hash(b"abc").to_hex(),
+            blake3::hash(b"def").to_hex()
+        );
+        let dir = param_dir(
+            "manifest-part-mismatch",
+            &[
+                ("params.bin.manifest", manifest.as_bytes()),
+                ("params.bin.0", b"abc"),
+                ("params.bin.1", b"corrupted"),
+            ],
+        );
+        let checksums = HashMap::from([(
+            "params.bin.manifest".to_string(),
+            blake3::hash(manifest.as_bytes()),
+        )]);
+
+        // Only the mismatching part is downloaded again, which fails here.
+        let err = prepare_raw(
+            UNREACHABLE_URL,
+            dir.to_str().unwrap(),
+            "params.bin",
+            &checksums,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("downloading `params.bin.1`"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_params_memory_limit() {
+        const MIB: u64 = 1024 * 1024;
+        let memory = ParamsMemory::new();
+        *memory.limit.lock().unwrap() = Some(100 * MIB);
+
+        memory.reserve("query_params.bin", 60 * MIB).unwrap();
+        memory
+            .reserve("preprocessing_params.bin", 30 * MIB)
+            .unwrap();
+        let err = memory
+            .reserve("groth16_assets/pk.bin", 20 * MIB)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "loading `groth16_assets/pk.bin` (20MiB) would bring the params in memory to 110MiB, \
+             over their 100MiB limit"
+        );
+        assert_eq!(memory.usage(), 90 * MIB);
+
+        // Loading the same params again does not count them twice.
+        memory.reserve("query_params.bin", 70 * MIB).unwrap();
+        assert_eq!(memory.usage(), 100 * MIB);
+    }
+
+    #[test]
+    fn test_deserialization_failure() {
+        let bytes = bincode::serialize(&(1u64, 2u64)).unwrap();
+        assert_eq!(
+            deserialize_params::<(u64, u64)>("params.bin", &bytes).unwrap(),
+            (1, 2)
+        );
+
+        // A truncated layout, as a version skew would produce.
+        let err = deserialize_params::<(u64, u64, u64)>("params.bin", &bytes).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("deserializing `params.bin` failed although its checksum matches"));
+    }
 }
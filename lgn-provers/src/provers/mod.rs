@@ -1,8 +1,30 @@
 use lgn_messages::types::MessageEnvelope;
 use lgn_messages::types::MessageReplyEnvelope;
+use lgn_messages::types::ProverStamp;
+use lgn_messages::TableId;
 
 pub mod v1;
 
+/// Names a prover implementation, for the replies to record which one produced them.
+pub trait ProverName {
+    const NAME: &'static str;
+}
+
+/// Identify the prover `P` along with the `verifiable_db` version of its parameters.
+pub(crate) fn prover_stamp<P: ProverName>() -> ProverStamp {
+    ProverStamp {
+        prover: P::NAME.to_string(),
+        mp2_version: verifiable_db::version().to_string(),
+    }
+}
+
+/// Announces a burst of tasks, for the provers to prepare for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WarmHint {
+    /// The table the tasks of the burst are about.
+    pub table_id: TableId,
+}
+
 /// The prover trait that accepts [`MessageEnvelope`] and is able to process tasks of type
 /// [`TaskType`].
 pub trait LgnProver<T, R> {
@@ -18,4 +40,14 @@ pub trait LgnProver<T, R> {
         &self,
         envelope: &MessageEnvelope<T>,
     ) -> anyhow::Result<MessageReplyEnvelope<R>>;
+
+    /// Prepare for the burst of tasks announced by `hint`, e.g. by populating caches.
+    ///
+    /// Provers without anything to prepare keep the default, which does nothing.
+    fn warm(
+        &self,
+        _hint: &WarmHint,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
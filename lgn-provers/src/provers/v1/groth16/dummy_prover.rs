@@ -1,5 +1,6 @@
 use crate::dummy_utils::dummy_proof;
 use crate::provers::v1::groth16::prover::Prover;
+use crate::provers::ProverName;
 
 const PROOF_SIZE: usize = 32;
 
@@ -14,3 +15,7 @@ impl Prover for DummyProver {
         Ok(dummy_proof(PROOF_SIZE))
     }
 }
+
+impl ProverName for DummyProver {
+    const NAME: &'static str = "dummy-groth16";
+}
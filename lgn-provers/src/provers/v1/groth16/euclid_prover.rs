@@ -6,6 +6,7 @@ use tracing::debug;
 
 use crate::params;
 use crate::provers::v1::groth16::prover::Prover;
+use crate::provers::ProverName;
 
 #[derive(Debug)]
 pub struct Groth16Prover {
@@ -46,3 +47,7 @@ impl Prover for Groth16Prover {
         self.inner.prove(revelation)
     }
 }
+
+impl ProverName for Groth16Prover {
+    const NAME: &'static str = "euclid-groth16";
+}
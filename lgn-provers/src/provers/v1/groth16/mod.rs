@@ -6,6 +6,7 @@ use tracing::debug;
 use tracing::info;
 
 use crate::provers::v1::groth16::task::Groth16;
+use crate::provers::ProverName;
 
 mod prover;
 mod task;
@@ -23,9 +24,9 @@ pub fn create_prover(
     dir: &str,
     circuit_file: &str,
     checksums: &HashMap<String, blake3::Hash>,
+    r1cs_file: &str,
     pk_file: &str,
-    vk_file: &str,
-) -> anyhow::Result<Groth16<impl Prover>> {
+) -> anyhow::Result<Groth16<impl Prover + ProverName>> {
     let prover = {
         #[cfg(feature = "dummy-prover")]
         let prover = {
@@ -35,7 +36,14 @@ pub fn create_prover(
         #[cfg(not(feature = "dummy-prover"))]
         let prover = {
             info!("Creating groth16 prover");
-            euclid_prover::Groth16Prover::init(url, dir, circuit_file, pk_file, vk_file, checksums)?
+            euclid_prover::Groth16Prover::init(
+                url,
+                dir,
+                circuit_file,
+                r1cs_file,
+                pk_file,
+                checksums,
+            )?
         };
 
         debug!("Groth16 prover created");
@@ -44,3 +52,24 @@ pub fn create_prover(
 
     Ok(Groth16::new(prover))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::provers::prover_stamp;
+
+    #[test]
+    fn test_prover_stamp() {
+        #[cfg(feature = "dummy-prover")]
+        let stamp = prover_stamp::<super::dummy_prover::DummyProver>();
+        #[cfg(not(feature = "dummy-prover"))]
+        let stamp = prover_stamp::<super::euclid_prover::Groth16Prover>();
+
+        let expected = if cfg!(feature = "dummy-prover") {
+            "dummy-groth16"
+        } else {
+            "euclid-groth16"
+        };
+        assert_eq!(stamp.prover, expected);
+        assert_eq!(stamp.mp2_version, verifiable_db::version());
+    }
+}
@@ -14,14 +14,16 @@ use tracing::debug;
 use tracing::info;
 
 use super::prover::Prover;
+use crate::provers::prover_stamp;
 use crate::provers::LgnProver;
+use crate::provers::ProverName;
 
-impl<GP: Prover> LgnProver<TaskType, ReplyType> for Groth16<GP> {
+impl<GP: Prover + ProverName> LgnProver<TaskType, ReplyType> for Groth16<GP> {
     fn run(
         &self,
         envelope: &MessageEnvelope<TaskType>,
     ) -> anyhow::Result<MessageReplyEnvelope<ReplyType>> {
-        self.run_inner(envelope)
+        Ok(self.run_inner(envelope)?.with_prover(prover_stamp::<GP>()))
     }
 }
 
@@ -96,3 +98,38 @@ impl<GP: Prover> Groth16<GP> {
         Ok((key, proof))
     }
 }
+
+#[cfg(all(test, feature = "dummy-prover"))]
+mod tests {
+    use lgn_messages::routing::RoutingKey;
+    use lgn_messages::types::v1::query;
+
+    use super::*;
+    use crate::provers::v1::groth16::dummy_prover::DummyProver;
+
+    #[test]
+    fn test_groth16_reply() {
+        let mut task = WorkerTask::new(1, query::keys::ProofKey::Revelation("query".to_string()));
+        task.revelation_proof.hydrate(vec![1; 64]);
+        let envelope = MessageEnvelope::new(
+            "query".to_string(),
+            "task".to_string(),
+            TaskType::V1Groth16(task),
+            RoutingKey::combined("sg".to_string(), 0),
+            "1.0.0".to_string(),
+        );
+
+        let reply = Groth16::new(DummyProver).run(&envelope).unwrap();
+        assert_eq!(reply.prover().unwrap().prover, "dummy-groth16");
+        let ReplyType::V1Groth16(worker_reply) = reply.content() else {
+            panic!("unexpected reply: {:?}", reply.content());
+        };
+        assert_eq!(worker_reply.chain_id, 1);
+        assert_eq!(worker_reply.proof_type, ProofCategory::Querying);
+        let (key, proof) = worker_reply.proof.as_ref().unwrap();
+        assert_eq!(key, &ProofKey("query".to_string()).to_string());
+        // The proof is the bincode serialization of the dummy proof bytes.
+        let proof: Vec<u8> = bincode::deserialize(proof).unwrap();
+        assert_eq!(proof.len(), 32);
+    }
+}
@@ -1,5 +1,8 @@
 use alloy::primitives::Address;
 use alloy::primitives::U256;
+use anyhow::ensure;
+use anyhow::Context;
+use ethers::types::H256;
 use mp2_common::digest::TableDimension;
 use mp2_common::types::HashOutput;
 use tracing::debug;
@@ -7,6 +10,7 @@ use tracing::debug;
 use crate::dummy_utils::dummy_proof;
 use crate::provers::v1::preprocessing::prover::StorageDatabaseProver;
 use crate::provers::v1::preprocessing::prover::StorageExtractionProver;
+use crate::provers::ProverName;
 
 const PROOF_SIZE: usize = 120;
 
@@ -54,6 +58,20 @@ impl StorageExtractionProver for DummyProver {
         Ok(dummy_proof(PROOF_SIZE))
     }
 
+    fn verify_mpt_child_proof(
+        &self,
+        child_proof: &[u8],
+        _node_hash: H256,
+    ) -> anyhow::Result<()> {
+        // Dummy proofs do not tell which node they prove, only their encoding can be checked.
+        let data = bincode::deserialize::<Vec<u8>>(child_proof).context("corrupted proof")?;
+        ensure!(
+            bincode::serialized_size(&data)? == child_proof.len() as u64,
+            "corrupted proof: trailing bytes"
+        );
+        Ok(())
+    }
+
     fn prove_length_leaf(
         &self,
         _node: Vec<u8>,
@@ -251,3 +269,7 @@ impl StorageDatabaseProver for DummyProver {
         Ok(dummy_proof(PROOF_SIZE))
     }
 }
+
+impl ProverName for DummyProver {
+    const NAME: &'static str = "dummy-preprocessing";
+}
@@ -3,11 +3,17 @@ use std::collections::HashMap;
 use alloy::primitives::Address;
 use alloy::primitives::U256;
 use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use ethers::types::H256;
 use ethers::utils::rlp::Prototype;
 use ethers::utils::rlp::Rlp;
 use mp2_common::digest::TableDimension;
 use mp2_common::poseidon::empty_poseidon_hash_as_vec;
+use mp2_common::proof::ProofWithVK;
 use mp2_common::types::HashOutput;
+use mp2_common::utils::Endianness;
+use mp2_common::utils::Packer;
 use mp2_v1::api::generate_proof;
 use mp2_v1::api::CircuitInput::BlockExtraction;
 use mp2_v1::api::CircuitInput::BlockTree;
@@ -32,6 +38,7 @@ use tracing::debug;
 use crate::params;
 use crate::provers::v1::preprocessing::prover::StorageDatabaseProver;
 use crate::provers::v1::preprocessing::prover::StorageExtractionProver;
+use crate::provers::ProverName;
 
 pub struct EuclidProver {
     params: PublicParameters,
@@ -48,9 +55,7 @@ impl EuclidProver {
         file: &str,
         checksums: &HashMap<String, blake3::Hash>,
     ) -> anyhow::Result<Self> {
-        let params = params::prepare_raw(url, dir, file, checksums)?;
-        let reader = std::io::BufReader::new(params.as_ref());
-        let params = bincode::deserialize_from(reader)?;
+        let params = params::prepare(url, dir, file, checksums)?;
         Ok(Self { params })
     }
 
@@ -150,6 +155,22 @@ impl StorageExtractionProver for EuclidProver {
         }
     }
 
+    fn verify_mpt_child_proof(
+        &self,
+        child_proof: &[u8],
+        node_hash: H256,
+    ) -> anyhow::Result<()> {
+        // The branch circuit verifies the child proofs, but a bad one only surfaces as an opaque
+        // proving failure.
+        let proof = ProofWithVK::deserialize(child_proof).context("deserializing proof")?;
+        let public_inputs = values_extraction::PublicInputs::new(&proof.proof().public_inputs);
+        ensure!(
+            public_inputs.root_hash() == node_hash.as_bytes().pack(Endianness::Little),
+            "the proof is not of node {node_hash:?}"
+        );
+        Ok(())
+    }
+
     fn prove_length_leaf(
         &self,
         node: Vec<u8>,
@@ -468,3 +489,7 @@ impl StorageDatabaseProver for EuclidProver {
         self.prove(input, "ivc")
     }
 }
+
+impl ProverName for EuclidProver {
+    const NAME: &'static str = "euclid-preprocessing";
+}
@@ -6,6 +6,7 @@ use tracing::info;
 use crate::provers::v1::preprocessing::prover::StorageDatabaseProver;
 use crate::provers::v1::preprocessing::prover::StorageExtractionProver;
 use crate::provers::v1::preprocessing::task::Preprocessing;
+use crate::provers::ProverName;
 pub mod prover;
 pub mod task;
 
@@ -21,7 +22,8 @@ pub fn create_prover(
     dir: &str,
     file: &str,
     checksums: &HashMap<String, blake3::Hash>,
-) -> anyhow::Result<Preprocessing<impl StorageExtractionProver + StorageDatabaseProver>> {
+) -> anyhow::Result<Preprocessing<impl StorageExtractionProver + StorageDatabaseProver + ProverName>>
+{
     let prover = {
         #[cfg(feature = "dummy-prover")]
         let prover = {
@@ -41,3 +43,24 @@ pub fn create_prover(
 
     Ok(Preprocessing::new(prover))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::provers::prover_stamp;
+
+    #[test]
+    fn test_prover_stamp() {
+        #[cfg(feature = "dummy-prover")]
+        let stamp = prover_stamp::<super::dummy_prover::DummyProver>();
+        #[cfg(not(feature = "dummy-prover"))]
+        let stamp = prover_stamp::<super::euclid_prover::EuclidProver>();
+
+        let expected = if cfg!(feature = "dummy-prover") {
+            "dummy-preprocessing"
+        } else {
+            "euclid-preprocessing"
+        };
+        assert_eq!(stamp.prover, expected);
+        assert_eq!(stamp.mp2_version, verifiable_db::version());
+    }
+}
@@ -1,5 +1,6 @@
 use alloy::primitives::Address;
 use alloy::primitives::U256;
+use ethers::types::H256;
 use mp2_common::digest::TableDimension;
 use mp2_common::types::HashOutput;
 
@@ -35,6 +36,13 @@ pub trait StorageExtractionProver {
         child_proofs: Vec<Vec<u8>>,
     ) -> anyhow::Result<Vec<u8>>;
 
+    /// Check that `child_proof` is a well-formed proof of the MPT node hashing to `node_hash`.
+    fn verify_mpt_child_proof(
+        &self,
+        child_proof: &[u8],
+        node_hash: H256,
+    ) -> anyhow::Result<()>;
+
     /// Prove the length extraction of a leaf MPT node.
     fn prove_length_leaf(
         &self,
@@ -1,3 +1,5 @@
+use anyhow::ensure;
+use anyhow::Context;
 use lgn_messages::types::v1::preprocessing::db_keys;
 use lgn_messages::types::v1::preprocessing::db_tasks::DatabaseType;
 use lgn_messages::types::v1::preprocessing::db_tasks::DbBlockType;
@@ -7,6 +9,7 @@ use lgn_messages::types::v1::preprocessing::ext_keys;
 use lgn_messages::types::v1::preprocessing::ext_tasks::ExtractionType;
 use lgn_messages::types::v1::preprocessing::ext_tasks::FinalExtraction;
 use lgn_messages::types::v1::preprocessing::ext_tasks::FinalExtractionType;
+use lgn_messages::types::v1::preprocessing::ext_tasks::MptNodeVersion;
 use lgn_messages::types::v1::preprocessing::ext_tasks::MptType;
 use lgn_messages::types::v1::preprocessing::WorkerTask;
 use lgn_messages::types::v1::preprocessing::WorkerTaskType;
@@ -17,15 +20,19 @@ use lgn_messages::types::ReplyType;
 use lgn_messages::types::TaskType;
 use lgn_messages::types::WorkerReply;
 
+use crate::provers::prover_stamp;
 use crate::provers::v1::preprocessing::prover::StorageDatabaseProver;
 use crate::provers::v1::preprocessing::prover::StorageExtractionProver;
 use crate::provers::LgnProver;
+use crate::provers::ProverName;
 
 pub struct Preprocessing<P> {
     prover: P,
+    /// Whether the children proofs of the branch MPT nodes are checked before being used.
+    verify_children_proofs: bool,
 }
 
-impl<P: StorageExtractionProver + StorageDatabaseProver> LgnProver<TaskType, ReplyType>
+impl<P: StorageExtractionProver + StorageDatabaseProver + ProverName> LgnProver<TaskType, ReplyType>
     for Preprocessing<P>
 {
     fn run(
@@ -51,7 +58,8 @@ impl<P: StorageExtractionProver + StorageDatabaseProver> LgnProver<TaskType, Rep
                 Some((key, result)),
                 ProofCategory::Querying,
             ));
-            Ok(MessageReplyEnvelope::new(query_id, task_id, reply_type))
+            Ok(MessageReplyEnvelope::new(query_id, task_id, reply_type)
+                .with_prover(prover_stamp::<P>()))
         } else {
             anyhow::bail!("Received unexpected task: {:?}", envelope);
         }
@@ -59,7 +67,20 @@ impl<P: StorageExtractionProver + StorageDatabaseProver> LgnProver<TaskType, Rep
 }
 impl<P: StorageExtractionProver + StorageDatabaseProver> Preprocessing<P> {
     pub fn new(prover: P) -> Self {
-        Self { prover }
+        Self {
+            prover,
+            verify_children_proofs: false,
+        }
+    }
+
+    /// Check the children proofs of the branch MPT nodes before proving the branches, for a
+    /// corrupted or mismatched child proof to fail the task with a descriptive error.
+    pub fn with_verify_children_proofs(
+        mut self,
+        verify_children_proofs: bool,
+    ) -> Self {
+        self.verify_children_proofs = verify_children_proofs;
+        self
     }
 
     pub fn run_inner(
@@ -70,6 +91,7 @@ impl<P: StorageExtractionProver + StorageDatabaseProver> Preprocessing<P> {
             WorkerTaskType::Extraction(extraction) => {
                 match extraction {
                     ExtractionType::MptExtraction(mpt) => {
+                        mpt.validate().context("invalid MPT node")?;
                         match &mpt.mpt_type {
                             MptType::VariableLeaf(variable_leaf) => {
                                 self.prover.prove_single_variable_leaf(
@@ -79,6 +101,7 @@ impl<P: StorageExtractionProver + StorageDatabaseProver> Preprocessing<P> {
                                 )?
                             },
                             MptType::MappingLeaf(mapping_leaf) => {
+                                mapping_leaf.validate().context("invalid mapping leaf")?;
                                 self.prover.prove_mapping_variable_leaf(
                                     mapping_leaf.key.clone(),
                                     mapping_leaf.node.clone(),
@@ -88,12 +111,26 @@ impl<P: StorageExtractionProver + StorageDatabaseProver> Preprocessing<P> {
                                 )?
                             },
                             MptType::MappingBranch(mapping_branch) => {
+                                mapping_branch
+                                    .validate()
+                                    .context("invalid mapping branch")?;
+                                self.check_children_proofs(
+                                    &mapping_branch.children,
+                                    &mapping_branch.children_proofs,
+                                )?;
                                 self.prover.prove_mapping_variable_branch(
                                     mapping_branch.node.clone(),
                                     mapping_branch.children_proofs.to_owned(),
                                 )?
                             },
                             MptType::VariableBranch(variable_branch) => {
+                                variable_branch
+                                    .validate()
+                                    .context("invalid variable branch")?;
+                                self.check_children_proofs(
+                                    &variable_branch.children,
+                                    &variable_branch.children_proofs,
+                                )?;
                                 self.prover.prove_single_variable_branch(
                                     variable_branch.node.clone(),
                                     variable_branch.children_proofs.clone(),
@@ -102,6 +139,7 @@ impl<P: StorageExtractionProver + StorageDatabaseProver> Preprocessing<P> {
                         }
                     },
                     ExtractionType::LengthExtraction(length) => {
+                        length.validate().context("invalid length extraction")?;
                         let mut proofs = vec![];
                         for (i, node) in length.nodes.iter().enumerate() {
                             if i == 0 {
@@ -121,6 +159,7 @@ impl<P: StorageExtractionProver + StorageDatabaseProver> Preprocessing<P> {
                         proofs.last().unwrap().clone()
                     },
                     ExtractionType::ContractExtraction(contract) => {
+                        contract.validate().context("invalid contract extraction")?;
                         let mut proofs = vec![];
                         for (i, node) in contract.nodes.iter().enumerate() {
                             if i == 0 {
@@ -146,6 +185,9 @@ impl<P: StorageExtractionProver + StorageDatabaseProver> Preprocessing<P> {
                     ExtractionType::FinalExtraction(final_extraction) => {
                         match *final_extraction {
                             FinalExtraction::Single(single_table_extraction) => {
+                                single_table_extraction
+                                    .validate()
+                                    .context("invalid single table extraction")?;
                                 match single_table_extraction.extraction_type {
                                     FinalExtractionType::Simple(compound) => {
                                         self.prover.prove_final_extraction_simple(
@@ -166,6 +208,9 @@ impl<P: StorageExtractionProver + StorageDatabaseProver> Preprocessing<P> {
                                 }
                             },
                             FinalExtraction::Merge(mapping_table_extraction) => {
+                                mapping_table_extraction
+                                    .validate()
+                                    .context("invalid merge table extraction")?;
                                 self.prover.prove_final_extraction_merge(
                                     mapping_table_extraction.block_proof.clone(),
                                     mapping_table_extraction.contract_proof.clone(),
@@ -286,4 +331,114 @@ impl<P: StorageExtractionProver + StorageDatabaseProver> Preprocessing<P> {
             },
         })
     }
+
+    /// Check the proof of every child of a branch MPT node, if enabled.
+    fn check_children_proofs(
+        &self,
+        children: &[MptNodeVersion],
+        children_proofs: &[Vec<u8>],
+    ) -> anyhow::Result<()> {
+        if !self.verify_children_proofs {
+            return Ok(());
+        }
+        ensure!(
+            children.len() == children_proofs.len(),
+            "{} children proofs for {} children",
+            children_proofs.len(),
+            children.len()
+        );
+        for (i, ((block_nr, node_hash), proof)) in children.iter().zip(children_proofs).enumerate()
+        {
+            self.prover
+                .verify_mpt_child_proof(proof, *node_hash)
+                .with_context(|| {
+                    format!("invalid proof of child {i}, node {node_hash:?} at block {block_nr}")
+                })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "dummy-prover"))]
+mod tests {
+    use alloy::primitives::Address;
+    use ethers::types::H256;
+
+    use super::*;
+    use crate::dummy_utils::dummy_proof;
+    use crate::provers::v1::preprocessing::dummy_prover::DummyProver;
+
+    #[test]
+    fn test_check_children_proofs() {
+        let children = vec![(1, H256::repeat_byte(1)), (1, H256::repeat_byte(2))];
+        let mut proofs = vec![dummy_proof(120), dummy_proof(120)];
+        let preprocessing = Preprocessing::new(DummyProver).with_verify_children_proofs(true);
+        preprocessing
+            .check_children_proofs(&children, &proofs)
+            .unwrap();
+
+        // A tampered child proof is reported by its position.
+        proofs[1].truncate(100);
+        let error = preprocessing
+            .check_children_proofs(&children, &proofs)
+            .unwrap_err();
+        assert!(error.to_string().starts_with("invalid proof of child 1,"));
+        assert!(preprocessing
+            .check_children_proofs(&children, &proofs[..1])
+            .is_err());
+
+        // Unless enabled, the children proofs are trusted.
+        Preprocessing::new(DummyProver)
+            .check_children_proofs(&children, &proofs)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_seeded_proof() {
+        let preprocessing = Preprocessing::new(DummyProver);
+        let task = WorkerTask::new(1, 2, WorkerTaskType::ivc(7, 2, false));
+        let prove = |seed| crate::seed::with_seed(seed, || preprocessing.run_inner(task.clone()));
+
+        assert_eq!(prove(Some(42)).unwrap(), prove(Some(42)).unwrap());
+        assert_ne!(prove(Some(42)).unwrap(), prove(Some(43)).unwrap());
+        assert_ne!(prove(None).unwrap(), prove(None).unwrap());
+    }
+
+    #[test]
+    fn test_standalone_contract_extraction() {
+        let preprocessing = Preprocessing::new(DummyProver);
+        let contract = Address::repeat_byte(1);
+        let contract_proof = preprocessing
+            .run_inner(WorkerTask::new(
+                1,
+                2,
+                WorkerTaskType::ext_contract(2, contract, vec![vec![1], vec![2]], vec![3]),
+            ))
+            .unwrap();
+
+        let mut final_extraction =
+            FinalExtraction::new_single_table(7, 8, 2, contract, None, (2, H256::zero()));
+        let FinalExtraction::Single(single_table_extraction) = &mut final_extraction else {
+            unreachable!()
+        };
+        single_table_extraction.block_proof = dummy_proof(120);
+        single_table_extraction.value_proof = dummy_proof(120);
+        single_table_extraction.length_proof = dummy_proof(120);
+        let task = |final_extraction| {
+            WorkerTask::new(
+                1,
+                2,
+                WorkerTaskType::Extraction(ExtractionType::FinalExtraction(Box::new(
+                    final_extraction,
+                ))),
+            )
+        };
+
+        // The final extraction can not be proven until given the contract proof.
+        assert!(preprocessing
+            .run_inner(task(final_extraction.clone()))
+            .is_err());
+        *final_extraction.contract_proof_ref_mut().1 = contract_proof;
+        preprocessing.run_inner(task(final_extraction)).unwrap();
+    }
 }
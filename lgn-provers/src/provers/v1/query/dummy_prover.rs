@@ -8,6 +8,7 @@ use verifiable_db::revelation::api::MatchingRow;
 
 use crate::dummy_utils::dummy_proof;
 use crate::provers::v1::query::prover::StorageQueryProver;
+use crate::provers::ProverName;
 
 const PROOF_SIZE: usize = 120;
 
@@ -69,3 +70,7 @@ impl StorageQueryProver for DummyProver {
         Ok(dummy_proof(PROOF_SIZE))
     }
 }
+
+impl ProverName for DummyProver {
+    const NAME: &'static str = "dummy-query";
+}
@@ -28,6 +28,7 @@ use super::MAX_NUM_PREDICATE_OPS;
 use super::MAX_NUM_RESULT_OPS;
 use super::ROW_TREE_MAX_DEPTH;
 use crate::params;
+use crate::provers::ProverName;
 
 pub(crate) struct EuclidQueryProver {
     params: QueryParameters<
@@ -69,10 +70,8 @@ impl EuclidQueryProver {
         file: &str,
         checksums: &HashMap<String, blake3::Hash>,
     ) -> anyhow::Result<Self> {
-        let params = params::prepare_raw(url, dir, file, checksums)
+        let params = params::prepare(url, dir, file, checksums)
             .context("while loading bincode-serialized parameters")?;
-        let reader = std::io::BufReader::new(params.as_ref());
-        let params = bincode::deserialize_from(reader)?;
         Ok(Self { params })
     }
 }
@@ -326,3 +325,7 @@ impl StorageQueryProver for EuclidQueryProver {
         Ok(proof)
     }
 }
+
+impl ProverName for EuclidQueryProver {
+    const NAME: &'static str = "euclid-query";
+}
@@ -5,6 +5,7 @@ use tracing::info;
 
 use crate::provers::v1::query::prover::StorageQueryProver;
 use crate::provers::v1::query::task::Querying;
+use crate::provers::ProverName;
 
 pub(crate) mod prover;
 pub mod task;
@@ -31,7 +32,7 @@ pub fn create_prover(
     dir: &str,
     file: &str,
     checksums: &HashMap<String, blake3::Hash>,
-) -> anyhow::Result<Querying<impl StorageQueryProver>> {
+) -> anyhow::Result<Querying<impl StorageQueryProver + ProverName>> {
     let prover = {
         #[cfg(feature = "dummy-prover")]
         let prover = {
@@ -54,3 +55,24 @@ pub fn create_prover(
 
     Ok(Querying::new(prover))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::provers::prover_stamp;
+
+    #[test]
+    fn test_prover_stamp() {
+        #[cfg(feature = "dummy-prover")]
+        let stamp = prover_stamp::<super::dummy_prover::DummyProver>();
+        #[cfg(not(feature = "dummy-prover"))]
+        let stamp = prover_stamp::<super::euclid_prover::EuclidQueryProver>();
+
+        let expected = if cfg!(feature = "dummy-prover") {
+            "dummy-query"
+        } else {
+            "euclid-query"
+        };
+        assert_eq!(stamp.prover, expected);
+        assert_eq!(stamp.mp2_version, verifiable_db::version());
+    }
+}
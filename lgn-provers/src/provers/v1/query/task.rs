@@ -1,10 +1,13 @@
 use anyhow::bail;
+use anyhow::ensure;
+use lgn_messages::types::v1::preprocessing::db_keys;
 use lgn_messages::types::v1::query::keys::ProofKey;
 use lgn_messages::types::v1::query::tasks::Hydratable;
 use lgn_messages::types::v1::query::tasks::HydratableMatchingRow;
 use lgn_messages::types::v1::query::tasks::ProofInputKind;
 use lgn_messages::types::v1::query::tasks::QueryStep;
 use lgn_messages::types::v1::query::tasks::RevelationInput;
+use lgn_messages::types::v1::query::QueryPublicInputs;
 use lgn_messages::types::v1::query::WorkerTask;
 use lgn_messages::types::v1::query::WorkerTaskType;
 use lgn_messages::types::MessageEnvelope;
@@ -13,16 +16,21 @@ use lgn_messages::types::ProofCategory;
 use lgn_messages::types::ReplyType;
 use lgn_messages::types::TaskType;
 use lgn_messages::types::WorkerReply;
+use metrics::counter;
 use parsil::assembler::DynamicCircuitPis;
 
+use crate::provers::prover_stamp;
 use crate::provers::v1::query::prover::StorageQueryProver;
 use crate::provers::LgnProver;
+use crate::provers::ProverName;
 
 pub struct Querying<P> {
     prover: P,
+    /// If set, the tabular queries matching more rows than this are rejected.
+    max_matching_rows: Option<usize>,
 }
 
-impl<P: StorageQueryProver> LgnProver<TaskType, ReplyType> for Querying<P> {
+impl<P: StorageQueryProver + ProverName> LgnProver<TaskType, ReplyType> for Querying<P> {
     fn run(
         &self,
         envelope: &MessageEnvelope<TaskType>,
@@ -32,13 +40,17 @@ impl<P: StorageQueryProver> LgnProver<TaskType, ReplyType> for Querying<P> {
 
         if let TaskType::V1Query(ref task @ WorkerTask { chain_id, .. }) = envelope.inner {
             let key: ProofKey = task.into();
-            let result = self.run_inner(task)?;
-            let reply_type = ReplyType::V1Query(WorkerReply::new(
-                chain_id,
-                Some((key.to_string(), result)),
-                ProofCategory::Querying,
-            ));
-            Ok(MessageReplyEnvelope::new(query_id, task_id, reply_type))
+            let (result, public_inputs) = self.run_inner(task)?;
+            let reply_type = ReplyType::V1Query(
+                WorkerReply::new(
+                    chain_id,
+                    Some((key.to_string(), result)),
+                    ProofCategory::Querying,
+                )
+                .with_public_inputs(public_inputs),
+            );
+            Ok(MessageReplyEnvelope::new(query_id, task_id, reply_type)
+                .with_prover(prover_stamp::<P>()))
         } else {
             bail!("Received unexpected task: {:?}", envelope);
         }
@@ -47,13 +59,27 @@ impl<P: StorageQueryProver> LgnProver<TaskType, ReplyType> for Querying<P> {
 
 impl<P: StorageQueryProver> Querying<P> {
     pub fn new(prover: P) -> Self {
-        Self { prover }
+        Self {
+            prover,
+            max_matching_rows: None,
+        }
+    }
+
+    /// Reject the tabular queries matching more than `max_matching_rows` rows, to bound the
+    /// proving time and memory of a single task.
+    pub fn with_max_matching_rows(
+        mut self,
+        max_matching_rows: Option<usize>,
+    ) -> Self {
+        self.max_matching_rows = max_matching_rows;
+        self
     }
 
+    /// Prove `task`, returning the proof along with its public inputs for the revelation steps.
     pub fn run_inner(
         &self,
         task: &WorkerTask,
-    ) -> anyhow::Result<Vec<u8>> {
+    ) -> anyhow::Result<(Vec<u8>, Option<QueryPublicInputs>)> {
         #[allow(irrefutable_let_patterns)]
         let WorkerTaskType::Query(ref input) = task.task_type
         else {
@@ -62,7 +88,7 @@ impl<P: StorageQueryProver> Querying<P> {
 
         let pis: DynamicCircuitPis = serde_json::from_slice(&input.pis)?;
 
-        let final_proof = match &input.query_step {
+        let (final_proof, public_inputs) = match &input.query_step {
             QueryStep::Tabular(rows_inputs, revelation_input) => {
                 let RevelationInput::Tabular {
                     placeholders,
@@ -76,6 +102,9 @@ impl<P: StorageQueryProver> Querying<P> {
                 else {
                     panic!("Wrong RevelationInput for QueryStep::Tabular");
                 };
+                check_matching_rows(matching_rows.len(), self.max_matching_rows)?;
+                let public_inputs =
+                    QueryPublicInputs::new(placeholders)?.with_pagination(*limit, *offset);
 
                 let mut matching_rows_proofs = vec![];
                 for (row_input, mut matching_row) in rows_inputs.iter().zip(matching_rows.clone()) {
@@ -91,7 +120,7 @@ impl<P: StorageQueryProver> Querying<P> {
                     matching_rows_proofs.push(matching_row_proof);
                 }
 
-                self.prover.prove_tabular_revelation(
+                let proof = self.prover.prove_tabular_revelation(
                     &pis,
                     placeholders.clone().into(),
                     indexing_proof.clone_proof(),
@@ -99,10 +128,11 @@ impl<P: StorageQueryProver> Querying<P> {
                     column_ids,
                     *limit,
                     *offset,
-                )?
+                )?;
+                (proof, Some(public_inputs))
             },
             QueryStep::Aggregation(input) => {
-                match &input.input_kind {
+                let proof = match &input.input_kind {
                     ProofInputKind::RowsChunk(rc) => self.prover.prove_row_chunks(rc.clone(), &pis),
                     ProofInputKind::ChunkAggregation(ca) => {
                         let chunks_proofs = ca
@@ -115,7 +145,8 @@ impl<P: StorageQueryProver> Querying<P> {
                     ProofInputKind::NonExistence(ne) => {
                         self.prover.prove_non_existence(*ne.clone(), &pis)
                     },
-                }?
+                }?;
+                (proof, None)
             },
             QueryStep::Revelation(input) => {
                 match input {
@@ -125,12 +156,14 @@ impl<P: StorageQueryProver> Querying<P> {
                         query_proof,
                         ..
                     } => {
-                        self.prover.prove_aggregated_revelation(
+                        let public_inputs = QueryPublicInputs::new(placeholders)?;
+                        let proof = self.prover.prove_aggregated_revelation(
                             &pis,
                             placeholders.clone().into(),
                             query_proof.clone_proof(),
                             indexing_proof.clone_proof(),
-                        )
+                        )?;
+                        (proof, Some(public_inputs))
                     },
                     RevelationInput::Tabular {
                         placeholders,
@@ -141,7 +174,11 @@ impl<P: StorageQueryProver> Querying<P> {
                         offset,
                         ..
                     } => {
-                        self.prover.prove_tabular_revelation(
+                        check_matching_rows(matching_rows.len(), self.max_matching_rows)?;
+                        check_hydrated(indexing_proof, matching_rows)?;
+                        let public_inputs =
+                            QueryPublicInputs::new(placeholders)?.with_pagination(*limit, *offset);
+                        let proof = self.prover.prove_tabular_revelation(
                             &pis,
                             placeholders.clone().into(),
                             indexing_proof.clone_proof(),
@@ -153,12 +190,79 @@ impl<P: StorageQueryProver> Querying<P> {
                             column_ids,
                             *limit,
                             *offset,
-                        )
+                        )?;
+                        (proof, Some(public_inputs))
                     },
-                }?
+                }
             },
         };
 
-        Ok(final_proof)
+        Ok((final_proof, public_inputs))
+    }
+}
+
+/// Fail if a tabular query matches more than `max_matching_rows` rows.
+fn check_matching_rows(
+    matching_rows: usize,
+    max_matching_rows: Option<usize>,
+) -> anyhow::Result<()> {
+    let Some(max_matching_rows) = max_matching_rows else {
+        return Ok(());
+    };
+    if matching_rows > max_matching_rows {
+        counter!("zkmr_worker_tasks_rejected_total", "reason" => "too_many_matching_rows")
+            .increment(1);
+        bail!("the query matches {matching_rows} rows, over the limit of {max_matching_rows}");
+    }
+    Ok(())
+}
+
+/// Ensure that a revelation task embeds all the proofs it reveals, none being proven here.
+fn check_hydrated(
+    indexing_proof: &Hydratable<db_keys::ProofKey>,
+    matching_rows: &[HydratableMatchingRow],
+) -> anyhow::Result<()> {
+    ensure!(
+        indexing_proof.is_hydrated(),
+        "the indexing proof {} of the revelation is not hydrated",
+        indexing_proof.key()
+    );
+    if let Some(row) = matching_rows.iter().find(|row| !row.proof.is_hydrated()) {
+        bail!(
+            "the matching row proof {} of the revelation is not hydrated",
+            row.proof.key()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_matching_rows() {
+        assert!(check_matching_rows(1_000_000, None).is_ok());
+        assert!(check_matching_rows(0, Some(10)).is_ok());
+        assert!(check_matching_rows(10, Some(10)).is_ok());
+        assert_eq!(
+            check_matching_rows(11, Some(10)).unwrap_err().to_string(),
+            "the query matches 11 rows, over the limit of 10"
+        );
+    }
+
+    #[test]
+    fn test_check_hydrated() {
+        let hydrated = Hydratable::Hydrated(std::sync::Arc::new(vec![1, 2, 3]));
+        check_hydrated(&hydrated, &[]).unwrap();
+
+        let dehydrated = Hydratable::new(db_keys::ProofKey::IVC(1, 2));
+        assert_eq!(
+            check_hydrated(&dehydrated, &[]).unwrap_err().to_string(),
+            format!(
+                "the indexing proof {} of the revelation is not hydrated",
+                db_keys::ProofKey::IVC(1, 2)
+            )
+        );
     }
 }
@@ -0,0 +1,65 @@
+//! Seeding of the randomized steps of proving, for a task carrying a seed to be reproduced byte
+//! for byte, e.g. to audit a disputed proof, see `MessageEnvelope::seed`.
+//!
+//! Only the dummy provers draw their randomness from the seed. The euclid preprocessing and query
+//! provers draw none of their own, so that their proofs do not depend on it, while the Groth16
+//! prover draws its blinding factors within gnark, which the seed does not reach.
+
+use std::cell::Cell;
+
+thread_local! {
+    /// The seed of the task being proven on this thread, and how many draws were made from it.
+    static SEED: Cell<Option<(u64, u64)>> = const { Cell::new(None) };
+}
+
+/// Restores the seed of the enclosing scope, even if proving panicked.
+struct Restore(Option<(u64, u64)>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        SEED.set(self.0);
+    }
+}
+
+/// Run `f`, proving a task, with its randomized steps seeded by `seed` if set.
+pub fn with_seed<T>(
+    seed: Option<u64>,
+    f: impl FnOnce() -> T,
+) -> T {
+    let _restore = Restore(SEED.replace(seed.map(|seed| (seed, 0))));
+    f()
+}
+
+/// `len` bytes drawn from the seed of the task being proven, if it has one.
+#[cfg_attr(not(feature = "dummy-prover"), allow(dead_code))]
+pub(crate) fn seeded_bytes(len: usize) -> Option<Vec<u8>> {
+    let (seed, draws) = SEED.get()?;
+    SEED.set(Some((seed, draws + 1)));
+    let mut bytes = vec![0; len];
+    blake3::Hasher::new()
+        .update(&seed.to_le_bytes())
+        .update(&draws.to_le_bytes())
+        .finalize_xof()
+        .fill(&mut bytes);
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_seed() {
+        assert_eq!(seeded_bytes(8), None);
+
+        let draws = || (seeded_bytes(8).unwrap(), seeded_bytes(8).unwrap());
+        let (first, second) = with_seed(Some(7), draws);
+        assert_ne!(first, second);
+        assert_eq!(with_seed(Some(7), draws), (first.clone(), second));
+        assert_ne!(with_seed(Some(8), draws).0, first);
+
+        // The seed does not outlive the task, even if it panicked.
+        let _ = std::panic::catch_unwind(|| with_seed(Some(7), || panic!("proving failed")));
+        assert_eq!(seeded_bytes(8), None);
+    }
+}
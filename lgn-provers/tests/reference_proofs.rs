@@ -0,0 +1,103 @@
+//! Regression tests proving the checked-in tasks of `tests/fixtures` and comparing their proofs
+//! with reference ones, for param or circuit regressions to be caught before deploying.
+//!
+//! Each fixture is a directory holding a preprocessing task envelope, `task.json`, and the
+//! reference proof of each prover, `proof.<prover>.hex`: `dummy` with the `dummy-prover` feature,
+//! `euclid` otherwise. The fixtures without a reference proof for the current prover are skipped.
+//! The tasks carry a seed, for the dummy proofs to be reproducible; the euclid preprocessing
+//! proofs are deterministic.
+//!
+//! The euclid prover needs its params, hence the fixtures are only proven with it when
+//! `LGN_PREPROCESSING_PARAMS` is set to the path of the preprocessing params file. Setting
+//! `BLESS_REFERENCE_PROOFS` writes the proofs of the current prover as the reference ones instead
+//! of comparing them, e.g. after a deliberate circuit change.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use lgn_messages::types::MessageEnvelope;
+use lgn_messages::types::ReplyType;
+use lgn_messages::types::TaskType;
+use lgn_provers::provers::v1::preprocessing::create_prover;
+use lgn_provers::provers::LgnProver;
+use lgn_provers::seed::with_seed;
+
+/// The prover the reference proofs are compared with.
+const PROVER: &str = if cfg!(feature = "dummy-prover") {
+    "dummy"
+} else {
+    "euclid"
+};
+
+/// The preprocessing prover, unless its params are not available.
+fn preprocessing_prover() -> Option<impl LgnProver<TaskType, ReplyType>> {
+    if cfg!(feature = "dummy-prover") {
+        return Some(create_prover("", "", "", &HashMap::new()).unwrap());
+    }
+
+    let params = PathBuf::from(std::env::var("LGN_PREPROCESSING_PARAMS").ok()?);
+    let dir = params.parent().unwrap().to_str().unwrap();
+    let file = params.file_name().unwrap().to_str().unwrap();
+    // With a matching checksum, the local params are used as they are, without being downloaded.
+    let checksum = blake3::hash(&std::fs::read(&params).unwrap());
+    let checksums = HashMap::from([(file.to_string(), checksum)]);
+    Some(create_prover("", dir, file, &checksums).unwrap())
+}
+
+/// Prove the task of `fixture`, returning its proof.
+fn prove(
+    prover: &impl LgnProver<TaskType, ReplyType>,
+    fixture: &Path,
+) -> Vec<u8> {
+    let task = std::fs::read(fixture.join("task.json")).unwrap();
+    let envelope: MessageEnvelope<TaskType> = serde_json::from_slice(&task).unwrap();
+    let mut reply = with_seed(envelope.seed, || prover.run(&envelope)).unwrap();
+    let (_, proof) = reply
+        .content_mut()
+        .worker_reply_mut()
+        .and_then(|reply| reply.proof.take())
+        .expect("the reply holds a proof");
+    proof
+}
+
+#[test]
+fn test_reference_proofs() {
+    let Some(prover) = preprocessing_prover() else {
+        eprintln!("LGN_PREPROCESSING_PARAMS is not set, skipping the reference proofs");
+        return;
+    };
+    let bless = std::env::var_os("BLESS_REFERENCE_PROOFS").is_some();
+
+    let mut fixtures =
+        std::fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+    fixtures.sort();
+    for fixture in fixtures {
+        let reference = fixture.join(format!("proof.{PROVER}.hex"));
+        if !bless && !reference.exists() {
+            eprintln!(
+                "{}: no {PROVER} reference proof, skipping",
+                fixture.display()
+            );
+            continue;
+        }
+
+        let proof = prove(&prover, &fixture)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        if bless {
+            std::fs::write(&reference, format!("{proof}\n")).unwrap();
+        } else {
+            assert_eq!(
+                proof,
+                std::fs::read_to_string(&reference).unwrap().trim(),
+                "{}: the proof differs from the reference one",
+                fixture.display()
+            );
+        }
+    }
+}
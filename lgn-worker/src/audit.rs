@@ -0,0 +1,162 @@
+//! Append-only trail of the decisions taken on every task, see `audit_log`.
+//!
+//! Every `received` event is followed by exactly one terminal event, `rejected`, `completed` or
+//! `failed`, with `accepted` and `started` in between for the tasks which got that far.
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Context;
+use anyhow::Result;
+use lgn_messages::TableId;
+use metrics::counter;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::config::AuditLogConfig;
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum AuditEvent<'a> {
+    Received {
+        task: &'a str,
+        size: usize,
+    },
+    Rejected {
+        task: &'a str,
+        reason: &'a str,
+    },
+    Accepted {
+        task: &'a str,
+        id: &'a str,
+        class: String,
+        table_id: Option<TableId>,
+    },
+    Started {
+        task: &'a str,
+    },
+    Completed {
+        task: &'a str,
+        reply_size: usize,
+        duration_ms: u64,
+        /// Whether the reply of a previous run of the task was resent.
+        cached: bool,
+    },
+    Failed {
+        task: &'a str,
+        error_code: &'static str,
+        error: &'a str,
+        duration_ms: u64,
+    },
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    timestamp_ms: u64,
+    #[serde(flatten)]
+    event: AuditEvent<'a>,
+}
+
+/// Writes the audit events as JSON lines to a file, if configured.
+pub(crate) struct AuditLog {
+    file: Option<Mutex<File>>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(config: Option<&AuditLogConfig>) -> Result<Self> {
+        let file = config
+            .map(|config| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&config.file)
+                    .with_context(|| format!("opening audit log {}", config.file))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            file: file.map(Mutex::new),
+        })
+    }
+
+    /// Append `event` to the log.
+    ///
+    /// Failing to do so must not fail the task, but is counted for alerts.
+    pub(crate) fn record(
+        &self,
+        event: AuditEvent<'_>,
+    ) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        if let Err(e) = Self::write(file, event) {
+            warn!("writing audit log: {e:?}");
+            counter!("zkmr_worker_audit_log_errors_total").increment(1);
+        }
+    }
+
+    fn write(
+        file: &Mutex<File>,
+        event: AuditEvent<'_>,
+    ) -> Result<()> {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_millis() as u64;
+        let mut line = serde_json::to_vec(&Entry {
+            timestamp_ms,
+            event,
+        })?;
+        line.push(b'\n');
+        // One write per line, for lines not to interleave.
+        file.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .write_all(&line)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", std::process::id()));
+        let config = AuditLogConfig {
+            file: path.display().to_string(),
+        };
+        let audit_log = AuditLog::new(Some(&config)).unwrap();
+        audit_log.record(AuditEvent::Received {
+            task: "task",
+            size: 3,
+        });
+        audit_log.record(AuditEvent::Rejected {
+            task: "task",
+            reason: "table 8 is not served by this worker",
+        });
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let events = lines
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "received");
+        assert_eq!(events[0]["size"], 3);
+        assert!(events[0]["timestamp_ms"].is_u64());
+        assert_eq!(events[1]["event"], "rejected");
+        assert_eq!(events[1]["reason"], "table 8 is not served by this worker");
+
+        // Disabled, the log records nothing.
+        AuditLog::new(None)
+            .unwrap()
+            .record(AuditEvent::Started { task: "task" });
+    }
+}
@@ -0,0 +1,82 @@
+//! Delay before reconnecting to the gateway after losing the stream, see `reconnect_backoff_ms`.
+//!
+//! The delay doubles with each reconnection in a row, from `reconnect_backoff_ms` up to
+//! `reconnect_max_backoff_ms`, and is reset once a connection outlived the longest delay, for a
+//! flapping gateway not to be hammered while a one-off disconnection is recovered from quickly.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::config::WorkerConfig;
+
+pub(crate) struct Backoff {
+    base: Duration,
+    max: Duration,
+    /// How many reconnections in a row happened.
+    attempts: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new(config: &WorkerConfig) -> Self {
+        Self {
+            base: Duration::from_millis(config.reconnect_backoff_ms),
+            max: Duration::from_millis(config.reconnect_max_backoff_ms),
+            attempts: 0,
+        }
+    }
+
+    /// How long to wait before reconnecting, after losing a connection opened at `connected_at`
+    /// at `now`.
+    pub(crate) fn next(
+        &mut self,
+        connected_at: Instant,
+        now: Instant,
+    ) -> Duration {
+        if now.duration_since(connected_at) >= self.max {
+            self.attempts = 0;
+        }
+        let delay = self
+            .base
+            .checked_mul(2u32.saturating_pow(self.attempts))
+            .map_or(self.max, |delay| delay.min(self.max));
+        self.attempts = self.attempts.saturating_add(1);
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff {
+            base: Duration::from_millis(500),
+            max: Duration::from_secs(3),
+            attempts: 0,
+        };
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        assert_eq!(backoff.next(at(0), at(10)), Duration::from_millis(500));
+        assert_eq!(backoff.next(at(600), at(700)), Duration::from_millis(1000));
+        assert_eq!(
+            backoff.next(at(2000), at(2100)),
+            Duration::from_millis(2000)
+        );
+        // The delay is capped.
+        assert_eq!(backoff.next(at(4000), at(4100)), Duration::from_secs(3));
+        assert_eq!(backoff.next(at(7000), at(7100)), Duration::from_secs(3));
+
+        // A connection which outlived the longest delay starts afresh.
+        assert_eq!(
+            backoff.next(at(10000), at(13000)),
+            Duration::from_millis(500)
+        );
+
+        for _ in 0..100 {
+            backoff.next(at(20000), at(20000));
+        }
+        assert_eq!(backoff.next(at(20000), at(20000)), Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,128 @@
+//! Summary of the proving of a directory of captured tasks, see `lgn-worker bench`.
+//!
+//! Built with the `dummy-prover` feature, the benchmark runs without the real params, measuring
+//! the overhead of the worker alone.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::ensure;
+use anyhow::Result;
+use lgn_messages::types::MessageEnvelope;
+use lgn_messages::types::TaskType;
+use lgn_messages::types::ToProverType;
+use tracing::error;
+
+use crate::transport::ResultSink;
+use crate::transport::Task;
+
+/// Collects the outcome and latency of every task, grouped by prover.
+#[derive(Default)]
+pub(crate) struct BenchSink {
+    classes: BTreeMap<String, ClassStats>,
+}
+
+#[derive(Default)]
+struct ClassStats {
+    failures: usize,
+    latencies: Vec<Duration>,
+}
+
+impl ResultSink<PathBuf> for BenchSink {
+    async fn send_result(
+        &mut self,
+        task: &Task<PathBuf>,
+        result: Result<Vec<u8>, String>,
+    ) -> Result<()> {
+        let class = serde_json::from_slice::<MessageEnvelope<TaskType>>(&task.payload)
+            .map(|envelope| envelope.inner().to_prover_type().to_string())
+            .unwrap_or_else(|_| "invalid".to_string());
+        let stats = self.classes.entry(class).or_default();
+
+        match result {
+            Ok(_) => stats.latencies.push(task.received_at.elapsed()),
+            Err(error_str) => {
+                error!("{}: {error_str}", task.name);
+                stats.failures += 1;
+            },
+        }
+
+        Ok(())
+    }
+}
+
+impl BenchSink {
+    /// Print the summary table, failing if any task failed.
+    ///
+    /// Tasks being proven one at a time, the throughput of a class is the number of its tasks
+    /// proven per second of proving them.
+    pub(crate) fn report(&self) -> Result<()> {
+        println!(
+            "{:<24} {:>6} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "class", "ok", "failed", "tasks/s", "p50 (s)", "p90 (s)", "p99 (s)", "max (s)"
+        );
+
+        let mut total_ok = 0;
+        let mut total_failures = 0;
+        for (class, stats) in &self.classes {
+            let mut latencies = stats.latencies.clone();
+            latencies.sort();
+            let seconds = |latency: Option<&Duration>| {
+                latency
+                    .map(|latency| format!("{:.3}", latency.as_secs_f64()))
+                    .unwrap_or_else(|| "-".to_string())
+            };
+            let busy = latencies.iter().sum::<Duration>().as_secs_f64();
+            let throughput = if busy > 0.0 {
+                format!("{:.3}", latencies.len() as f64 / busy)
+            } else {
+                "-".to_string()
+            };
+            println!(
+                "{:<24} {:>6} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                class,
+                latencies.len(),
+                stats.failures,
+                throughput,
+                seconds(percentile(&latencies, 50)),
+                seconds(percentile(&latencies, 90)),
+                seconds(percentile(&latencies, 99)),
+                seconds(latencies.last()),
+            );
+            total_ok += latencies.len();
+            total_failures += stats.failures;
+        }
+        println!("{:<24} {:>6} {:>6}", "total", total_ok, total_failures);
+
+        ensure!(total_failures == 0, "{total_failures} tasks failed");
+        Ok(())
+    }
+}
+
+/// The nearest-rank `p`th percentile of the sorted `latencies`.
+fn percentile(
+    latencies: &[Duration],
+    p: usize,
+) -> Option<&Duration> {
+    let rank = (latencies.len() * p).div_ceil(100);
+    latencies.get(rank.saturating_sub(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let latencies = (1..=10).map(Duration::from_secs).collect::<Vec<_>>();
+        assert_eq!(percentile(&latencies, 50), Some(&Duration::from_secs(5)));
+        assert_eq!(percentile(&latencies, 90), Some(&Duration::from_secs(9)));
+        assert_eq!(percentile(&latencies, 99), Some(&Duration::from_secs(10)));
+        assert_eq!(
+            percentile(&latencies[..1], 50),
+            Some(&Duration::from_secs(1))
+        );
+        assert_eq!(percentile(&[], 50), None);
+    }
+}
@@ -0,0 +1,54 @@
+//! The canonical form of the replies, which identical proofs share whatever the order their fields
+//! are serialized in, for consumers to dedupe them by its hash, see `worker.content_hash`.
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+
+/// The hex-encoded Blake3 hash of the canonical form of `content`: compact JSON, with the keys of
+/// every object sorted.
+///
+/// Computed before the proof is compressed or encoded, for the hash not to depend on how it is
+/// sent.
+pub(crate) fn content_hash(content: &impl Serialize) -> Result<String> {
+    let value = serde_json::to_value(content).context("serializing reply content")?;
+    let canonical =
+        serde_json::to_vec(&canonicalize(value)).context("serializing reply content")?;
+    Ok(blake3::hash(&canonical).to_hex().to_string())
+}
+
+/// Sort the keys of the objects of `value`, whichever map type backs them.
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries = map
+                .into_iter()
+                .map(|(key, value)| (key, canonicalize(value)))
+                .collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(entries.into_iter().collect())
+        },
+        Value::Array(values) => Value::Array(values.into_iter().map(canonicalize).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_field_order() {
+        let a = json!({"proof": [1, 2], "chain_id": 1, "inputs": {"b": 2, "a": 1}});
+        let b = json!({"inputs": {"a": 1, "b": 2}, "chain_id": 1, "proof": [1, 2]});
+        assert_eq!(content_hash(&a).unwrap(), content_hash(&b).unwrap());
+        assert_ne!(
+            content_hash(&a).unwrap(),
+            content_hash(&json!({"proof": [2, 1], "chain_id": 1, "inputs": {"a": 1, "b": 2}}))
+                .unwrap()
+        );
+    }
+}
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use anyhow::anyhow;
 use anyhow::Context;
+use lgn_provers::params::PARAMS_MANIFEST_SUFFIX;
 use reqwest::IntoUrl;
 
 /// Fetch the checksums stored at `url`, then parse them into a mapping from file name to Blake3
@@ -47,3 +48,80 @@ pub(crate) async fn fetch_checksums(
 
     Ok(r)
 }
+
+/// Check that `checksums` has an entry for every file of `required`, or for its manifest if it is
+/// split across multiple files, listing all the missing ones otherwise. The entries of the files
+/// which are not required, e.g. the params of the provers of other classes, are ignored.
+pub(crate) fn check_checksums(
+    checksums: &HashMap<String, blake3::Hash>,
+    required: &[&str],
+) -> anyhow::Result<()> {
+    let missing = required
+        .iter()
+        .filter(|file| {
+            !checksums.contains_key(**file)
+                && !checksums.contains_key(&format!("{file}{PARAMS_MANIFEST_SUFFIX}"))
+        })
+        .map(|file| format!("`{file}`"))
+        .collect::<Vec<_>>();
+    anyhow::ensure!(
+        missing.is_empty(),
+        "no checksum for the required param files {}",
+        missing.join(", ")
+    );
+
+    for file in checksums.keys() {
+        let params = file.strip_suffix(PARAMS_MANIFEST_SUFFIX).unwrap_or(file);
+        if !required.contains(&params) {
+            tracing::debug!("ignoring the checksum of `{file}`, which is not required");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksums(files: &[&str]) -> HashMap<String, blake3::Hash> {
+        files
+            .iter()
+            .map(|file| (file.to_string(), blake3::hash(file.as_bytes())))
+            .collect()
+    }
+
+    #[test]
+    fn test_check_checksums_missing() {
+        let checksums = checksums(&["query_params.bin"]);
+        let error = check_checksums(
+            &checksums,
+            &[
+                "query_params.bin",
+                "preprocessing_params.bin",
+                "groth16_assets/pk.bin",
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "no checksum for the required param files `preprocessing_params.bin`, \
+             `groth16_assets/pk.bin`"
+        );
+    }
+
+    #[test]
+    fn test_check_checksums_extra() {
+        let checksums = checksums(&["query_params.bin", "groth16_assets/pk.bin"]);
+        check_checksums(&checksums, &["query_params.bin"]).unwrap();
+    }
+
+    #[test]
+    fn test_check_checksums_manifest() {
+        let checksums = checksums(&["query_params.bin", "preprocessing_params.bin.manifest"]);
+        check_checksums(
+            &checksums,
+            &["query_params.bin", "preprocessing_params.bin"],
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+//! Cap on the pressure the worker puts on a failing gateway, see `circuit_breaker`.
+//!
+//! Connection attempts are retried after `retry_delay` while the circuit is closed. Once
+//! `failure_threshold` attempts failed within `window`, the circuit opens and the worker waits
+//! for `cooldown` instead, then tries a single half-open attempt which either closes the circuit
+//! or opens it again.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use metrics::gauge;
+use serde::Serialize;
+use tracing::info;
+use tracing::warn;
+
+use crate::config::CircuitBreakerConfig;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum CircuitState {
+    /// Connection attempts are retried after a short delay.
+    Closed,
+    /// Connection attempts are suspended until the cooldown is over.
+    Open,
+    /// A single connection attempt probes whether the gateway recovered.
+    HalfOpen,
+}
+
+impl CircuitState {
+    /// The value of the `zkmr_worker_gateway_circuit_state` gauge.
+    fn gauge_value(self) -> f64 {
+        match self {
+            CircuitState::Closed => 0.0,
+            CircuitState::HalfOpen => 1.0,
+            CircuitState::Open => 2.0,
+        }
+    }
+}
+
+pub(crate) struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    state: CircuitState,
+    /// When the recent failed attempts happened, within the window.
+    failures: VecDeque<Instant>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(config: CircuitBreakerConfig) -> Self {
+        gauge!("zkmr_worker_gateway_circuit_state").set(CircuitState::Closed.gauge_value());
+        Self {
+            config,
+            inner: Mutex::new(Inner {
+                state: CircuitState::Closed,
+                failures: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn state(&self) -> CircuitState {
+        self.lock().state
+    }
+
+    /// Announce a connection attempt, which probes the gateway if the circuit is open.
+    pub(crate) fn attempt(&self) {
+        let mut inner = self.lock();
+        if inner.state == CircuitState::Open {
+            Self::transition(&mut inner, CircuitState::HalfOpen);
+            info!("gateway circuit half-open, probing the gateway");
+        }
+    }
+
+    /// Record a successful connection attempt.
+    pub(crate) fn record_success(&self) {
+        let mut inner = self.lock();
+        inner.failures.clear();
+        if inner.state != CircuitState::Closed {
+            Self::transition(&mut inner, CircuitState::Closed);
+            info!("gateway circuit closed, the gateway recovered");
+        }
+    }
+
+    /// Record a connection attempt which failed at `now`, returning how long to wait before the
+    /// next one.
+    pub(crate) fn record_failure(
+        &self,
+        now: Instant,
+    ) -> Duration {
+        let window = Duration::from_secs(self.config.window);
+        let cooldown = Duration::from_secs(self.config.cooldown);
+        let mut inner = self.lock();
+
+        inner.failures.push_back(now);
+        while inner
+            .failures
+            .front()
+            .is_some_and(|failure| now.duration_since(*failure) > window)
+        {
+            inner.failures.pop_front();
+        }
+
+        match inner.state {
+            CircuitState::HalfOpen => {
+                Self::transition(&mut inner, CircuitState::Open);
+                warn!(
+                    "gateway circuit open again, the gateway is still failing; next attempt in \
+                     {}s",
+                    cooldown.as_secs()
+                );
+                cooldown
+            },
+            CircuitState::Closed if inner.failures.len() >= self.config.failure_threshold => {
+                Self::transition(&mut inner, CircuitState::Open);
+                warn!(
+                    "gateway circuit open after {} failed connection attempts within {}s; next \
+                     attempt in {}s",
+                    inner.failures.len(),
+                    window.as_secs(),
+                    cooldown.as_secs()
+                );
+                cooldown
+            },
+            CircuitState::Closed | CircuitState::Open => {
+                Duration::from_secs(self.config.retry_delay)
+            },
+        }
+    }
+
+    fn transition(
+        inner: &mut Inner,
+        state: CircuitState,
+    ) {
+        inner.state = state;
+        gauge!("zkmr_worker_gateway_circuit_state").set(state.gauge_value());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 3,
+            window: 60,
+            retry_delay: 5,
+            cooldown: 300,
+        });
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // Failures spread wider than the window do not open the circuit.
+        assert_eq!(breaker.record_failure(at(0)), Duration::from_secs(5));
+        assert_eq!(breaker.record_failure(at(50)), Duration::from_secs(5));
+        assert_eq!(breaker.record_failure(at(100)), Duration::from_secs(5));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        assert_eq!(breaker.record_failure(at(105)), Duration::from_secs(300));
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // A failed probe opens the circuit again.
+        breaker.attempt();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_eq!(breaker.record_failure(at(405)), Duration::from_secs(300));
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // A successful probe closes it, with a clean slate.
+        breaker.attempt();
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(breaker.record_failure(at(706)), Duration::from_secs(5));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
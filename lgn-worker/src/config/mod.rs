@@ -1,10 +1,24 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
 use config::FileFormat;
 use lazy_static_include::*;
+use lgn_messages::types::ProofCompression;
+use lgn_messages::types::ProofEncoding;
+use lgn_messages::types::ProverType;
 use lgn_messages::types::TaskDifficulty;
+use lgn_messages::TableId;
 use lgn_provers::params::PARAMS_CHECKSUM_FILENAME;
 use redact::Secret;
+use reqwest::Url;
 use serde_derive::Deserialize;
-use tracing::debug;
+use tracing_subscriber::EnvFilter;
+
+use crate::tenants::MAX_TENANT_METRIC_LABELS;
+use crate::tenants::NO_TENANT;
+use crate::tenants::OTHER_TENANT;
 
 lazy_static_include_str! {
     DEFAULT_CONFIG => "src/config/default.toml",
@@ -20,6 +34,34 @@ pub(crate) struct Config {
     pub(crate) public_params: PublicParamsConfig,
     /// Prometheus-specific settings.
     pub(crate) prometheus: PrometheusConfig,
+    /// How hard the worker retries connecting to a failing gateway.
+    pub(crate) circuit_breaker: CircuitBreakerConfig,
+    /// What the liveness probe reports while the worker is saturated with tasks.
+    pub(crate) saturation: SaturationConfig,
+    /// Where to upload the proofs too large to be sent to the gateway, if anywhere.
+    pub(crate) proof_store: Option<ProofStoreConfig>,
+    /// Where to keep a copy of the tasks which failed, if anywhere.
+    pub(crate) failed_tasks: Option<FailedTasksConfig>,
+    /// Where to append the trail of the decisions taken on every task, if anywhere.
+    pub(crate) audit_log: Option<AuditLogConfig>,
+    /// How the tasks of the tenants sharing the worker are told apart, if they are.
+    pub(crate) tenants: Option<TenantsConfig>,
+    /// Where to write the flamegraphs of the proofs profiled on demand, if they may be.
+    pub(crate) flamegraph: Option<FlamegraphConfig>,
+    /// How the per-task error logs are rate limited, if they are.
+    pub(crate) log_rate_limit: Option<LogRateLimitConfig>,
+    /// How and where the worker logs.
+    #[serde(default)]
+    pub(crate) logging: LoggingConfig,
+    /// How proofs are encoded in the replies, per task type.
+    #[serde(default)]
+    pub(crate) proof_encoding: ProofEncodingConfig,
+    /// How proofs are compressed in the replies, per task type.
+    #[serde(default)]
+    pub(crate) proof_compression: ProofCompressionConfig,
+    /// When the provers are initialized, per task type.
+    #[serde(default)]
+    pub(crate) prover_loading: ProverLoadingConfig,
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq)]
@@ -29,6 +71,16 @@ pub(crate) struct PublicParamsConfig {
     pub(crate) params_root_url: String,
     /// Where to store the public parameters on disk.
     pub(crate) dir: String,
+    /// How many param files may be downloaded at the same time from the same host.
+    pub(crate) max_downloads_per_host: usize,
+    /// If set, the most memory the loaded params may hold, in MiB, as approximated by the size of
+    /// their files; loading params which would exceed it fails.
+    pub(crate) max_memory_mb: Option<u64>,
+    /// Whether the configured params are not fit for production, e.g. faster params for staging.
+    /// The worker then advertises a distinct class, for the gateway not to route production
+    /// tasks to it.
+    #[serde(default)]
+    pub(crate) non_production: bool,
     /// The files required to build the pre-processing public parameters.
     pub(crate) preprocessing_params: PreprocessingParams,
     /// The files required to build the querying public parameters.
@@ -38,12 +90,30 @@ pub(crate) struct PublicParamsConfig {
 }
 
 impl PublicParamsConfig {
-    pub fn validate(&self) {
-        assert!(!self.params_root_url.is_empty(), "URL is required");
-        assert!(!self.dir.is_empty(), "Directory is required");
-        self.preprocessing_params.validate();
-        self.query_params.validate();
-        self.groth16_assets.validate();
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(errors, !self.params_root_url.is_empty(), "URL is required");
+        check(
+            errors,
+            self.params_root_url.is_empty() || Url::parse(&self.params_root_url).is_ok(),
+            format!("Invalid params URL {}", self.params_root_url),
+        );
+        check(errors, !self.dir.is_empty(), "Directory is required");
+        check(
+            errors,
+            self.max_downloads_per_host > 0,
+            "max_downloads_per_host must be at least 1",
+        );
+        check(
+            errors,
+            self.max_memory_mb != Some(0),
+            "max_memory_mb must be positive",
+        );
+        self.preprocessing_params.validate(errors);
+        self.query_params.validate(errors);
+        self.groth16_assets.validate(errors);
     }
 
     /// Build the base URL with path of mp2 version for downloading param files.
@@ -51,6 +121,17 @@ impl PublicParamsConfig {
         add_mp2_version_path_to_url(&self.params_root_url)
     }
 
+    /// The param files of all the provers.
+    pub(crate) fn files(&self) -> Vec<&str> {
+        vec![
+            &self.preprocessing_params.file,
+            &self.query_params.file,
+            &self.groth16_assets.circuit_file,
+            &self.groth16_assets.r1cs_file,
+            &self.groth16_assets.pk_file,
+        ]
+    }
+
     /// Build the URL for downloading the checksum file.
     pub fn checksum_file_url(&self) -> String {
         let url = self.params_base_url();
@@ -64,8 +145,15 @@ pub(crate) struct PreprocessingParams {
 }
 
 impl PreprocessingParams {
-    pub fn validate(&self) {
-        assert!(!self.file.is_empty(), "Preprocessing file is required");
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(
+            errors,
+            !self.file.is_empty(),
+            "Preprocessing file is required",
+        );
     }
 }
 
@@ -75,8 +163,11 @@ pub(crate) struct QueryParams {
 }
 
 impl QueryParams {
-    pub fn validate(&self) {
-        assert!(!self.file.is_empty(), "Query2 file is required");
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(errors, !self.file.is_empty(), "Query2 file is required");
     }
 }
 
@@ -88,10 +179,17 @@ pub(crate) struct Groth16Assets {
 }
 
 impl Groth16Assets {
-    pub fn validate(&self) {
-        assert!(!self.circuit_file.is_empty(), "Circuit URL is required");
-        assert!(!self.r1cs_file.is_empty(), "R1CS URL is required");
-        assert!(!self.pk_file.is_empty(), "PK URL is required");
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(
+            errors,
+            !self.circuit_file.is_empty(),
+            "Circuit URL is required",
+        );
+        check(errors, !self.r1cs_file.is_empty(), "R1CS URL is required");
+        check(errors, !self.pk_file.is_empty(), "PK URL is required");
     }
 }
 
@@ -99,74 +197,878 @@ impl Groth16Assets {
 pub(crate) struct WorkerConfig {
     pub(crate) instance_type: TaskDifficulty,
     pub(crate) liveness_check_interval: u64,
+    /// If set, exit cleanly after having processed this many tasks.
+    pub(crate) max_tasks: Option<u64>,
+    /// If set, exit cleanly after having run for this many seconds.
+    pub(crate) max_uptime: Option<u64>,
+    /// How many replies to completed tasks to retain, for them to be resent if the gateway
+    /// requests the same task again; 0 disables the cache.
+    pub(crate) result_cache_size: usize,
+    /// How long to retain replies to completed tasks, in seconds.
+    pub(crate) result_cache_ttl: u64,
+    /// How many replies to the last tasks proven to retain by the hash of their envelope, for a
+    /// task delivered again not to be proven again; 0 disables the cache.
+    pub(crate) proof_cache_entries: usize,
+    /// The most tasks taken from the gateway stream at once, among those which already arrived,
+    /// to amortize the per-message overhead of small tasks.
+    pub(crate) task_batch_size: usize,
+    /// The most tasks proven at the same time; no more tasks are taken from the gateway stream
+    /// while that many are in flight.
+    pub(crate) max_concurrent_tasks: usize,
+    /// What to do with a task received while another one with the same task ID is in flight.
+    #[serde(default)]
+    pub(crate) duplicate_task_ids: DuplicateTaskPolicy,
+    /// Over how many of the last tasks the success ratio is computed.
+    pub(crate) success_ratio_window: usize,
+    /// If set, fail startup if the provers are not all initialized within this many seconds.
+    pub(crate) init_timeout: Option<u64>,
+    /// If set, fail startup if any single prover takes longer than this many seconds to
+    /// initialize.
+    pub(crate) prover_init_timeout: Option<u64>,
+    /// If set, prove in a child process, restarted whenever it crashes, rather than in the worker
+    /// process itself.
+    #[serde(default)]
+    pub(crate) isolated_proving: bool,
+    /// How many seconds to wait after the provers are initialized before reporting the worker
+    /// as ready.
+    #[serde(default)]
+    pub(crate) readiness_delay: u64,
+    /// Panics while proving whose message contains any of these are deemed to leave the worker
+    /// in a bad state, which then exits to be restarted instead of proving further tasks.
+    #[serde(default)]
+    pub(crate) fatal_panics: Vec<String>,
+    /// How much of the backtrace of the panics while proving a task is logged, the panics
+    /// elsewhere always logging their full backtrace.
+    #[serde(default)]
+    pub(crate) task_panic_backtrace: BacktraceVerbosity,
+    /// Tasks which panicked with a message containing any of these are proven once more before
+    /// failing, as a stopgap for the circuits known to panic spuriously.
+    #[serde(default)]
+    pub(crate) retry_panics: Vec<String>,
+    /// If not empty, only the tasks of these tables are proven, the others being rejected. Tasks
+    /// which are not tied to a table ID, e.g. block extraction or query tasks, are always proven.
+    #[serde(default)]
+    pub(crate) allowed_tables: Vec<TableId>,
+    /// If not empty, only the tasks of these classes are proven, the others being rejected as
+    /// unsupported for the gateway to route them to another worker. The provers and params of the
+    /// other classes are not loaded.
+    #[serde(default)]
+    pub(crate) accepted_classes: Vec<MessageClass>,
+    /// If set, the longest a task may take to prove, in seconds. A tighter deadline set by the
+    /// gateway on the task takes precedence.
+    pub(crate) task_timeout: Option<u64>,
+    /// If set, a task which exceeded its timeout may keep proving for this many more seconds, its
+    /// late proof being sent for the gateway to use it if still relevant: after the timeout error
+    /// with isolated proving, in place of it otherwise.
+    pub(crate) late_proof_grace: Option<u64>,
+    /// If set, the tabular queries matching more rows than this are rejected before any row is
+    /// proven.
+    pub(crate) max_matching_rows: Option<usize>,
+    /// If set, the children proofs of the branch MPT nodes are checked before proving the
+    /// branches, failing the task with a descriptive error if one is corrupted or mismatched.
+    #[serde(default)]
+    pub(crate) verify_children_proofs: bool,
+    /// If set, the CPU time and peak memory spent proving each task are attached to its reply.
+    #[serde(default)]
+    pub(crate) resource_usage: bool,
+    /// If set, the hash of the canonical form of each reply content is attached to it.
+    #[serde(default)]
+    pub(crate) content_hash: bool,
+    /// How long to wait before the first reconnection to the gateway after losing the stream, in
+    /// milliseconds, doubling with each reconnection in a row.
+    pub(crate) reconnect_backoff_ms: u64,
+    /// The longest wait before reconnecting to the gateway, in milliseconds.
+    pub(crate) reconnect_max_backoff_ms: u64,
+    /// How long to wait for the task in flight to complete after a SIGTERM or SIGINT, in seconds,
+    /// before exiting anyway.
+    pub(crate) shutdown_grace_secs: u64,
+}
+
+impl WorkerConfig {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(
+            errors,
+            self.max_tasks != Some(0),
+            "max_tasks must be positive",
+        );
+        check(
+            errors,
+            self.task_batch_size > 0,
+            "task_batch_size must be positive",
+        );
+        check(
+            errors,
+            self.max_concurrent_tasks > 0,
+            "max_concurrent_tasks must be positive",
+        );
+        check(
+            errors,
+            self.max_concurrent_tasks == 1 || !self.isolated_proving,
+            "isolated_proving proves one task at a time, max_concurrent_tasks must be 1",
+        );
+        check(
+            errors,
+            self.success_ratio_window > 0,
+            "success_ratio_window must be positive",
+        );
+        check(
+            errors,
+            self.max_uptime != Some(0),
+            "max_uptime must be positive",
+        );
+        check(
+            errors,
+            self.init_timeout != Some(0),
+            "init_timeout must be positive",
+        );
+        check(
+            errors,
+            self.prover_init_timeout != Some(0),
+            "prover_init_timeout must be positive",
+        );
+        check(
+            errors,
+            self.task_timeout != Some(0),
+            "task_timeout must be positive",
+        );
+        check(
+            errors,
+            self.late_proof_grace != Some(0),
+            "late_proof_grace must be positive",
+        );
+        check(
+            errors,
+            self.max_matching_rows != Some(0),
+            "max_matching_rows must be positive",
+        );
+        check(
+            errors,
+            self.retry_panics.iter().all(|pattern| !pattern.is_empty()),
+            "retry_panics may not contain an empty pattern, which would retry every panic",
+        );
+        check(
+            errors,
+            self.reconnect_backoff_ms > 0,
+            "reconnect_backoff_ms must be positive",
+        );
+        check(
+            errors,
+            self.reconnect_max_backoff_ms >= self.reconnect_backoff_ms,
+            "reconnect_max_backoff_ms must be at least reconnect_backoff_ms",
+        );
+    }
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq)]
 pub(crate) struct AvsConfig {
     pub(crate) gateway_url: String,
     pub(crate) max_grpc_message_size_mb: Option<usize>,
+    /// PEM bundle of additional CAs to trust for the gateway certificate.
+    pub(crate) gateway_ca_file: Option<String>,
+    /// The domain name to expect in the gateway certificate, if different from the URL host.
+    pub(crate) gateway_domain: Option<String>,
     pub(crate) issuer: String,
     pub(crate) worker_id: String,
+    /// The `aud` claim of the token presented to the gateway, if it requires one.
+    pub(crate) audience: Option<String>,
+    /// If set, the token presented to the gateway is minted in the background every this many
+    /// seconds rather than on every connection.
+    pub(crate) token_refresh_secs: Option<u64>,
+    /// Additional private claims of the token presented to the gateway, e.g. the region.
+    #[serde(default)]
+    pub(crate) extra_claims: BTreeMap<String, serde_json::Value>,
+    /// Where the key signing the worker token comes from.
+    #[serde(default)]
+    pub(crate) wallet_mode: WalletMode,
     pub(crate) lagr_keystore: Option<String>,
     pub(crate) lagr_pwd: Option<Secret<String>>,
     pub(crate) lagr_private_key: Option<Secret<String>>,
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WalletMode {
+    /// Decrypt `lagr_keystore` with `lagr_pwd`.
+    Keystore,
+    /// Use `lagr_private_key`, ignoring `lagr_keystore`.
+    PrivateKey,
+    /// Use `lagr_private_key` if set, and decrypt `lagr_keystore` with `lagr_pwd` otherwise, as
+    /// the configurations predating `wallet_mode` expect.
+    #[default]
+    KeystoreFallback,
+}
+
+/// The key signing the worker token, as resolved by [`AvsConfig::wallet_key`].
+#[derive(Debug, PartialEq)]
+pub(crate) enum WalletKey<'a> {
+    Keystore {
+        path: &'a str,
+        password: &'a Secret<String>,
+    },
+    PrivateKey(&'a Secret<String>),
+}
+
 #[derive(Deserialize, Debug, Clone, PartialEq)]
 pub(crate) struct PrometheusConfig {
     pub(crate) port: u16,
+    /// If set, prepended to the name of every metric.
+    pub(crate) prefix: Option<String>,
+    /// Labels added to every metric, e.g. the region or cluster of the deployment.
+    #[serde(default)]
+    pub(crate) global_labels: HashMap<String, String>,
+    /// What to do if `port` can not be bound, e.g. because another worker of the same host uses
+    /// it.
+    #[serde(default)]
+    pub(crate) on_bind_failure: OnBindFailure,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OnBindFailure {
+    /// Fail startup.
+    #[default]
+    Abort,
+    /// Run without exporting the metrics.
+    Warn,
+    /// Export the metrics on an ephemeral port instead, which is logged.
+    RandomPort,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct CircuitBreakerConfig {
+    /// How many failed connection attempts within `window` open the circuit.
+    pub(crate) failure_threshold: usize,
+    /// The window over which failed connection attempts are counted, in seconds.
+    pub(crate) window: u64,
+    /// How many seconds to wait before retrying a failed connection while the circuit is closed.
+    pub(crate) retry_delay: u64,
+    /// How many seconds to wait before probing the gateway once the circuit is open.
+    pub(crate) cooldown: u64,
+}
+
+impl CircuitBreakerConfig {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(
+            errors,
+            self.failure_threshold > 0,
+            "circuit_breaker.failure_threshold must be positive",
+        );
+        check(
+            errors,
+            self.window > 0,
+            "circuit_breaker.window must be positive",
+        );
+        check(
+            errors,
+            self.cooldown >= self.retry_delay,
+            "circuit_breaker.cooldown may not be shorter than circuit_breaker.retry_delay",
+        );
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct SaturationConfig {
+    /// What the liveness probe reports while the worker is saturated.
+    pub(crate) liveness: SaturationLiveness,
+    /// The share of the time spent with the proving pool full from which the worker is saturated.
+    pub(crate) threshold: f64,
+    /// Over how many seconds the share of the time spent with the proving pool full is measured.
+    pub(crate) duration: u64,
+}
+
+impl SaturationConfig {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(
+            errors,
+            self.threshold > 0.0 && self.threshold <= 1.0,
+            "saturation.threshold must be in (0, 1]",
+        );
+        check(
+            errors,
+            self.duration > 0,
+            "saturation.duration must be positive",
+        );
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SaturationLiveness {
+    /// Report the worker as live, saturation being normal under load.
+    #[default]
+    Live,
+    /// Fail the liveness probe once saturated for `duration`, for the worker to be rescheduled.
+    Degraded,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct ProofStoreConfig {
+    /// The object store URL to upload proofs to, e.g. `s3://bucket/prefix`.
+    pub(crate) url: String,
+    /// Replies larger than this are uploaded rather than sent inline.
+    pub(crate) threshold_mb: usize,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct FailedTasksConfig {
+    /// The directory to write the failed tasks to.
+    pub(crate) dir: String,
+    /// How many failed tasks to retain, the oldest ones being deleted first.
+    pub(crate) max_records: usize,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct FlamegraphConfig {
+    /// The directory to write the flamegraphs to, as SVG files named after their task.
+    pub(crate) dir: String,
+    /// How many times per second the stacks of the worker are sampled while capturing.
+    pub(crate) frequency: i32,
+}
+
+impl FlamegraphConfig {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(
+            errors,
+            cfg!(feature = "flamegraph"),
+            "flamegraph requires the worker to be built with the `flamegraph` feature",
+        );
+        check(
+            errors,
+            !self.dir.is_empty(),
+            "Flamegraph directory is required",
+        );
+        check(
+            errors,
+            self.frequency > 0,
+            "flamegraph.frequency must be positive",
+        );
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct LogRateLimitConfig {
+    /// Over how many seconds the occurrences of a message are counted.
+    pub(crate) window: u64,
+    /// How many occurrences of the same message are logged per window, the others being
+    /// summarized once it is over.
+    pub(crate) burst: usize,
+}
+
+impl LogRateLimitConfig {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(
+            errors,
+            self.window > 0,
+            "log_rate_limit.window must be positive",
+        );
+        check(
+            errors,
+            self.burst > 0,
+            "log_rate_limit.burst must be positive",
+        );
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct LoggingConfig {
+    #[serde(default)]
+    pub(crate) format: LogFormat,
+    /// If set, the logs are written to this file, rotated daily, rather than to stdout.
+    pub(crate) file: Option<String>,
+    /// If set, which logs are written, as `RUST_LOG` directives, unless `RUST_LOG` itself is set.
+    pub(crate) directives: Option<String>,
+}
+
+impl LoggingConfig {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        if let Some(file) = &self.file {
+            check(
+                errors,
+                std::path::Path::new(file).file_name().is_some(),
+                format!("logging.file `{file}` is not a file path"),
+            );
+        }
+        if let Some(directives) = &self.directives {
+            check(
+                errors,
+                EnvFilter::builder().parse(directives).is_ok(),
+                format!("logging.directives `{directives}` are invalid"),
+            );
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    /// Human-readable, one line per event.
+    #[default]
+    Pretty,
+    /// One JSON object per event.
+    Json,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct AuditLogConfig {
+    /// The file to append the audit events to, as JSON lines.
+    pub(crate) file: String,
+}
+
+impl AuditLogConfig {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(errors, !self.file.is_empty(), "Audit log file is required");
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct TenantsConfig {
+    /// The tenants labelling the per-tenant metrics, any other being labelled `other`. Every
+    /// tenant listed adds series to each of these metrics, hence the list must stay short.
+    #[serde(default)]
+    pub(crate) metric_labels: Vec<String>,
+    /// The audit logs of tenants, kept apart from the main one.
+    #[serde(default)]
+    pub(crate) audit_logs: BTreeMap<String, AuditLogConfig>,
+}
+
+impl TenantsConfig {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(
+            errors,
+            self.metric_labels.len() <= MAX_TENANT_METRIC_LABELS,
+            format!("tenants.metric_labels may list at most {MAX_TENANT_METRIC_LABELS} tenants"),
+        );
+        check(
+            errors,
+            self.metric_labels
+                .iter()
+                .all(|tenant| ![NO_TENANT, OTHER_TENANT, ""].contains(&tenant.as_str())),
+            format!(
+                "tenants.metric_labels may not list an empty tenant, `{NO_TENANT}` or \
+                 `{OTHER_TENANT}`"
+            ),
+        );
+        for audit_log in self.audit_logs.values() {
+            audit_log.validate(errors);
+        }
+    }
+}
+
+impl FailedTasksConfig {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(
+            errors,
+            !self.dir.is_empty(),
+            "Failed tasks directory is required",
+        );
+        check(
+            errors,
+            self.max_records > 0,
+            "Failed tasks max_records must be positive",
+        );
+    }
+}
+
+impl ProofStoreConfig {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(errors, !self.url.is_empty(), "Proof store URL is required");
+        check(
+            errors,
+            self.url.is_empty() || Url::parse(&self.url).is_ok(),
+            format!("Invalid proof store URL {}", self.url),
+        );
+        check(
+            errors,
+            self.threshold_mb > 0,
+            "Proof store threshold must be positive",
+        );
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct ProofEncodingConfig {
+    #[serde(default)]
+    pub(crate) preprocessing: ProofEncoding,
+    #[serde(default)]
+    pub(crate) query: ProofEncoding,
+    #[serde(default)]
+    pub(crate) groth16: ProofEncoding,
+}
+
+impl ProofEncodingConfig {
+    /// The encoding to use for the proofs of the given task type.
+    pub fn get(
+        &self,
+        prover_type: ProverType,
+    ) -> ProofEncoding {
+        match prover_type {
+            ProverType::V1Preprocessing => self.preprocessing,
+            ProverType::V1Query => self.query,
+            ProverType::V1Groth16 => self.groth16,
+            _ => ProofEncoding::Raw,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct ProofCompressionConfig {
+    #[serde(default)]
+    pub(crate) preprocessing: CompressionConfig,
+    #[serde(default)]
+    pub(crate) query: CompressionConfig,
+    #[serde(default)]
+    pub(crate) groth16: CompressionConfig,
+}
+
+impl ProofCompressionConfig {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        for (class, compression) in [
+            ("preprocessing", &self.preprocessing),
+            ("query", &self.query),
+            ("groth16", &self.groth16),
+        ] {
+            check(
+                errors,
+                (0..=22).contains(&compression.level),
+                format!("Invalid {class} compression level {}", compression.level),
+            );
+        }
+    }
+
+    /// The compression to use for the proofs of the given task type.
+    pub fn get(
+        &self,
+        prover_type: ProverType,
+    ) -> CompressionConfig {
+        match prover_type {
+            ProverType::V1Preprocessing => self.preprocessing,
+            ProverType::V1Query => self.query,
+            ProverType::V1Groth16 => self.groth16,
+            _ => CompressionConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct CompressionConfig {
+    #[serde(default)]
+    pub(crate) algorithm: ProofCompression,
+    /// The compression level, 0 for the default level of the algorithm.
+    #[serde(default)]
+    pub(crate) level: i32,
+    /// Proofs shorter than this many bytes are sent uncompressed.
+    #[serde(default)]
+    pub(crate) min_size: usize,
+}
+
+/// The claims set by the worker itself, which `avs.extra_claims` may not override.
+const RESERVED_CLAIMS: &[&str] = &[
+    "iss",
+    "sub",
+    "aud",
+    "exp",
+    "nbf",
+    "iat",
+    "jti",
+    "worker_class",
+];
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BacktraceVerbosity {
+    /// Only the panic message and location are logged.
+    None,
+    /// The innermost frames of the backtrace are logged along with them.
+    Short,
+    /// The whole backtrace is logged along with them.
+    #[default]
+    Full,
+}
+
+/// The classes of the tasks, the variants of their inner task, as named in the envelopes.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MessageClass {
+    V1Preprocessing,
+    V1Query,
+    V1Groth16,
+}
+
+impl From<MessageClass> for ProverType {
+    fn from(class: MessageClass) -> Self {
+        match class {
+            MessageClass::V1Preprocessing => ProverType::V1Preprocessing,
+            MessageClass::V1Query => ProverType::V1Query,
+            MessageClass::V1Groth16 => ProverType::V1Groth16,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DuplicateTaskPolicy {
+    /// The duplicate task is replied to with an error, without being proven.
+    #[default]
+    Reject,
+    /// The duplicate task is proven once the first one is replied to.
+    Queue,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct ProverLoadingConfig {
+    #[serde(default)]
+    pub(crate) preprocessing: ProverLoading,
+    #[serde(default)]
+    pub(crate) query: ProverLoading,
+    #[serde(default)]
+    pub(crate) groth16: ProverLoading,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ProverLoading {
+    /// The prover is initialized at startup, before the worker reports as ready.
+    #[default]
+    Eager,
+    /// The prover is initialized when the first task for it arrives, which waits for it.
+    Lazy,
 }
 
 impl AvsConfig {
-    pub fn validate(&self) {
-        assert!(!self.gateway_url.is_empty(), "Gateway URL is required");
-        assert!(!self.issuer.is_empty(), "Issuer is required");
-        assert!(!self.worker_id.is_empty(), "Worker ID is required");
-
-        match (&self.lagr_keystore, &self.lagr_pwd, &self.lagr_private_key) {
-            (Some(kpath), Some(pwd), _) => {
-                assert!(!kpath.is_empty(), "Keystore path is empty");
-                assert!(!pwd.expose_secret().is_empty(), "Password is empty");
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+    ) {
+        check(
+            errors,
+            !self.gateway_url.is_empty(),
+            "Gateway URL is required",
+        );
+        check(
+            errors,
+            self.gateway_url.is_empty()
+                || self.gateway_url.parse::<tonic::transport::Uri>().is_ok(),
+            format!("Invalid gateway URL {}", self.gateway_url),
+        );
+        check(errors, !self.issuer.is_empty(), "Issuer is required");
+        check(errors, !self.worker_id.is_empty(), "Worker ID is required");
+        check(
+            errors,
+            self.token_refresh_secs != Some(0),
+            "token_refresh_secs must be positive",
+        );
+        for key in self.extra_claims.keys() {
+            check(
+                errors,
+                !RESERVED_CLAIMS.contains(&key.as_str()),
+                format!("Extra claim `{key}` collides with a reserved claim"),
+            );
+        }
+        if let Some(ca_file) = &self.gateway_ca_file {
+            check(
+                errors,
+                std::path::Path::new(ca_file).is_file(),
+                format!("Gateway CA file {ca_file} does not exist"),
+            );
+        }
+
+        match self.wallet_key() {
+            Ok(WalletKey::Keystore { path, password }) => {
+                check(errors, !path.is_empty(), "Keystore path is empty");
+                check(
+                    errors,
+                    !password.expose_secret().is_empty(),
+                    "Password is empty",
+                );
             },
-            (None, None, Some(pkey)) => {
-                assert!(
+            Ok(WalletKey::PrivateKey(pkey)) => {
+                check(
+                    errors,
                     !pkey.expose_secret().is_empty(),
-                    "Private key value is empty"
+                    "Private key value is empty",
                 )
             },
-            _ => (),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    /// Resolve the key signing the worker token according to `wallet_mode`.
+    ///
+    /// A keystore path alone does not select the keystore, as the default configuration sets
+    /// one; a password or a private key set but unused by the mode is an error, as it likely
+    /// betrays a configuration mistake.
+    pub(crate) fn wallet_key(&self) -> Result<WalletKey<'_>, String> {
+        let keystore = || {
+            match (&self.lagr_keystore, &self.lagr_pwd) {
+                (Some(path), Some(password)) => Ok(WalletKey::Keystore { path, password }),
+                (None, _) => Err("lagr_keystore is required to use a keystore".to_string()),
+                (Some(_), None) => Err("lagr_pwd is required to decrypt the keystore".to_string()),
+            }
+        };
+
+        match (self.wallet_mode, &self.lagr_private_key) {
+            (WalletMode::Keystore, Some(_)) => {
+                Err(
+                    "lagr_private_key is set but wallet_mode is `keystore`; set wallet_mode to \
+                     `private_key` or `keystore_fallback` to use it"
+                        .to_string(),
+                )
+            },
+            (WalletMode::Keystore, None) => keystore(),
+            (WalletMode::PrivateKey, _) if self.lagr_pwd.is_some() => {
+                Err(
+                    "lagr_pwd is set but wallet_mode is `private_key`, which does not use the \
+                     keystore"
+                        .to_string(),
+                )
+            },
+            (WalletMode::PrivateKey, Some(pkey)) => Ok(WalletKey::PrivateKey(pkey)),
+            (WalletMode::PrivateKey, None) => {
+                Err("lagr_private_key is required with wallet_mode `private_key`".to_string())
+            },
+            (WalletMode::KeystoreFallback, Some(pkey)) => Ok(WalletKey::PrivateKey(pkey)),
+            (WalletMode::KeystoreFallback, None) => {
+                keystore().map_err(|e| format!("lagr_private_key is not set, and {e}"))
+            },
         }
     }
 }
 
+/// The documented configuration, with the default values and examples of the optional settings,
+/// as printed by `lgn-worker example-config`.
+pub(crate) fn example() -> &'static str {
+    &DEFAULT_CONFIG
+}
+
 impl Config {
-    pub fn load(local_file: Option<String>) -> Config {
+    /// Load the default configuration, overridden by `local_file` then by the environment.
+    ///
+    /// Failing rather than panicking, as this runs before the logging is set up.
+    pub fn load(local_file: Option<String>) -> Result<Config, config::ConfigError> {
         let mut config_builder = config::Config::builder();
         config_builder =
             config_builder.add_source(config::File::from_str(&DEFAULT_CONFIG, FileFormat::Toml));
 
         if let Some(local_file) = local_file {
-            debug!("Loading local configuration from {}", local_file);
             config_builder = config_builder.add_source(config::File::with_name(&local_file));
         }
 
-        let config_builder = config_builder
+        config_builder
             .add_source(
                 config::Environment::default()
                     .separator("__")
                     .ignore_empty(true),
             )
-            .build()
-            .expect("Could not load configuration");
-
-        config_builder
+            .build()?
             .try_deserialize()
-            .expect("Could not deserialize configuration")
     }
 
-    pub fn validate(&self) {
-        self.public_params.validate();
-        self.avs.validate();
+    /// Check the whole configuration, reporting every problem found rather than only the first
+    /// one.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = vec![];
+        self.worker.validate(&mut errors);
+        self.public_params.validate(&mut errors);
+        self.avs.validate(&mut errors);
+        self.circuit_breaker.validate(&mut errors);
+        self.saturation.validate(&mut errors);
+        if let Some(proof_store) = &self.proof_store {
+            proof_store.validate(&mut errors);
+        }
+        if let Some(failed_tasks) = &self.failed_tasks {
+            failed_tasks.validate(&mut errors);
+        }
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.validate(&mut errors);
+        }
+        if let Some(tenants) = &self.tenants {
+            tenants.validate(&mut errors);
+        }
+        if let Some(log_rate_limit) = &self.log_rate_limit {
+            log_rate_limit.validate(&mut errors);
+        }
+        self.logging.validate(&mut errors);
+        if let Some(flamegraph) = &self.flamegraph {
+            flamegraph.validate(&mut errors);
+            // The prover process can not be told to capture its proofs.
+            check(
+                &mut errors,
+                !self.worker.isolated_proving,
+                "flamegraph captures require isolated_proving to be disabled",
+            );
+        }
+        self.proof_compression.validate(&mut errors);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError { errors })
+        }
+    }
+}
+
+/// All the problems found in a [`Config`].
+#[derive(Debug)]
+pub(crate) struct ConfigError {
+    pub(crate) errors: Vec<String>,
+}
+
+impl Display for ConfigError {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(f, "{} configuration error(s):", self.errors.len())?;
+        for error in &self.errors {
+            write!(f, "\n  - {error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {
+}
+
+/// Record `message` in `errors` unless `condition` holds.
+fn check(
+    errors: &mut Vec<String>,
+    condition: bool,
+    message: impl Into<String>,
+) {
+    if !condition {
+        errors.push(message.into());
     }
 }
 
@@ -177,3 +1079,207 @@ fn add_mp2_version_path_to_url(url: &str) -> String {
     let mp2_version = semver::Version::parse(mp2_version_str).unwrap();
     format!("{url}/{}", mp2_version.major)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_config() {
+        // Uncomment the examples of the optional settings, leaving the comments alone.
+        let uncommented = example()
+            .lines()
+            .map(|line| {
+                let Some(setting) = line.strip_prefix("# ") else {
+                    return line;
+                };
+                let is_key = setting.split_once(" = ").is_some_and(|(key, _)| {
+                    key.chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                });
+                if setting.starts_with('[') || is_key {
+                    setting
+                } else {
+                    line
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let config = config::Config::builder()
+            .add_source(config::File::from_str(&uncommented, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize::<Config>()
+            .unwrap();
+        assert!(config.proof_store.is_some());
+        assert!(config.failed_tasks.is_some());
+        assert!(config.audit_log.is_some());
+        assert!(config.tenants.is_some());
+        assert!(config.avs.lagr_private_key.is_some());
+        assert!(config.logging.file.is_some());
+        let mut errors = vec![];
+        config.logging.validate(&mut errors);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn test_validate_reports_all_errors() {
+        let mut config = Config::load(None).unwrap();
+        config.worker.max_tasks = Some(0);
+        config.avs.issuer = String::new();
+        config.avs.gateway_url = "not a url".to_string();
+        config.avs.wallet_mode = WalletMode::Keystore;
+        config.avs.lagr_private_key = Some(Secret::new("0x00".to_string()));
+        config.public_params.query_params.file = String::new();
+
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.errors,
+            vec![
+                "max_tasks must be positive",
+                "Query2 file is required",
+                "Invalid gateway URL not a url",
+                "Issuer is required",
+                "lagr_private_key is set but wallet_mode is `keystore`; set wallet_mode to \
+                 `private_key` or `keystore_fallback` to use it",
+            ]
+        );
+        assert!(err
+            .to_string()
+            .starts_with("5 configuration error(s):\n  - max_tasks must be positive"));
+    }
+
+    #[test]
+    fn test_validate_reserved_claims() {
+        let mut config = Config::load(None).unwrap();
+        config.avs.extra_claims = BTreeMap::from([
+            ("region".to_string(), "eu-west-1".into()),
+            ("worker_class".to_string(), "large".into()),
+            ("iat".to_string(), 0.into()),
+        ]);
+
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.errors,
+            vec![
+                "Extra claim `iat` collides with a reserved claim",
+                "Extra claim `worker_class` collides with a reserved claim",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_wallet_mode() {
+        // As in the configurations predating `wallet_mode`, e.g. `devenv.nix`.
+        let mut avs = Config::load(None).unwrap().avs;
+        avs.lagr_private_key = Some(Secret::new("0x01".to_string()));
+        assert!(matches!(avs.wallet_key(), Ok(WalletKey::PrivateKey(_))));
+
+        avs.lagr_private_key = None;
+        avs.lagr_pwd = Some(Secret::new("password".to_string()));
+        assert!(matches!(avs.wallet_key(), Ok(WalletKey::Keystore { .. })));
+    }
+
+    #[test]
+    fn test_wallet_key() {
+        use WalletMode::*;
+
+        const KEYSTORE_WITH_PRIVATE_KEY: &str = "lagr_private_key is set but wallet_mode is \
+                                                 `keystore`; set wallet_mode to `private_key` or \
+                                                 `keystore_fallback` to use it";
+        const NO_KEYSTORE: &str = "lagr_keystore is required to use a keystore";
+        const NO_PASSWORD: &str = "lagr_pwd is required to decrypt the keystore";
+        const PRIVATE_KEY_WITH_PASSWORD: &str = "lagr_pwd is set but wallet_mode is \
+                                                 `private_key`, which does not use the keystore";
+        const NO_PRIVATE_KEY: &str = "lagr_private_key is required with wallet_mode `private_key`";
+        const FALLBACK_NO_KEYSTORE: &str =
+            "lagr_private_key is not set, and lagr_keystore is required to use a keystore";
+        const FALLBACK_NO_PASSWORD: &str =
+            "lagr_private_key is not set, and lagr_pwd is required to decrypt the keystore";
+
+        // (mode, keystore, password, private key, expected key or error)
+        let cases = [
+            (Keystore, false, false, false, Err(NO_KEYSTORE)),
+            (Keystore, false, false, true, Err(KEYSTORE_WITH_PRIVATE_KEY)),
+            (Keystore, false, true, false, Err(NO_KEYSTORE)),
+            (Keystore, false, true, true, Err(KEYSTORE_WITH_PRIVATE_KEY)),
+            (Keystore, true, false, false, Err(NO_PASSWORD)),
+            (Keystore, true, false, true, Err(KEYSTORE_WITH_PRIVATE_KEY)),
+            (Keystore, true, true, false, Ok("keystore")),
+            (Keystore, true, true, true, Err(KEYSTORE_WITH_PRIVATE_KEY)),
+            (PrivateKey, false, false, false, Err(NO_PRIVATE_KEY)),
+            (PrivateKey, false, false, true, Ok("private_key")),
+            (
+                PrivateKey,
+                false,
+                true,
+                false,
+                Err(PRIVATE_KEY_WITH_PASSWORD),
+            ),
+            (
+                PrivateKey,
+                false,
+                true,
+                true,
+                Err(PRIVATE_KEY_WITH_PASSWORD),
+            ),
+            (PrivateKey, true, false, false, Err(NO_PRIVATE_KEY)),
+            (PrivateKey, true, false, true, Ok("private_key")),
+            (
+                PrivateKey,
+                true,
+                true,
+                false,
+                Err(PRIVATE_KEY_WITH_PASSWORD),
+            ),
+            (PrivateKey, true, true, true, Err(PRIVATE_KEY_WITH_PASSWORD)),
+            (
+                KeystoreFallback,
+                false,
+                false,
+                false,
+                Err(FALLBACK_NO_KEYSTORE),
+            ),
+            (KeystoreFallback, false, false, true, Ok("private_key")),
+            (
+                KeystoreFallback,
+                false,
+                true,
+                false,
+                Err(FALLBACK_NO_KEYSTORE),
+            ),
+            (KeystoreFallback, false, true, true, Ok("private_key")),
+            (
+                KeystoreFallback,
+                true,
+                false,
+                false,
+                Err(FALLBACK_NO_PASSWORD),
+            ),
+            (KeystoreFallback, true, false, true, Ok("private_key")),
+            (KeystoreFallback, true, true, false, Ok("keystore")),
+            (KeystoreFallback, true, true, true, Ok("private_key")),
+        ];
+
+        for (mode, keystore, password, private_key, expected) in cases {
+            let mut avs = Config::load(None).unwrap().avs;
+            avs.wallet_mode = mode;
+            avs.lagr_keystore = keystore.then(|| "lagr_keystore.json".to_string());
+            avs.lagr_pwd = password.then(|| Secret::new("password".to_string()));
+            avs.lagr_private_key = private_key.then(|| Secret::new("0x01".to_string()));
+
+            let key = avs.wallet_key().map(|key| {
+                match key {
+                    WalletKey::Keystore { .. } => "keystore",
+                    WalletKey::PrivateKey(_) => "private_key",
+                }
+            });
+            assert_eq!(
+                key.as_ref().map_err(String::as_str),
+                expected.as_ref(),
+                "{mode:?}, keystore: {keystore}, password: {password}, private key: {private_key}"
+            );
+        }
+    }
+}
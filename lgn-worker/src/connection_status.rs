@@ -0,0 +1,97 @@
+//! The state of the reconnection to the gateway, served on `/status` for on-call to tell why a
+//! worker is not connected without going through its logs.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct ConnectionState {
+    /// Whether a gateway stream is open.
+    connected: bool,
+    /// How many connection attempts were made since the last stream was open.
+    attempts: u32,
+    /// How long the worker waits before its next connection attempt, in milliseconds, `null`
+    /// while connected or connecting.
+    backoff_ms: Option<u64>,
+    /// Why the last connection attempt failed or the last stream was lost, `null` until then.
+    last_error: Option<String>,
+}
+
+#[derive(Default)]
+pub(crate) struct ConnectionStatus {
+    state: Mutex<ConnectionState>,
+}
+
+impl ConnectionStatus {
+    pub(crate) fn state(&self) -> ConnectionState {
+        self.lock().clone()
+    }
+
+    /// Record a connection attempt.
+    pub(crate) fn attempt(&self) {
+        let mut state = self.lock();
+        state.attempts = state.attempts.saturating_add(1);
+        state.backoff_ms = None;
+    }
+
+    /// Record that a stream is open.
+    pub(crate) fn connected(&self) {
+        let mut state = self.lock();
+        state.connected = true;
+        state.attempts = 0;
+        state.backoff_ms = None;
+    }
+
+    /// Record a failed connection attempt or a lost stream, the next attempt being made after
+    /// `backoff`.
+    pub(crate) fn failed(
+        &self,
+        error: String,
+        backoff: Duration,
+    ) {
+        let mut state = self.lock();
+        state.connected = false;
+        state.backoff_ms = Some(backoff.as_millis() as u64);
+        state.last_error = Some(error);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ConnectionState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_status() {
+        let status = ConnectionStatus::default();
+        status.attempt();
+        status.failed("connection refused".to_string(), Duration::from_secs(5));
+        status.attempt();
+        assert_eq!(
+            status.state(),
+            ConnectionState {
+                connected: false,
+                attempts: 2,
+                backoff_ms: None,
+                last_error: Some("connection refused".to_string()),
+            }
+        );
+
+        status.connected();
+        status.failed("stream closed".to_string(), Duration::from_millis(500));
+        assert_eq!(
+            status.state(),
+            ConnectionState {
+                connected: false,
+                attempts: 0,
+                backoff_ms: Some(500),
+                last_error: Some("stream closed".to_string()),
+            }
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Copies of the tasks which failed, to be replayed with `--tasks-dir`.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use metrics::counter;
+use tracing::info;
+
+use crate::config::FailedTasksConfig;
+
+/// Writes the payload of every failed task to a directory, along with the error, retaining only
+/// the most recent ones.
+pub(crate) struct FailedTasks {
+    dir: PathBuf,
+    max_records: usize,
+    /// The task files, from the oldest to the most recent.
+    records: VecDeque<PathBuf>,
+}
+
+impl FailedTasks {
+    pub(crate) fn new(config: &FailedTasksConfig) -> Result<Self> {
+        let dir = PathBuf::from(&config.dir);
+        std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+
+        // Pick up the records of the previous runs, for them to count against the limit.
+        let mut records = std::fs::read_dir(&dir)
+            .with_context(|| format!("listing {}", dir.display()))?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let path = entry.path();
+                if path.extension()? != "json" {
+                    return None;
+                }
+                Some((entry.metadata().ok()?.modified().ok()?, path))
+            })
+            .collect::<Vec<_>>();
+        records.sort();
+
+        let mut failed_tasks = Self {
+            dir,
+            max_records: config.max_records,
+            records: records.into_iter().map(|(_, path)| path).collect(),
+        };
+        failed_tasks.evict();
+        Ok(failed_tasks)
+    }
+
+    /// Record the failure of the task `name` with `payload` on `error`.
+    pub(crate) fn record(
+        &mut self,
+        name: &str,
+        payload: &[u8],
+        error: &str,
+    ) -> Result<()> {
+        let class = classify(error);
+        // Directory tasks are named after their path.
+        let name = name.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_");
+        let task_path = self.dir.join(format!("{name}-{class}.json"));
+
+        std::fs::write(&task_path, payload)
+            .with_context(|| format!("writing {}", task_path.display()))?;
+        let error_path = task_path.with_extension("error");
+        std::fs::write(&error_path, format!("class: {class}\nerror: {error}\n"))
+            .with_context(|| format!("writing {}", error_path.display()))?;
+        info!("failed task recorded to {}", task_path.display());
+        counter!("zkmr_worker_failed_tasks_recorded_total", "class" => class).increment(1);
+
+        self.records.retain(|path| *path != task_path);
+        self.records.push_back(task_path);
+        self.evict();
+        Ok(())
+    }
+
+    /// Delete the oldest records beyond the limit.
+    fn evict(&mut self) {
+        while self.records.len() > self.max_records {
+            if let Some(oldest) = self.records.pop_front() {
+                remove_record(&oldest);
+            }
+        }
+    }
+}
+
+fn remove_record(task_path: &Path) {
+    let _ = std::fs::remove_file(task_path);
+    let _ = std::fs::remove_file(task_path.with_extension("error"));
+}
+
+/// Coarse cause of a task failure, from its error message.
+pub(crate) fn classify(error: &str) -> &'static str {
+    if error.contains("panic") {
+        "panic"
+    } else if error.contains("deserializ") {
+        "invalid_task"
+    } else if error.contains("version mismatch") || error.contains("unsupported message class") {
+        "version_mismatch"
+    } else if error.contains("is not served by this worker") || error.contains("already in flight")
+    {
+        "rejected"
+    } else if error.contains("unsupported:") {
+        "unsupported"
+    } else {
+        "proving"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_tasks_limit() {
+        let dir = std::env::temp_dir().join(format!("failed-tasks-{}", std::process::id()));
+        let config = FailedTasksConfig {
+            dir: dir.display().to_string(),
+            max_records: 2,
+        };
+
+        let mut failed_tasks = FailedTasks::new(&config).unwrap();
+        failed_tasks.record("a", b"{}", "boom").unwrap();
+        failed_tasks
+            .record("b", b"{}", "panic while proving")
+            .unwrap();
+        failed_tasks
+            .record("c", b"{}", "failed to deserialize")
+            .unwrap();
+
+        let mut files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                "b-panic.error",
+                "b-panic.json",
+                "c-invalid_task.error",
+                "c-invalid_task.json"
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("b-panic.error")).unwrap(),
+            "class: panic\nerror: panic while proving\n"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+//! CPU profiles of proofs, captured on demand and rendered as flamegraphs, see `flamegraph`.
+//!
+//! A capture is requested with `POST /flamegraph` on the health server and covers the next task
+//! proven. Only one capture is requested or running at a time, for profiling never to outlast the
+//! investigation it was requested for.
+
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+
+use anyhow::Context;
+use anyhow::Result;
+use metrics::counter;
+use tracing::info;
+use tracing::warn;
+
+use crate::config::FlamegraphConfig;
+
+/// No capture is requested.
+const IDLE: u8 = 0;
+/// The next proof is to be captured.
+const REQUESTED: u8 = 1;
+/// A proof is being captured.
+const CAPTURING: u8 = 2;
+
+pub(crate) struct Flamegraphs {
+    dir: PathBuf,
+    /// How many times per second the stacks are sampled.
+    frequency: i32,
+    state: AtomicU8,
+}
+
+impl Flamegraphs {
+    pub(crate) fn new(config: &FlamegraphConfig) -> Result<Self> {
+        let dir = PathBuf::from(&config.dir);
+        std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            frequency: config.frequency,
+            state: AtomicU8::new(IDLE),
+        })
+    }
+
+    /// Request the capture of the next proof, unless a capture is already requested or running.
+    pub(crate) fn request(&self) -> bool {
+        self.state
+            .compare_exchange(IDLE, REQUESTED, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+
+    /// Start capturing the proof about to start, if requested.
+    pub(crate) fn start(&self) -> Option<Capture<'_>> {
+        self.state
+            .compare_exchange(REQUESTED, CAPTURING, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()?;
+        match profiler::start(self.frequency) {
+            Ok(profiler) => {
+                Some(Capture {
+                    flamegraphs: self,
+                    profiler,
+                })
+            },
+            Err(e) => {
+                warn!("starting flamegraph capture: {e:?}");
+                self.state.store(IDLE, Ordering::SeqCst);
+                None
+            },
+        }
+    }
+}
+
+/// A proof being captured.
+pub(crate) struct Capture<'a> {
+    flamegraphs: &'a Flamegraphs,
+    profiler: profiler::Profiler,
+}
+
+impl Capture<'_> {
+    /// Write the flamegraph of the proof of `task`, named after it.
+    pub(crate) fn finish(
+        self,
+        task: &str,
+    ) {
+        let name = task.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_");
+        let path = self.flamegraphs.dir.join(format!("{name}.svg"));
+        match profiler::write(self.profiler, &path) {
+            Ok(()) => {
+                info!("flamegraph of task {task} written to {}", path.display());
+                counter!("zkmr_worker_flamegraphs_total").increment(1);
+            },
+            Err(e) => warn!("writing flamegraph of task {task}: {e:?}"),
+        }
+        self.flamegraphs.state.store(IDLE, Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "flamegraph")]
+mod profiler {
+    use std::path::Path;
+
+    use anyhow::Context;
+    use anyhow::Result;
+
+    pub(super) type Profiler = pprof::ProfilerGuard<'static>;
+
+    pub(super) fn start(frequency: i32) -> Result<Profiler> {
+        pprof::ProfilerGuardBuilder::default()
+            .frequency(frequency)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+            .context("starting the profiler")
+    }
+
+    pub(super) fn write(
+        profiler: Profiler,
+        path: &Path,
+    ) -> Result<()> {
+        let report = profiler
+            .report()
+            .build()
+            .context("building the profile report")?;
+        let file =
+            std::fs::File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        report.flamegraph(file).context("rendering the flamegraph")
+    }
+}
+
+/// Without the `flamegraph` feature, which the configuration requires for captures to be
+/// requested, nothing is ever captured.
+#[cfg(not(feature = "flamegraph"))]
+mod profiler {
+    use std::path::Path;
+
+    use anyhow::bail;
+    use anyhow::Result;
+
+    pub(super) enum Profiler {}
+
+    pub(super) fn start(_frequency: i32) -> Result<Profiler> {
+        bail!("the worker is built without the `flamegraph` feature")
+    }
+
+    pub(super) fn write(
+        profiler: Profiler,
+        _path: &Path,
+    ) -> Result<()> {
+        match profiler {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_capture() {
+        let dir = std::env::temp_dir().join(format!("flamegraphs-{}", std::process::id()));
+        let flamegraphs = Flamegraphs::new(&FlamegraphConfig {
+            dir: dir.display().to_string(),
+            frequency: 99,
+        })
+        .unwrap();
+
+        assert!(flamegraphs.start().is_none());
+        assert!(flamegraphs.request());
+        // Only one capture at a time.
+        assert!(!flamegraphs.request());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,352 @@
+//! Proving in a child process, so that a prover crash or OOM does not bring down the gateway
+//! connection and the health server along with it.
+//!
+//! The worker and its prover process exchange length-prefixed JSON frames over the stdin and
+//! stdout of the latter, which therefore logs to stderr.
+
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::process::Child;
+use std::process::ChildStdin;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use lgn_messages::types::MessageEnvelope;
+use lgn_messages::types::MessageReplyEnvelope;
+use lgn_messages::types::ReplyType;
+use lgn_messages::types::TaskType;
+use metrics::counter;
+use tracing::info;
+use tracing::warn;
+
+use crate::config::ProofCompressionConfig;
+use crate::config::ProofEncodingConfig;
+use crate::manager::ProversManager;
+use crate::PanicPolicy;
+
+/// The largest frame exchanged with a prover process. Larger than the gateway messages, as the
+/// tasks are sent with their referenced proofs resolved, and the replies before their proofs are
+/// offloaded.
+const MAX_PROCESS_FRAME_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Proves tasks in a child process, restarting it after any failure.
+pub(crate) struct IsolatedProver {
+    /// The arguments to run the worker executable with to start a prover process.
+    args: Vec<String>,
+    process: Option<ProverProcess>,
+    /// How long the prover process may keep proving a task past its timeout, see
+    /// [`IsolatedProver::late_reply`].
+    late_proof_grace: Option<Duration>,
+    /// Whether the prover process is still proving a task which exceeded its timeout.
+    proving_late: bool,
+}
+
+struct ProverProcess {
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    /// The frames sent by the process, read from its stdout by a dedicated thread for them to be
+    /// waited for with a timeout.
+    frames: mpsc::Receiver<std::io::Result<Vec<u8>>>,
+}
+
+impl IsolatedProver {
+    /// Start a prover process, waiting for its provers to be ready.
+    pub(crate) fn new(
+        args: Vec<String>,
+        late_proof_grace: Option<Duration>,
+    ) -> Result<Self> {
+        let mut prover = Self {
+            args,
+            process: None,
+            late_proof_grace,
+            proving_late: false,
+        };
+        prover.process()?;
+        Ok(prover)
+    }
+
+    /// Prove `envelope`, killing the prover process if it takes longer than `timeout`, unless
+    /// late proofs are awaited.
+    pub(crate) fn prove(
+        &mut self,
+        envelope: &MessageEnvelope<TaskType>,
+        timeout: Option<Duration>,
+    ) -> Result<MessageReplyEnvelope<ReplyType>, String> {
+        if self.proving_late {
+            // The late proof was not awaited, the process is still busy with it.
+            self.proving_late = false;
+            self.stop();
+        }
+        match self
+            .process()
+            .and_then(|process| process.prove(envelope, timeout))
+        {
+            Ok(Some(reply)) => reply,
+            Ok(None) => {
+                let timeout = timeout.unwrap_or_default();
+                counter!("zkmr_worker_tasks_deadline_exceeded_total", "stage" => "proving")
+                    .increment(1);
+                if self.late_proof_grace.is_some() {
+                    self.proving_late = true;
+                    Err(format!("proving exceeded its {timeout:?} timeout"))
+                } else {
+                    self.stop();
+                    Err(format!(
+                        "proving exceeded its {timeout:?} timeout, the prover process was killed"
+                    ))
+                }
+            },
+            Err(e) => {
+                // Whatever happened, the state of the child is unknown: the next task gets a
+                // fresh one.
+                self.stop();
+                counter!("zkmr_worker_prover_process_failures_total").increment(1);
+                Err(format!("prover process failed: {e:?}"))
+            },
+        }
+    }
+
+    /// Wait for the proof of the task which has just exceeded its timeout, for at most the late
+    /// proof grace, killing the prover process if it does not come.
+    pub(crate) fn late_reply(&mut self) -> Option<MessageReplyEnvelope<ReplyType>> {
+        if !std::mem::take(&mut self.proving_late) {
+            return None;
+        }
+        let grace = self.late_proof_grace?;
+        let reply = self.process.as_mut()?.read_reply_within(grace);
+        match reply {
+            Some(Ok(Ok(reply))) => Some(reply),
+            Some(Ok(Err(e))) => {
+                warn!("late proving failed: {e}");
+                None
+            },
+            Some(Err(e)) => {
+                warn!("reading the late reply of the prover process: {e:?}");
+                self.stop();
+                None
+            },
+            None => {
+                self.stop();
+                None
+            },
+        }
+    }
+
+    /// Replace the prover process with a new one, e.g. to pick up new params.
+    pub(crate) fn restart(&mut self) -> Result<()> {
+        self.stop();
+        self.process()?;
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            let _ = process.child.kill();
+            match process.child.wait() {
+                Ok(status) => warn!("prover process exited: {status}"),
+                Err(e) => warn!("waiting for the prover process: {e}"),
+            }
+        }
+    }
+
+    fn process(&mut self) -> Result<&mut ProverProcess> {
+        let process = match self.process.take() {
+            // A prover process exits by itself after a fatal panic.
+            Some(mut process) if process.child.try_wait()?.is_some() => {
+                info!("prover process exited, restarting it");
+                ProverProcess::spawn(&self.args)?
+            },
+            Some(process) => process,
+            None => ProverProcess::spawn(&self.args)?,
+        };
+        Ok(self.process.insert(process))
+    }
+}
+
+impl ProverProcess {
+    fn spawn(args: &[String]) -> Result<Self> {
+        let executable = std::env::current_exe().context("locating the worker executable")?;
+        let mut child = Command::new(executable)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("spawning prover process")?;
+        let stdin = child.stdin.take().context("prover process without stdin")?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("prover process without stdout")?;
+
+        let (frames_tx, frames) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("prover-process-reader".to_string())
+            .spawn(move || {
+                let mut stdout = BufReader::new(stdout);
+                // Until the process exits, closing its stdout.
+                loop {
+                    let frame = read_frame(&mut stdout, MAX_PROCESS_FRAME_SIZE);
+                    let failed = frame.is_err();
+                    if frames_tx.send(frame).is_err() || failed {
+                        break;
+                    }
+                }
+            })
+            .context("spawning prover process reader")?;
+
+        let process = Self {
+            child,
+            stdin: BufWriter::new(stdin),
+            frames,
+        };
+        // The prover process sends an empty frame once its provers are initialized.
+        process
+            .read_frame()
+            .context("waiting for the prover process to start")?;
+        info!("prover process {} ready", process.child.id());
+
+        Ok(process)
+    }
+
+    /// Prove `envelope`, `None` if its reply does not come within `timeout`.
+    fn prove(
+        &mut self,
+        envelope: &MessageEnvelope<TaskType>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Result<MessageReplyEnvelope<ReplyType>, String>>> {
+        let task = serde_json::to_vec(envelope).context("serializing task")?;
+        write_frame(&mut self.stdin, &task).context("sending task to the prover process")?;
+        match timeout {
+            Some(timeout) => self.read_reply_within(timeout).transpose(),
+            None => self.read_reply().map(Some),
+        }
+    }
+
+    fn read_reply(&self) -> Result<Result<MessageReplyEnvelope<ReplyType>, String>> {
+        parse_reply(self.read_frame())
+    }
+
+    /// Read the reply of the process, `None` if it does not come within `timeout`.
+    fn read_reply_within(
+        &self,
+        timeout: Duration,
+    ) -> Option<Result<Result<MessageReplyEnvelope<ReplyType>, String>>> {
+        let frame = match self.frames.recv_timeout(timeout) {
+            Ok(frame) => frame,
+            Err(mpsc::RecvTimeoutError::Timeout) => return None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(ErrorKind::UnexpectedEof.into()),
+        };
+        Some(parse_reply(frame))
+    }
+
+    fn read_frame(&self) -> std::io::Result<Vec<u8>> {
+        self.frames
+            .recv()
+            .unwrap_or_else(|_| Err(ErrorKind::UnexpectedEof.into()))
+    }
+}
+
+fn parse_reply(
+    frame: std::io::Result<Vec<u8>>
+) -> Result<Result<MessageReplyEnvelope<ReplyType>, String>> {
+    let reply = frame.context("reading reply of the prover process")?;
+    serde_json::from_slice(&reply).context("deserializing reply of the prover process")
+}
+
+/// Serve the tasks sent by the parent worker over stdin until it closes it.
+pub(crate) fn serve_prover_process(
+    provers_manager: &ProversManager<TaskType, ReplyType>,
+    mp2_requirement: &semver::VersionReq,
+    panic_policy: &PanicPolicy,
+    proof_encoding: &ProofEncodingConfig,
+    proof_compression: &ProofCompressionConfig,
+    measure_resources: bool,
+    content_hash: bool,
+) -> Result<()> {
+    let mut stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+    write_frame(&mut stdout, &[])?;
+
+    loop {
+        let task = match read_frame(&mut stdin, MAX_PROCESS_FRAME_SIZE) {
+            Ok(task) => task,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e).context("reading task"),
+        };
+        let reply = serde_json::from_slice::<MessageEnvelope<TaskType>>(&task)
+            .map_err(|e| format!("deserializing task in the prover process: {e}"))
+            .and_then(|envelope| {
+                crate::process_downstream_payload(
+                    provers_manager,
+                    envelope,
+                    mp2_requirement,
+                    panic_policy,
+                    proof_encoding,
+                    proof_compression,
+                    measure_resources,
+                    content_hash,
+                )
+            });
+        write_frame(&mut stdout, &serde_json::to_vec(&reply)?).context("sending reply")?;
+        if panic_policy.tripped() {
+            bail!("exiting after a fatal panic");
+        }
+    }
+}
+
+/// Write `data` as a frame, prefixed with its length as a little-endian `u64`.
+pub(crate) fn write_frame(
+    writer: &mut impl Write,
+    data: &[u8],
+) -> std::io::Result<()> {
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    writer.write_all(data)?;
+    writer.flush()
+}
+
+/// Read a frame written by [`write_frame`], failing with [`ErrorKind::InvalidData`] if it is
+/// longer than `max_len` rather than trusting a length prefix which may be garbage.
+pub(crate) fn read_frame(
+    reader: &mut impl Read,
+    max_len: usize,
+) -> std::io::Result<Vec<u8>> {
+    let mut len = [0; 8];
+    reader.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    if len > max_len as u64 {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("frame of {len}B, over the {max_len}B limit"),
+        ));
+    }
+    let mut data = vec![0; len as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_frame() {
+        let mut frames = vec![];
+        write_frame(&mut frames, b"task").unwrap();
+        assert_eq!(read_frame(&mut frames.as_slice(), 4).unwrap(), b"task");
+
+        let err = read_frame(&mut frames.as_slice(), 3).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // JSON piped without a length prefix.
+        let err = read_frame(&mut br#"{"query_id": "q"}"#.as_slice(), 1024).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,136 @@
+//! Rate limiting of the per-task error logs, see `log_rate_limit`.
+//!
+//! Up to `burst` occurrences of the same message are logged per `window`, the others being
+//! collapsed into a single summary logged once the window is over, for a storm of bad tasks not to
+//! flood the logs.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use metrics::counter;
+use tracing::error;
+
+use crate::config::LogRateLimitConfig;
+
+pub(crate) struct LogLimiter {
+    window: Duration,
+    burst: usize,
+    /// The occurrences of each message within its current window.
+    messages: Mutex<HashMap<String, Occurrences>>,
+}
+
+struct Occurrences {
+    /// When the window started, at the first occurrence of the message.
+    since: Instant,
+    logged: usize,
+    suppressed: usize,
+}
+
+impl LogLimiter {
+    pub(crate) fn new(config: &LogRateLimitConfig) -> Self {
+        Self {
+            window: Duration::from_secs(config.window),
+            burst: config.burst,
+            messages: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How often [`LogLimiter::flush`] is to be called.
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Whether to log an occurrence of `message` at `now`, or to only count it towards the
+    /// summary of its window.
+    pub(crate) fn allow(
+        &self,
+        message: &str,
+        now: Instant,
+    ) -> bool {
+        let mut messages = self.messages.lock().unwrap_or_else(|e| e.into_inner());
+        let occurrences = messages.entry(message.to_string()).or_insert_with(|| {
+            Occurrences {
+                since: now,
+                logged: 0,
+                suppressed: 0,
+            }
+        });
+        if now.duration_since(occurrences.since) >= self.window {
+            self.summarize(message, occurrences);
+            *occurrences = Occurrences {
+                since: now,
+                logged: 0,
+                suppressed: 0,
+            };
+        }
+
+        if occurrences.logged < self.burst {
+            occurrences.logged += 1;
+            true
+        } else {
+            occurrences.suppressed += 1;
+            counter!("zkmr_worker_logs_suppressed_total").increment(1);
+            false
+        }
+    }
+
+    /// Log the summary of the messages whose window is over at `now`.
+    pub(crate) fn flush(
+        &self,
+        now: Instant,
+    ) {
+        let mut messages = self.messages.lock().unwrap_or_else(|e| e.into_inner());
+        messages.retain(|message, occurrences| {
+            let over = now.duration_since(occurrences.since) >= self.window;
+            if over {
+                self.summarize(message, occurrences);
+            }
+            !over
+        });
+    }
+
+    fn summarize(
+        &self,
+        message: &str,
+        occurrences: &Occurrences,
+    ) {
+        if occurrences.suppressed > 0 {
+            error!(
+                "{} similar errors in the last {}s: {message}",
+                occurrences.suppressed,
+                self.window.as_secs()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_limiter() {
+        let limiter = LogLimiter::new(&LogRateLimitConfig {
+            window: 10,
+            burst: 2,
+        });
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(limiter.allow("boom", at(0)));
+        assert!(limiter.allow("boom", at(1)));
+        assert!(!limiter.allow("boom", at(2)));
+        // Other messages have a budget of their own.
+        assert!(limiter.allow("bang", at(3)));
+
+        // A new window starts once the previous one is over.
+        limiter.flush(at(5));
+        assert!(!limiter.allow("boom", at(9)));
+        assert!(limiter.allow("boom", at(10)));
+
+        limiter.flush(at(25));
+        assert!(limiter.messages.lock().unwrap().is_empty());
+    }
+}
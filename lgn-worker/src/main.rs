@@ -1,66 +1,188 @@
 use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::panic;
+use std::path::Path;
+use std::path::PathBuf;
 use std::result::Result::Ok;
 use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::Instant;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
 use anyhow::*;
+use arc_swap::ArcSwapOption;
 use backtrace::Backtrace;
 use checksum::fetch_checksums;
 use clap::Parser;
+use clap::Subcommand;
 use ethers::signers::Wallet;
 use jwt::Claims;
 use jwt::RegisteredClaims;
 use k256::ecdsa::SigningKey;
-use lagrange::worker_done::Reply;
-use lagrange::WorkerDone;
 use lagrange::WorkerToGwRequest;
-use lagrange::WorkerToGwResponse;
 use lgn_auth::jwt::JWTAuth;
 use lgn_messages::types::MessageEnvelope;
 use lgn_messages::types::MessageReplyEnvelope;
+use lgn_messages::types::ProverType;
 use lgn_messages::types::ReplyType;
 use lgn_messages::types::TaskType;
+use lgn_messages::types::ToProverType;
+use lgn_messages::types::WorkerError;
+use lgn_messages::TableId;
+use lgn_provers::provers::WarmHint;
+use lgn_worker::avs::public_key::PublicKey;
 use lgn_worker::avs::utils::read_keystore;
 use metrics::counter;
+use metrics::gauge;
+use metrics::histogram;
+use metrics_exporter_prometheus::BuildError;
+use metrics_exporter_prometheus::ExporterFuture;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use metrics_exporter_prometheus::PrometheusHandle;
+use metrics_exporter_prometheus::PrometheusRecorder;
+use metrics_util::layers::Layer;
+use metrics_util::layers::PrefixLayer;
 use mimalloc::MiMalloc;
-use tokio_stream::StreamExt;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::value::RawValue;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinSet;
+use tonic::metadata::Ascii;
 use tonic::metadata::MetadataValue;
+use tonic::transport::Certificate;
 use tonic::transport::ClientTlsConfig;
 use tonic::Request;
 use tracing::debug;
 use tracing::error;
 use tracing::info;
+use tracing::info_span;
 use tracing::level_filters::LevelFilter;
 use tracing::span;
 use tracing::trace;
+use tracing::warn;
+use tracing::Instrument;
 use tracing::Level;
+use tracing::Span;
+use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::EnvFilter;
 use warp::Filter;
 
+use crate::audit::AuditEvent;
+use crate::audit::AuditLog;
+use crate::backoff::Backoff;
+use crate::bench::BenchSink;
+use crate::canonical::content_hash;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::circuit_breaker::CircuitState;
+use crate::config::BacktraceVerbosity;
 use crate::config::Config;
+use crate::config::LogFormat;
+use crate::config::LoggingConfig;
+use crate::config::OnBindFailure;
+use crate::config::PrometheusConfig;
+use crate::config::ProofCompressionConfig;
+use crate::config::ProofEncodingConfig;
+use crate::config::WalletKey;
+use crate::connection_status::ConnectionState;
+use crate::connection_status::ConnectionStatus;
+use crate::failed_tasks::classify;
+use crate::failed_tasks::FailedTasks;
+use crate::flamegraph::Flamegraphs;
+use crate::isolated::serve_prover_process;
+use crate::isolated::IsolatedProver;
+use crate::log_limiter::LogLimiter;
 use crate::manager::v1::register_v1_provers;
 use crate::manager::ProversManager;
+use crate::manager::ProversReadiness;
+use crate::message_class::check_message_class;
+use crate::params_status::ParamsStatus;
+use crate::proof_store::resolve_proof_references;
+use crate::proof_store::ProofStore;
+use crate::resource_usage::ResourceSampler;
+use crate::result_cache::ResultCache;
+use crate::saturation::Saturation;
+use crate::shutdown::Shutdown;
+use crate::success_ratio::SuccessRatio;
+use crate::tenants::Tenants;
+use crate::transport::DirectorySink;
+use crate::transport::DirectorySource;
+use crate::transport::GatewaySink;
+use crate::transport::GatewaySource;
+use crate::transport::InFlightIds;
+use crate::transport::ResultSink;
+use crate::transport::StdioSink;
+use crate::transport::StdioSource;
+use crate::transport::Task;
+use crate::transport::TaskSource;
 
 pub mod lagrange {
     tonic::include_proto!("lagrange");
 }
 
+mod audit;
+mod backoff;
+mod bench;
+mod canonical;
 mod checksum;
+mod circuit_breaker;
 mod config;
+mod connection_status;
+mod failed_tasks;
+mod flamegraph;
+mod isolated;
+mod log_limiter;
 mod manager;
+mod message_class;
+mod metrics_snapshot;
+mod params_status;
+mod proof_store;
+mod resource_usage;
+mod result_cache;
+mod saturation;
+mod shutdown;
+mod success_ratio;
+mod tenants;
+mod transport;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
 const MAX_GRPC_MESSAGE_SIZE_MB: usize = 16;
 
+/// The port of the health server.
+const HEALTH_PORT: u16 = 8080;
+
+/// How soon a failed refresh of the gateway token is retried, at most.
+const TOKEN_REFRESH_RETRY: Duration = Duration::from_secs(30);
+
+/// How many of the innermost frames a short backtrace keeps, the panic machinery included.
+const SHORT_BACKTRACE_FRAMES: usize = 32;
+
+/// How many tasks are being proven, for the panic hook to tell the panics of tasks apart. A count
+/// rather than a flag of the proving thread, as a task may be proven over several threads.
+static PROVING: AtomicUsize = AtomicUsize::new(0);
+
+/// The backtrace verbosity of the panics while proving a task, see `worker.task_panic_backtrace`.
+static TASK_PANIC_BACKTRACE: OnceLock<BacktraceVerbosity> = OnceLock::new();
+
+/// The rate limiter of the error logs of the tasks, see `log_rate_limit`.
+static TASK_LOG_LIMITER: OnceLock<LogLimiter> = OnceLock::new();
+
 #[derive(Parser, Clone, Debug)]
 struct Cli {
     /// Path to the configuration file.
@@ -70,21 +192,95 @@ struct Cli {
     /// If set, output logs in JSON format.
     #[clap(short, long, action)]
     json: bool,
+
+    /// Prove the tasks stored as JSON files in this directory instead of connecting to the
+    /// gateway.
+    #[clap(long)]
+    tasks_dir: Option<String>,
+
+    /// Where to write the replies to the tasks of `--tasks-dir`.
+    #[clap(long, requires = "tasks_dir")]
+    replies_dir: Option<String>,
+
+    /// Prove the task envelopes read from stdin and write their replies to stdout, both as JSON
+    /// frames prefixed with their length as a little-endian `u64`, instead of connecting to the
+    /// gateway; logs go to stderr. The tasks which fail are replied to with an `error` and an
+    /// `error_code`. A frame larger than `avs.max_grpc_message_size_mb` stops the worker with an
+    /// error.
+    #[clap(long, conflicts_with = "tasks_dir")]
+    stdio: bool,
+
+    /// Check that the worker could start, without connecting to the gateway: the configuration is
+    /// valid, the wallet is decrypted and signs the token, and the params are downloaded and match
+    /// their checksums. Prints a summary and exits; the lazily loaded provers are left out.
+    #[clap(long, conflicts_with_all = ["tasks_dir", "stdio"])]
+    dry_run: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Prove the tasks stored as JSON files in a directory, print a summary of the outcomes,
+    /// throughput and latency percentiles per prover and exit with an error if any task failed.
+    Bench {
+        /// The directory holding the tasks.
+        tasks_dir: String,
+    },
+    /// Check the configuration, listing every problem found, and exit.
+    ValidateConfig,
+    /// Print an example configuration, documenting every setting along with its default value,
+    /// and exit.
+    ExampleConfig,
+    /// Prove the tasks sent by a parent worker, see `worker.isolated_proving`.
+    #[clap(hide = true)]
+    ProveChild,
 }
 
-fn setup_logging(json: bool) {
-    if json {
+/// Set up the logs as configured by `config`, in JSON regardless if `json`, to stderr rather than
+/// stdout if `to_stderr`. The guard returned, if any, flushes the logs written to a file once
+/// dropped, and is to be kept for the lifetime of the process.
+fn setup_logging(
+    config: &LoggingConfig,
+    json: bool,
+    to_stderr: bool,
+) -> Result<Option<WorkerGuard>> {
+    let (writer, guard) = match &config.file {
+        Some(file) => {
+            let path = Path::new(file);
+            let dir = path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            let file_name = path
+                .file_name()
+                .with_context(|| format!("log file `{file}` is not a file path"))?;
+            let (writer, guard) =
+                tracing_appender::non_blocking(tracing_appender::rolling::daily(dir, file_name));
+            (BoxMakeWriter::new(writer), Some(guard))
+        },
+        None if to_stderr => (BoxMakeWriter::new(std::io::stderr), None),
+        None => (BoxMakeWriter::new(std::io::stdout), None),
+    };
+    let filter = EnvFilter::builder().with_default_directive(LevelFilter::INFO.into());
+    let filter = match &config.directives {
+        Some(directives) if std::env::var_os(EnvFilter::DEFAULT_ENV).is_none() => {
+            filter
+                .parse(directives)
+                .with_context(|| format!("parsing log directives `{directives}`"))?
+        },
+        _ => filter.from_env_lossy(),
+    };
+    if json || config.format == LogFormat::Json {
         let subscriber = tracing_subscriber::fmt()
             .json()
+            .with_writer(writer)
             .with_level(true)
             .with_file(true)
             .with_line_number(true)
             .with_target(true)
-            .with_env_filter(
-                EnvFilter::builder()
-                    .with_default_directive(LevelFilter::INFO.into())
-                    .from_env_lossy(),
-            )
+            .with_env_filter(filter)
             .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
             .finish();
         tracing::subscriber::set_global_default(subscriber).expect("Setting up logging failed");
@@ -92,27 +288,40 @@ fn setup_logging(json: bool) {
         let subscriber = tracing_subscriber::fmt()
             .pretty()
             .compact()
+            .with_writer(writer)
             .with_level(true)
             .with_file(true)
             .with_line_number(true)
             .with_target(true)
-            .with_env_filter(
-                EnvFilter::builder()
-                    .with_default_directive(LevelFilter::INFO.into())
-                    .from_env_lossy(),
-            )
+            .with_env_filter(filter)
             .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
             .finish();
         tracing::subscriber::set_global_default(subscriber).expect("Setting up logging failed");
     };
+    Ok(guard)
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
-    setup_logging(cli.json);
+    if let Some(Command::ExampleConfig) = &cli.command {
+        // Before any log line, for the output to be a valid configuration file.
+        print!("{}", crate::config::example());
+        return Ok(());
+    }
+    let config = Config::load(cli.config.clone()).context("loading the configuration")?;
+    // The stdout of a prover process is reserved to talk to its worker, and that of a stdio
+    // worker to the replies.
+    let _log_guard = setup_logging(
+        &config.logging,
+        cli.json,
+        cli.stdio || matches!(cli.command, Some(Command::ProveChild)),
+    )?;
+    if let Some(config_file) = &cli.config {
+        debug!("Loaded local configuration from {config_file}");
+    }
 
-    let mp2_version = semver::Version::parse(verifiable_db::version())?;
+    let mp2_version = parse_mp2_version(verifiable_db::version())?;
     let mp2_requirement = semver::VersionReq::parse(&format!("^{mp2_version}"))?;
 
     info!("Running MR2 version {mp2_version} - requiring {mp2_requirement}");
@@ -132,35 +341,67 @@ async fn main() -> anyhow::Result<()> {
             None => ("<unknown>", 0, 0),
         };
 
-        error!(
-            msg,
-            file,
-            lineno,
-            col,
-            "Panic occurred: {:?}",
-            Backtrace::new(),
-        );
+        let verbosity = if PROVING.load(Ordering::Relaxed) > 0 {
+            TASK_PANIC_BACKTRACE.get().copied().unwrap_or_default()
+        } else {
+            BacktraceVerbosity::Full
+        };
+        match capture_backtrace(verbosity) {
+            Some(backtrace) => error!(msg, file, lineno, col, "Panic occurred: {backtrace:?}"),
+            None => error!(msg, file, lineno, col, "Panic occurred"),
+        }
     }));
 
     let last_task_processed =
         AtomicU64::new(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
 
-    if let Err(err) = run(cli, mp2_requirement, last_task_processed).await {
+    if let Err(err) = run(cli, config, mp2_requirement, last_task_processed).await {
         panic!("Worker exited due to an error: {err:?}")
     } else {
         Ok(())
     }
 }
 
+/// Parse the version reported by the linked `verifiable_db`.
+fn parse_mp2_version(version: &str) -> Result<semver::Version> {
+    semver::Version::parse(version).with_context(|| {
+        format!("the linked verifiable_db reports an unparseable version: `{version}`")
+    })
+}
+
 async fn run(
     cli: Cli,
+    config: Config,
     mp2_requirement: semver::VersionReq,
     last_task_processed: AtomicU64,
 ) -> Result<()> {
     let version = env!("CARGO_PKG_VERSION");
     info!("Starting worker. version: {}", version);
-    let config = Config::load(cli.config);
-    config.validate();
+    config.validate().context("invalid configuration")?;
+    let _ = TASK_PANIC_BACKTRACE.set(config.worker.task_panic_backtrace);
+    if let Some(Command::ValidateConfig) = &cli.command {
+        println!("the configuration is valid");
+        return Ok(());
+    }
+    if let Some(log_rate_limit) = &config.log_rate_limit {
+        let limiter = TASK_LOG_LIMITER.get_or_init(|| LogLimiter::new(log_rate_limit));
+        // Summarizes the errors of a storm once it is over, even if no error follows.
+        std::thread::Builder::new()
+            .name("log-limiter".to_string())
+            .spawn(move || {
+                loop {
+                    std::thread::sleep(limiter.window());
+                    limiter.flush(Instant::now());
+                }
+            })
+            .context("spawning log limiter thread")?;
+    }
+    if let Some(Command::ProveChild) = &cli.command {
+        return run_prover_process(&config, mp2_requirement).await;
+    }
+    if cli.dry_run {
+        return dry_run(&config).await;
+    }
     debug!("Loaded configuration: {:?}", config);
 
     let span = span!(
@@ -173,18 +414,88 @@ async fn run(
     );
     let _guard = span.enter();
 
-    metrics_exporter_prometheus::PrometheusBuilder::new()
-        .with_http_listener(([0, 0, 0, 0], config.prometheus.port))
-        .install()
-        .context("setting up Prometheus")?;
+    let (recorder, exporter) = build_prometheus(&config.prometheus)?;
+    // Kept to render the metrics snapshot of the health server.
+    let metrics = recorder.handle();
+    let metrics_port = exporter.as_ref().map(|(_, port)| *port);
+    if let Some((exporter, _)) = exporter {
+        tokio::spawn(exporter);
+    }
+    let upkeep = metrics.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            upkeep.run_upkeep();
+        }
+    });
+    match &config.prometheus.prefix {
+        Some(prefix) => metrics::set_global_recorder(PrefixLayer::new(prefix).layer(recorder)),
+        None => metrics::set_global_recorder(recorder),
+    }
+    .map_err(|_| anyhow!("a metrics recorder is already installed"))?;
 
-    run_worker(&config, mp2_requirement, last_task_processed).await
+    run_worker(
+        &config,
+        &cli,
+        mp2_requirement,
+        last_task_processed,
+        metrics,
+        metrics_port,
+    )
+    .await
+}
+
+/// Build the Prometheus recorder and the exporter serving its metrics along with its port, the
+/// latter as directed by `prometheus.on_bind_failure` if the configured port is unavailable.
+fn build_prometheus(
+    config: &PrometheusConfig
+) -> Result<(PrometheusRecorder, Option<(ExporterFuture, u16)>)> {
+    let builder = |port: u16| {
+        let mut prometheus = PrometheusBuilder::new().with_http_listener(([0, 0, 0, 0], port));
+        for (label, value) in &config.global_labels {
+            prometheus = prometheus.add_global_label(label, value);
+        }
+        prometheus
+    };
+
+    let error = match builder(config.port).build() {
+        Ok((recorder, exporter)) => return Ok((recorder, Some((exporter, config.port)))),
+        Err(error @ BuildError::FailedToCreateHTTPListener(_)) => error,
+        Err(error) => return Err(error).context("setting up Prometheus"),
+    };
+    match config.on_bind_failure {
+        OnBindFailure::Abort => Err(error).context("setting up Prometheus"),
+        OnBindFailure::Warn => {
+            warn!(
+                "metrics are not exported, serving them on port {} failed: {error}",
+                config.port
+            );
+            // Still recorded, for the metrics snapshot of the health server.
+            Ok((builder(config.port).build_recorder(), None))
+        },
+        OnBindFailure::RandomPort => {
+            let port = std::net::TcpListener::bind(("0.0.0.0", 0))
+                .and_then(|listener| listener.local_addr())
+                .context("picking a port for Prometheus")?
+                .port();
+            let (recorder, exporter) = builder(port).build().context("setting up Prometheus")?;
+            warn!(
+                "serving metrics on port {port}, serving them on port {} failed: {error}",
+                config.port
+            );
+            Ok((recorder, Some((exporter, port))))
+        },
+    }
 }
 
 async fn run_worker(
     config: &Config,
+    cli: &Cli,
     mp2_requirement: semver::VersionReq,
     last_task_processed: AtomicU64,
+    metrics: PrometheusHandle,
+    metrics_port: Option<u16>,
 ) -> Result<()> {
     let max_message_size = config
         .avs
@@ -193,27 +504,164 @@ async fn run_worker(
         * 1024
         * 1024;
 
-    let checksums = if cfg!(not(feature = "dummy-prover")) {
-        fetch_checksums(config.public_params.checksum_file_url())
-            .await
-            .context("downloading checksum file")?
+    let liveness_check_interval = config.worker.liveness_check_interval;
+    let last_task_processed = Arc::new(last_task_processed);
+    let last_task_processed_clone = Arc::clone(&last_task_processed);
+    let readiness = Arc::new(ProversReadiness::default());
+    if config.public_params.non_production {
+        warn!(
+            "!!! running with NON-PRODUCTION params, \
+             this worker must not serve production tasks !!!"
+        );
+        readiness.set_non_production();
+    }
+    let readiness_clone = Arc::clone(&readiness);
+    let (warm_sender, warm_requests) = tokio::sync::mpsc::unbounded_channel();
+    let params_status = Arc::new(OnceLock::new());
+    let circuit_breaker = Arc::new(CircuitBreaker::new(config.circuit_breaker.clone()));
+    let connection_status = Arc::new(ConnectionStatus::default());
+    let success_ratio = Arc::new(SuccessRatio::new(config.worker.success_ratio_window));
+    let saturation = Arc::new(Saturation::new(&config.saturation, Instant::now()));
+    let flamegraphs = config
+        .flamegraph
+        .as_ref()
+        .map(Flamegraphs::new)
+        .transpose()
+        .context("setting up flamegraphs")?
+        .map(Arc::new);
+
+    if !cli.stdio {
+        spawn_health_server(
+            liveness_check_interval,
+            last_task_processed_clone,
+            readiness_clone,
+            metrics,
+            warm_sender,
+            Arc::clone(&params_status),
+            Arc::clone(&circuit_breaker),
+            Arc::clone(&connection_status),
+            Arc::clone(&success_ratio),
+            Arc::clone(&saturation),
+            flamegraphs.clone(),
+        )?;
+    }
+
+    let params_checksums = fetch_params_checksums(config).await?;
+    if cfg!(not(feature = "dummy-prover")) {
+        let status = tokio::task::block_in_place(|| ParamsStatus::read(&config.public_params));
+        let _ = params_status.set(status);
+    }
+    let (provers_manager, isolated_prover) = if config.worker.isolated_proving {
+        let mut args = vec![];
+        if let Some(config_file) = &cli.config {
+            args.extend(["--config".to_string(), config_file.clone()]);
+        }
+        if cli.json {
+            args.push("--json".to_string());
+        }
+        args.push("prove-child".to_string());
+
+        let late_proof_grace = config.worker.late_proof_grace.map(Duration::from_secs);
+        let isolated_prover =
+            tokio::task::block_in_place(|| IsolatedProver::new(args, late_proof_grace))
+                .context("starting prover process")?;
+        // The tasks are still checked against the accepted classes before being sent over.
+        let mut provers_manager = ProversManager::new();
+        provers_manager.set_accepted_classes(accepted_classes(config));
+        (provers_manager, Some(isolated_prover))
     } else {
-        Default::default()
+        (
+            create_provers_manager(config, &readiness, &params_checksums)?,
+            None,
+        )
     };
 
-    let mut provers_manager =
-        tokio::task::block_in_place(move || -> Result<ProversManager<TaskType, ReplyType>> {
-            let mut provers_manager = ProversManager::<TaskType, ReplyType>::new();
-            register_v1_provers(config, &mut provers_manager, &checksums)
-                .context("while registering provers")?;
-            Ok(provers_manager)
-        })
-        .context("creating prover managers")?;
+    // Let the freshly loaded parameters settle (page-ins, allocator warmup) before the
+    // orchestrator starts routing tasks to the worker.
+    tokio::time::sleep(Duration::from_secs(config.worker.readiness_delay)).await;
+    readiness.set_initialized();
+    log_startup_report(
+        config,
+        &mp2_requirement,
+        &readiness,
+        &params_checksums,
+        metrics_port,
+    );
+    // Until then, the signals keep stopping the worker right away, e.g. while downloading params.
+    let mut shutdown = Shutdown::listen(
+        Duration::from_secs(config.worker.shutdown_grace_secs),
+        Arc::clone(&readiness),
+    )?;
+
+    let mut worker = Worker {
+        config,
+        prover: Arc::new(TaskProver {
+            config: config.clone(),
+            provers_manager,
+            isolated_prover: isolated_prover.map(Mutex::new),
+            mp2_requirement,
+            panic_policy: PanicPolicy::new(
+                config.worker.fatal_panics.clone(),
+                config.worker.retry_panics.clone(),
+            ),
+            audit_log: AuditLog::new(config.audit_log.as_ref()).context("setting up audit log")?,
+            tenants: Tenants::new(config.tenants.as_ref()).context("setting up tenants")?,
+            last_arrivals: Mutex::new(HashMap::new()),
+            flamegraphs,
+            proof_store: config
+                .proof_store
+                .as_ref()
+                .map(ProofStore::new)
+                .transpose()
+                .context("setting up proof store")?,
+        }),
+        params_checksums,
+        max_message_size,
+        failed_tasks: config
+            .failed_tasks
+            .as_ref()
+            .map(FailedTasks::new)
+            .transpose()
+            .context("setting up failed tasks directory")?,
+        result_cache: ResultCache::new(
+            config.worker.result_cache_size,
+            Duration::from_secs(config.worker.result_cache_ttl),
+        ),
+        warm_requests: Some(warm_requests),
+        success_ratio,
+        saturation,
+        last_task_processed,
+        started_at: Instant::now(),
+        tasks_processed: 0,
+        shutdown: shutdown.clone(),
+    };
+
+    if let Some(Command::Bench { tasks_dir }) = &cli.command {
+        let mut sink = BenchSink::default();
+        worker
+            .serve(DirectorySource::new(tasks_dir)?, &mut sink)
+            .await?
+            .into_result()?;
+        return sink.report();
+    }
+
+    if let Some(tasks_dir) = &cli.tasks_dir {
+        let source = DirectorySource::new(tasks_dir)?;
+        let mut sink = DirectorySink {
+            dir: cli.replies_dir.as_ref().map(PathBuf::from),
+        };
+        return worker.serve(source, &mut sink).await?.into_result();
+    }
+
+    if cli.stdio {
+        return worker
+            .serve(StdioSource::new(max_message_size), &mut StdioSink)
+            .await?
+            .into_result();
+    }
 
     // Connecting to the GW
     let wallet = get_wallet(config).context("fetching wallet")?;
-    let claims = get_claims(config).context("building claims")?;
-    let token = JWTAuth::new(claims, &wallet)?.encode()?;
 
     let grpc_url = &config.avs.gateway_url;
     info!(
@@ -230,102 +678,1210 @@ async fn run_worker(
         .install_default()
         .expect("Failed to install rustls crypto provider");
 
+    let tls = tls_config(config).context("setting up gateway TLS")?;
+    let token = Arc::new(ArcSwapOption::empty());
+    if let Some(secs) = config.avs.token_refresh_secs {
+        tokio::spawn(refresh_token(
+            config.clone(),
+            wallet.clone(),
+            Arc::clone(&token),
+            Duration::from_secs(secs),
+        ));
+    }
+    let mut backoff = Backoff::new(&config.worker);
+    let mut reconnecting = false;
+    loop {
+        let connecting = async {
+            loop {
+                circuit_breaker.attempt();
+                connection_status.attempt();
+                match connect_to_gateway(config, &uri, &tls, &wallet, &token, max_message_size)
+                    .await
+                {
+                    Ok(connection) => {
+                        circuit_breaker.record_success();
+                        connection_status.connected();
+                        break connection;
+                    },
+                    Err(e) => {
+                        counter!("zkmr_worker_gateway_connection_failures_total").increment(1);
+                        let delay = circuit_breaker.record_failure(Instant::now());
+                        connection_status.failed(format!("{e:?}"), delay);
+                        warn!(
+                            "connecting to the gateway failed, retrying in {}s: {e:?}",
+                            delay.as_secs()
+                        );
+                        tokio::time::sleep(delay).await;
+                    },
+                }
+            }
+        };
+        let (source, mut sink) = tokio::select! {
+            connection = connecting => connection,
+            () = shutdown.requested() => return Ok(()),
+        };
+        if reconnecting {
+            info!("reconnected to the gateway");
+            counter!("zkmr_worker_reconnects_total").increment(1);
+        }
+
+        // The worker, hence its provers and their params, outlives the connection.
+        let connected_at = Instant::now();
+        let reason = match worker.serve(source, &mut sink).await? {
+            ServeEnd::Recycled | ServeEnd::ShutDown => return Ok(()),
+            ServeEnd::Exhausted => "the gateway closed the stream".to_string(),
+            ServeEnd::Disconnected(e) => format!("{e:?}"),
+        };
+        let delay = backoff.next(connected_at, Instant::now());
+        connection_status.failed(reason.clone(), delay);
+        warn!(
+            "lost the connection to the gateway, reconnecting in {}ms: {reason}",
+            delay.as_millis()
+        );
+        tokio::select! {
+            () = tokio::time::sleep(delay) => {},
+            () = shutdown.requested() => return Ok(()),
+        }
+        reconnecting = true;
+    }
+}
+
+/// A token presented to the gateway.
+struct GatewayToken {
+    /// The `authorization` metadata presenting the token.
+    value: MetadataValue<Ascii>,
+    /// When the token was issued, in seconds since the Unix epoch.
+    issued_at: Option<u64>,
+}
+
+/// Mint a token for the worker to present to the gateway.
+fn mint_token(
+    config: &Config,
+    wallet: &Wallet<SigningKey>,
+) -> Result<GatewayToken> {
+    let claims = get_claims(config).context("building claims")?;
+    let token = JWTAuth::new(claims.clone(), wallet)?.encode()?;
+    check_token(&token, &claims, wallet).context("checking JWT")?;
+    Ok(GatewayToken {
+        value: format!("Bearer {token}").parse()?,
+        issued_at: claims.registered.issued_at,
+    })
+}
+
+/// Mint a fresh gateway token every `period`, for the connections opened later on to present a
+/// token the gateway still accepts. A failure, e.g. the wallet being unavailable, is logged and
+/// retried sooner, the current token being kept in the meantime.
+async fn refresh_token(
+    config: Config,
+    wallet: Wallet<SigningKey>,
+    token: Arc<ArcSwapOption<GatewayToken>>,
+    period: Duration,
+) {
+    let mut delay = period;
+    loop {
+        tokio::time::sleep(delay).await;
+        delay = match mint_token(&config, &wallet) {
+            Ok(fresh) => {
+                token.store(Some(Arc::new(fresh)));
+                counter!("zkmr_worker_token_refreshes_total").increment(1);
+                debug!("refreshed the gateway token");
+                period
+            },
+            Err(e) => {
+                counter!("zkmr_worker_token_refresh_failures_total").increment(1);
+                let retry = TOKEN_REFRESH_RETRY.min(period);
+                warn!(
+                    "refreshing the gateway token failed, retrying in {}s: {e:?}",
+                    retry.as_secs()
+                );
+                retry
+            },
+        };
+    }
+}
+
+/// Open the bidirectional stream with the gateway and announce the worker on it, presenting the
+/// current `token`.
+async fn connect_to_gateway(
+    config: &Config,
+    uri: &tonic::transport::Uri,
+    tls: &ClientTlsConfig,
+    wallet: &Wallet<SigningKey>,
+    token: &Arc<ArcSwapOption<GatewayToken>>,
+    max_message_size: usize,
+) -> Result<(GatewaySource, GatewaySink)> {
     let channel = tonic::transport::Channel::builder(uri.clone())
-        .tls_config(ClientTlsConfig::new().with_enabled_roots())?
+        .tls_config(tls.clone())?
         .connect()
         .await
         .with_context(|| format!("creating transport channel builder for {uri}"))?;
-    let token: MetadataValue<_> = format!("Bearer {token}").parse()?;
+
+    // Unless it is refreshed in the background, the token is minted only now, so that it is not
+    // stale by the time it is presented.
+    if config.avs.token_refresh_secs.is_none() || token.load().is_none() {
+        token.store(Some(Arc::new(mint_token(config, wallet)?)));
+    }
+    let issued_at = token.load().as_ref().and_then(|token| token.issued_at);
+    let presented = Arc::clone(token);
     let mut client = lagrange::workers_service_client::WorkersServiceClient::with_interceptor(
         channel,
         move |mut req: Request<()>| {
-            req.metadata_mut().insert("authorization", token.clone());
+            if let Some(token) = presented.load().as_ref() {
+                req.metadata_mut()
+                    .insert("authorization", token.value.clone());
+            }
             Ok(req)
         },
     )
     .max_encoding_message_size(max_message_size)
     .max_decoding_message_size(max_message_size);
 
-    let (mut outbound, outbound_rx) = tokio::sync::mpsc::channel(50);
+    let (outbound, outbound_rx) = tokio::sync::mpsc::channel(50);
     let outbound_rx = tokio_stream::wrappers::ReceiverStream::new(outbound_rx);
     outbound
         .send(WorkerToGwRequest {
             request: Some(lagrange::worker_to_gw_request::Request::WorkerReady(
                 lagrange::WorkerReady {
                     version: env!("CARGO_PKG_VERSION").to_string(),
-                    worker_class: format!(
-                        "{}-{}",
-                        config.worker.instance_type,
-                        semver::Version::parse(verifiable_db::version())
-                            .unwrap()
-                            .major
-                    ),
+                    worker_class: worker_class(config),
                 },
             )),
         })
         .await?;
 
+    if let Some(issued_at) = issued_at {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        histogram!("zkmr_worker_token_age_at_connect_seconds")
+            .record(now.saturating_sub(issued_at) as f64);
+    }
     let response = client
         .worker_to_gw(tonic::Request::new(outbound_rx))
         .await
         .context("connecting `worker_to_gw`")?;
 
     info!("Bidirectional stream with GW opened");
-    let mut inbound = response.into_inner();
+    let inbound = response.into_inner();
 
-    let liveness_check_interval = config.worker.liveness_check_interval;
-    let last_task_processed = Arc::new(last_task_processed);
-    let last_task_processed_clone = Arc::clone(&last_task_processed);
+    Ok((
+        GatewaySource::new(inbound, config.worker.task_batch_size),
+        GatewaySink { outbound },
+    ))
+}
 
-    // Start readiness and liveness check server
-    tokio::spawn(async move {
-        let readiness_route = warp::path!("readiness")
-            .map(|| warp::reply::with_status("OK", warp::http::StatusCode::OK));
-        let liveness_route = warp::path!("liveness").map(move || {
-            let last_processed = last_task_processed_clone.load(Ordering::Relaxed);
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            if now - last_processed <= liveness_check_interval {
-                warp::reply::with_status("OK", warp::http::StatusCode::OK)
+/// The class the worker announces to the gateway, for it to be routed the tasks it can prove.
+fn worker_class(config: &Config) -> String {
+    format!(
+        "{}-{}{}",
+        config.worker.instance_type,
+        semver::Version::parse(verifiable_db::version())
+            .unwrap()
+            .major,
+        if config.public_params.non_production {
+            "-nonprod"
+        } else {
+            ""
+        }
+    )
+}
+
+/// Go through the startup of the worker up to connecting to the gateway, see `--dry-run`.
+async fn dry_run(config: &Config) -> Result<()> {
+    let wallet = get_wallet(config).context("fetching wallet")?;
+    mint_token(config, &wallet)?;
+
+    let checksums = fetch_params_checksums(config).await?;
+    let readiness = ProversReadiness::default();
+    let provers_manager = create_provers_manager(config, &readiness, &checksums)?;
+
+    let (params_memory, _) = lgn_provers::params::params_memory();
+    println!("worker class: {}", worker_class(config));
+    println!("mp2 version: {}", verifiable_db::version());
+    println!("param checksums: {}", checksums.len());
+    println!("params loaded: {}MiB", params_memory / (1024 * 1024));
+    println!("provers: {:?}", readiness.provers());
+    println!(
+        "supported task types: {:?}",
+        provers_manager.supported_task_types()
+    );
+    println!("the worker is ready to connect to the gateway");
+    Ok(())
+}
+
+/// Log the effective runtime of the worker as a single event, once it is ready, for post-incident
+/// reviews and fleet audits not to piece it together from the startup logs. No secret is logged.
+fn log_startup_report(
+    config: &Config,
+    mp2_requirement: &semver::VersionReq,
+    readiness: &ProversReadiness,
+    params_checksums: &HashMap<String, blake3::Hash>,
+    metrics_port: Option<u16>,
+) {
+    let params = params_checksums
+        .iter()
+        .map(|(file, checksum)| (file.as_str(), checksum.to_hex().to_string()))
+        .collect::<BTreeMap<_, _>>();
+    // Credentials may be embedded in the URL.
+    let gateway_url = reqwest::Url::parse(&config.avs.gateway_url)
+        .map(|mut url| {
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+            url.to_string()
+        })
+        .unwrap_or_default();
+    info!(
+        worker_id = %config.avs.worker_id,
+        issuer = %config.avs.issuer,
+        class = %config.worker.instance_type,
+        gateway_url,
+        mp2_version = verifiable_db::version(),
+        mp2_requirement = %mp2_requirement,
+        non_production = config.public_params.non_production,
+        provers = ?readiness.provers(),
+        isolated_proving = config.worker.isolated_proving,
+        accepted_classes = ?config.worker.accepted_classes,
+        params = ?params,
+        max_tasks = ?config.worker.max_tasks,
+        max_uptime = ?config.worker.max_uptime,
+        task_timeout = ?config.worker.task_timeout,
+        health_port = HEALTH_PORT,
+        metrics_port = ?metrics_port,
+        "worker ready"
+    );
+}
+
+/// Fetch the expected checksums of the param files.
+async fn fetch_params_checksums(config: &Config) -> Result<HashMap<String, blake3::Hash>> {
+    if cfg!(not(feature = "dummy-prover")) {
+        fetch_checksums(config.public_params.checksum_file_url())
+            .await
+            .context("downloading checksum file")
+    } else {
+        Ok(Default::default())
+    }
+}
+
+/// The classes of the tasks to prove, see `worker.accepted_classes`.
+fn accepted_classes(config: &Config) -> impl Iterator<Item = ProverType> + '_ {
+    config
+        .worker
+        .accepted_classes
+        .iter()
+        .copied()
+        .map(ProverType::from)
+}
+
+fn create_provers_manager(
+    config: &Config,
+    readiness: &ProversReadiness,
+    checksums: &HashMap<String, blake3::Hash>,
+) -> Result<ProversManager<TaskType, ReplyType>> {
+    lgn_provers::params::set_max_downloads_per_host(config.public_params.max_downloads_per_host);
+    lgn_provers::params::set_params_memory_limit(
+        config
+            .public_params
+            .max_memory_mb
+            .map(|max_memory_mb| max_memory_mb * 1024 * 1024),
+    );
+
+    tokio::task::block_in_place(move || -> Result<ProversManager<TaskType, ReplyType>> {
+        let mut provers_manager = ProversManager::<TaskType, ReplyType>::new();
+        provers_manager.set_proof_cache(config.worker.proof_cache_entries);
+        provers_manager.set_accepted_classes(accepted_classes(config));
+        register_v1_provers(config, &mut provers_manager, checksums, readiness)
+            .context("while registering provers")?;
+        Ok(provers_manager)
+    })
+    .context("creating prover managers")
+}
+
+/// Entry point of the child process proving on behalf of a worker, see
+/// `worker.isolated_proving`.
+async fn run_prover_process(
+    config: &Config,
+    mp2_requirement: semver::VersionReq,
+) -> Result<()> {
+    let checksums = fetch_params_checksums(config).await?;
+    let provers_manager = create_provers_manager(config, &ProversReadiness::default(), &checksums)?;
+    tokio::task::block_in_place(|| {
+        serve_prover_process(
+            &provers_manager,
+            &mp2_requirement,
+            &PanicPolicy::new(
+                config.worker.fatal_panics.clone(),
+                config.worker.retry_panics.clone(),
+            ),
+            &config.proof_encoding,
+            &config.proof_compression,
+            config.worker.resource_usage,
+            config.worker.content_hash,
+        )
+    })
+}
+
+/// Start the readiness and liveness check server.
+///
+/// The server runs on its own thread and single-threaded runtime: proving saturates the CPU and
+/// parks the main runtime workers in `block_in_place`, which would otherwise delay the probe
+/// responses long enough for the orchestrator to deem a busy but healthy worker dead.
+#[allow(clippy::too_many_arguments)]
+fn spawn_health_server(
+    liveness_check_interval: u64,
+    last_task_processed: Arc<AtomicU64>,
+    readiness: Arc<ProversReadiness>,
+    metrics: PrometheusHandle,
+    warm_requests: UnboundedSender<WarmHint>,
+    params_status: Arc<OnceLock<ParamsStatus>>,
+    circuit_breaker: Arc<CircuitBreaker>,
+    connection_status: Arc<ConnectionStatus>,
+    success_ratio: Arc<SuccessRatio>,
+    saturation: Arc<Saturation>,
+    flamegraphs: Option<Arc<Flamegraphs>>,
+) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("creating health server runtime")?;
+    std::thread::Builder::new()
+        .name("health-server".to_string())
+        .spawn(move || {
+            runtime.block_on(async move {
+                let detailed_readiness = Arc::clone(&readiness);
+                let detailed_readiness_route = warp::path!("readiness" / "detailed")
+                    .map(move || warp::reply::json(&detailed_readiness.report()));
+                let readiness_route = warp::path!("readiness").map(move || {
+                    if readiness.is_ready() {
+                        warp::reply::with_status("OK", warp::http::StatusCode::OK)
+                    } else {
+                        warp::reply::with_status(
+                            "NOT READY",
+                            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+                        )
+                    }
+                });
+                let liveness_route = warp::path!("liveness").map(move || {
+                    let last_processed = last_task_processed.load(Ordering::Relaxed);
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs();
+                    if now - last_processed > liveness_check_interval {
+                        warp::reply::with_status(
+                            "FAIL",
+                            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                        )
+                    } else if saturation.degraded(Instant::now()) {
+                        warp::reply::with_status(
+                            "SATURATED",
+                            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+                        )
+                    } else {
+                        warp::reply::with_status("OK", warp::http::StatusCode::OK)
+                    }
+                });
+                let metrics_snapshot_route = warp::path!("metrics" / "snapshot")
+                    .map(move || warp::reply::json(&metrics_snapshot::parse(&metrics.render())));
+                // Announces a burst of tasks of a table, for the provers to prepare for it.
+                let warm_route =
+                    warp::post()
+                        .and(warp::path!("warm" / TableId))
+                        .map(move |table_id| {
+                            match warm_requests.send(WarmHint { table_id }) {
+                                Ok(()) => {
+                                    warp::reply::with_status(
+                                        "ACCEPTED",
+                                        warp::http::StatusCode::ACCEPTED,
+                                    )
+                                },
+                                Err(_) => {
+                                    warp::reply::with_status(
+                                        "NOT SERVING",
+                                        warp::http::StatusCode::SERVICE_UNAVAILABLE,
+                                    )
+                                },
+                            }
+                        });
+                // `null` until the param files have been looked up.
+                let params_status_route = warp::path!("status" / "params")
+                    .map(move || warp::reply::json(&params_status.get()));
+                let status_route = warp::path!("status").map(move || {
+                    let (params_memory, params_memory_limit) = lgn_provers::params::params_memory();
+                    warp::reply::json(&WorkerStatus {
+                        circuit_breaker: circuit_breaker.state(),
+                        connection: connection_status.state(),
+                        success_ratio: success_ratio.ratio(),
+                        params_memory,
+                        params_memory_limit,
+                    })
+                });
+                // Requests a CPU profile of the next proof, see `flamegraph`.
+                let flamegraph_route = warp::post().and(warp::path!("flamegraph")).map(move || {
+                    match flamegraphs.as_deref().map(Flamegraphs::request) {
+                        Some(true) => {
+                            warp::reply::with_status("ACCEPTED", warp::http::StatusCode::ACCEPTED)
+                        },
+                        Some(false) => {
+                            warp::reply::with_status(
+                                "CAPTURE IN PROGRESS",
+                                warp::http::StatusCode::CONFLICT,
+                            )
+                        },
+                        None => {
+                            warp::reply::with_status(
+                                "NOT CONFIGURED",
+                                warp::http::StatusCode::NOT_FOUND,
+                            )
+                        },
+                    }
+                });
+                let routes = detailed_readiness_route
+                    .or(readiness_route)
+                    .or(liveness_route)
+                    .or(metrics_snapshot_route)
+                    .or(warm_route)
+                    .or(params_status_route)
+                    .or(status_route)
+                    .or(flamegraph_route);
+                warp::serve(routes).run(([0, 0, 0, 0], HEALTH_PORT)).await;
+            })
+        })
+        .context("spawning health server thread")?;
+
+    Ok(())
+}
+
+/// The state of the worker served on `/status`.
+#[derive(Serialize)]
+struct WorkerStatus {
+    /// The state of the circuit breaker of the gateway connection.
+    circuit_breaker: CircuitState,
+    /// The state of the reconnection to the gateway.
+    connection: ConnectionState,
+    /// The share of the last tasks proven successfully, `null` until a task is done.
+    success_ratio: Option<f64>,
+    /// The memory held by the loaded params, in bytes, as approximated by the size of their files;
+    /// with `isolated_proving`, the params are loaded by the prover process, and not accounted
+    /// for.
+    params_memory: u64,
+    /// The most memory the loaded params may hold, in bytes, `null` if not capped.
+    params_memory_limit: Option<u64>,
+}
+
+/// The proving core of the worker, proving the tasks of a [`TaskSource`] and sending their results
+/// to a [`ResultSink`].
+struct Worker<'a> {
+    config: &'a Config,
+    /// Proves the tasks, shared with the tasks in flight.
+    prover: Arc<TaskProver>,
+    /// The expected checksums of the param files the provers have been built from.
+    params_checksums: HashMap<String, blake3::Hash>,
+    /// The largest message the gateway stream accepts.
+    max_message_size: usize,
+    failed_tasks: Option<FailedTasks>,
+    result_cache: Option<ResultCache>,
+    /// The burst announcements of the health server, taken by [`Worker::serve`].
+    warm_requests: Option<UnboundedReceiver<WarmHint>>,
+    success_ratio: Arc<SuccessRatio>,
+    /// Tracks the time the proving pool is full, for the liveness probe.
+    saturation: Arc<Saturation>,
+    last_task_processed: Arc<AtomicU64>,
+    /// When the worker started, for `max_uptime` to span the reconnections to the gateway.
+    started_at: Instant,
+    /// How many tasks were taken, for `max_tasks` to span the reconnections to the gateway.
+    tasks_processed: u64,
+    shutdown: Shutdown,
+}
+
+/// What proving a task needs, shared by the [`Worker`] with the tasks in flight.
+struct TaskProver {
+    config: Config,
+    provers_manager: ProversManager<TaskType, ReplyType>,
+    /// If set, proves the tasks instead of `provers_manager`.
+    isolated_prover: Option<Mutex<IsolatedProver>>,
+    mp2_requirement: semver::VersionReq,
+    panic_policy: PanicPolicy,
+    audit_log: AuditLog,
+    tenants: Tenants,
+    /// When the last task of each class was received.
+    last_arrivals: Mutex<HashMap<ProverType, Instant>>,
+    /// If set, captures the proofs it is requested to.
+    flamegraphs: Option<Arc<Flamegraphs>>,
+    /// Where the large proofs are offloaded to, and the referenced proofs fetched from.
+    proof_store: Option<ProofStore>,
+}
+
+/// A task proven by the [`TaskProver`], to be replied to.
+struct Proven<Id> {
+    task: Task<Id>,
+    reply: Result<MessageReplyEnvelope<ReplyType>, String>,
+    /// Whether the task passed the checks, its failures being rejections until then.
+    accepted: bool,
+    /// The reply telling that the task is of a class the worker does not prove, if it is.
+    unsupported: Option<MessageReplyEnvelope<ReplyType>>,
+    tenant: Option<String>,
+    span: Span,
+}
+
+/// Why [`Worker::serve`] returned.
+enum ServeEnd {
+    /// The source has no more tasks, e.g. the gateway closed its stream.
+    Exhausted,
+    /// Taking a task from the source or sending its result failed, e.g. the gateway stream broke.
+    Disconnected(anyhow::Error),
+    /// The worker is due for recycling, after `max_tasks` tasks or `max_uptime` seconds.
+    Recycled,
+    /// The worker was signaled to shut down.
+    ShutDown,
+}
+
+impl ServeEnd {
+    /// The outcome of serving a source which is not reconnected to.
+    fn into_result(self) -> Result<()> {
+        match self {
+            ServeEnd::Exhausted | ServeEnd::Recycled | ServeEnd::ShutDown => Ok(()),
+            ServeEnd::Disconnected(e) => Err(e),
+        }
+    }
+}
+
+impl Worker<'_> {
+    /// Prove the tasks of `source` until it is exhausted or disconnected, or the worker is due for
+    /// recycling; the errors returned are fatal to the worker.
+    async fn serve<S: TaskSource, R: ResultSink<S::Id>>(
+        &mut self,
+        mut source: S,
+        sink: &mut R,
+    ) -> Result<ServeEnd>
+    where
+        S::Id: Send + 'static,
+    {
+        let max_uptime = self.config.worker.max_uptime;
+        let uptime_reached = tokio::time::sleep_until(tokio::time::Instant::from_std(
+            self.started_at + Duration::from_secs(max_uptime.unwrap_or_default()),
+        ));
+        tokio::pin!(uptime_reached);
+        let mut reload_requested =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+                .context("listening for SIGHUP")?;
+        let mut warm_requests = self
+            .warm_requests
+            .take()
+            .unwrap_or_else(|| tokio::sync::mpsc::unbounded_channel().1);
+        let mut shutdown = self.shutdown.clone();
+        let max_concurrent_tasks = self.config.worker.max_concurrent_tasks;
+        let mut in_flight = JoinSet::new();
+        let mut in_flight_ids = InFlightIds::new();
+        // The tasks in flight hold the provers, hence the reload waits for them to complete, no
+        // more tasks being started in the meantime.
+        let mut reload_pending = false;
+
+        let end = loop {
+            if reload_pending && in_flight.is_empty() {
+                self.reload_params().await?;
+                reload_pending = false;
+            }
+            // Checked before taking another task, in case the signal arrived while proving.
+            if shutdown.is_requested() {
+                info!("shutting down");
+                break ServeEnd::ShutDown;
+            }
+            debug!("Waiting for message...");
+            tokio::select! {
+                // No task is taken while the pool is full, for the gateway to hold on to them
+                // rather than the worker buffering them.
+                task = source.next_task(), if in_flight.len() < max_concurrent_tasks && !reload_pending => {
+                    let task = match task {
+                        Ok(Some(task)) => task,
+                        Ok(None) => {
+                            info!("no more tasks to process");
+                            break ServeEnd::Exhausted;
+                        },
+                        Err(e) => break ServeEnd::Disconnected(e),
+                    };
+                    if let Some(task) = in_flight_ids.admit(task, self.config.worker.duplicate_task_ids) {
+                        let span = info_span!("task", uuid = %task.name);
+                        match self.start_task(task, sink, &mut in_flight, &mut in_flight_ids).instrument(span).await {
+                            Ok(true) => self.record_processed()?,
+                            // The worker is saturated while its proving pool is full.
+                            Ok(false) if in_flight.len() == max_concurrent_tasks => self.saturation.start_busy(Instant::now()),
+                            Ok(false) => {},
+                            Err(e) => break ServeEnd::Disconnected(anyhow!("task processing failed: {e:?}")),
+                        }
+                    }
+
+                    self.tasks_processed += 1;
+                    if self.config.worker.max_tasks.is_some_and(|max_tasks| self.tasks_processed >= max_tasks) {
+                        info!("recycling worker after {} tasks", self.tasks_processed);
+                        counter!("zkmr_worker_recycles_total", "reason" => "max_tasks").increment(1);
+                        break ServeEnd::Recycled;
+                    }
+                }
+                Some(proven) = in_flight.join_next() => {
+                    if in_flight.len() + 1 == max_concurrent_tasks {
+                        self.saturation.end_busy(Instant::now());
+                    }
+                    let proven = proven.context("proving a task panicked")?;
+                    let span = proven.span.clone();
+                    let next = in_flight_ids.completed(proven.task.key.as_deref());
+                    if let Err(e) = self.complete_task(proven, sink).instrument(span).await {
+                        break ServeEnd::Disconnected(anyhow!("task processing failed: {e:?}"));
+                    }
+                    self.record_processed()?;
+                    if let Err(e) = self.start_queued(next, sink, &mut in_flight, &mut in_flight_ids).await {
+                        break ServeEnd::Disconnected(anyhow!("task processing failed: {e:?}"));
+                    }
+                    if self.prover.panic_policy.tripped() {
+                        counter!("zkmr_worker_recycles_total", "reason" => "fatal_panic").increment(1);
+                        sink.flush().await;
+                        bail!("exiting after a fatal panic");
+                    }
+                }
+                Some(()) = reload_requested.recv(), if !reload_pending => {
+                    reload_pending = true;
+                }
+                Some(hint) = warm_requests.recv() => {
+                    self.warm(&hint);
+                }
+                () = shutdown.requested() => {}
+                () = &mut uptime_reached, if max_uptime.is_some() => {
+                    info!("recycling worker after {}s of uptime", max_uptime.unwrap_or_default());
+                    counter!("zkmr_worker_recycles_total", "reason" => "max_uptime").increment(1);
+                    break ServeEnd::Recycled;
+                }
+            }
+        };
+
+        // The tasks in flight are still replied to, as long as the sink takes their replies; the
+        // ones queued behind them are left to the gateway to send again.
+        if in_flight_ids.queued() > 0 {
+            warn!(
+                "dropping {} tasks queued behind a task with the same ID",
+                in_flight_ids.queued()
+            );
+        }
+        while let Some(proven) = in_flight.join_next().await {
+            if in_flight.len() + 1 == max_concurrent_tasks {
+                self.saturation.end_busy(Instant::now());
+            }
+            let proven = proven.context("proving a task panicked")?;
+            let span = proven.span.clone();
+            match self.complete_task(proven, sink).instrument(span).await {
+                Ok(()) => self.record_processed()?,
+                Err(e) => warn!("replying to a task in flight: {e:?}"),
+            }
+        }
+        if !matches!(end, ServeEnd::Disconnected(_)) {
+            sink.flush().await;
+        }
+        // Kept for the next source, once reconnected to the gateway.
+        self.warm_requests = Some(warm_requests);
+        Ok(end)
+    }
+
+    /// Record that a task was replied to, for the liveness probe.
+    fn record_processed(&self) -> Result<()> {
+        self.last_task_processed.store(
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            Ordering::Relaxed,
+        );
+        Ok(())
+    }
+
+    /// Prepare the provers for the burst of tasks announced by `hint`; a failure only delays the
+    /// preparation to the first tasks of the burst.
+    fn warm(
+        &self,
+        hint: &WarmHint,
+    ) {
+        if self.prover.isolated_prover.is_some() {
+            warn!(
+                "ignoring the announcement of table {}, the provers of a prover process can not \
+                 be warmed",
+                hint.table_id
+            );
+            return;
+        }
+
+        info!("warming the provers for table {}", hint.table_id);
+        let start = Instant::now();
+        match tokio::task::block_in_place(|| self.prover.provers_manager.warm(hint)) {
+            Ok(()) => {
+                histogram!("zkmr_worker_provers_warm_seconds").record(start.elapsed().as_secs_f64())
+            },
+            Err(e) => warn!("warming the provers for table {}: {e:?}", hint.table_id),
+        }
+    }
+
+    /// Rebuild the provers from the current param files, which may have changed since the worker
+    /// started, without dropping the connection to the gateway.
+    async fn reload_params(&mut self) -> Result<()> {
+        info!("reloading params");
+        let checksums = fetch_params_checksums(self.config).await?;
+        for (file, checksum) in &checksums {
+            let previous = self.params_checksums.get(file);
+            if previous != Some(checksum) {
+                info!(
+                    "param file `{file}` changed: {} -> {}",
+                    previous.map_or("none".to_string(), |previous| previous.to_hex().to_string()),
+                    checksum.to_hex()
+                );
+            }
+        }
+
+        let prover = Arc::get_mut(&mut self.prover).context("reloading with tasks in flight")?;
+        match prover.isolated_prover.as_mut() {
+            Some(isolated_prover) => {
+                let isolated_prover = isolated_prover.get_mut().unwrap_or_else(|e| e.into_inner());
+                tokio::task::block_in_place(|| isolated_prover.restart())
+                    .context("restarting prover process")?
+            },
+            None => {
+                // Free the current params first, the process may not fit both sets in memory.
+                prover.provers_manager = ProversManager::new();
+                prover.provers_manager =
+                    create_provers_manager(self.config, &ProversReadiness::default(), &checksums)
+                        .context("reloading provers")?;
+            },
+        }
+        self.params_checksums = checksums;
+
+        counter!("zkmr_worker_params_reloads_total").increment(1);
+        info!("params reloaded");
+        Ok(())
+    }
+
+    /// Start `next`, the task queued behind another one with the same ID which was just replied
+    /// to, and in turn the ones queued behind it as long as they are replied to right away.
+    async fn start_queued<Id: Send + 'static, R: ResultSink<Id>>(
+        &mut self,
+        mut next: Option<Task<Id>>,
+        sink: &mut R,
+        in_flight: &mut JoinSet<Proven<Id>>,
+        in_flight_ids: &mut InFlightIds<Id>,
+    ) -> Result<()> {
+        while let Some(task) = next {
+            let key = task.key.clone();
+            let span = info_span!("task", uuid = %task.name);
+            if self
+                .start_task(task, sink, in_flight, in_flight_ids)
+                .instrument(span)
+                .await?
+            {
+                self.record_processed()?;
+                next = in_flight_ids.completed(key.as_deref());
             } else {
-                warp::reply::with_status("FAIL", warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+                if in_flight.len() == self.config.worker.max_concurrent_tasks {
+                    self.saturation.start_busy(Instant::now());
+                }
+                next = None;
             }
+        }
+        Ok(())
+    }
+
+    /// Start processing `task`, returning whether it was replied to right away, e.g. if it was
+    /// rejected or already completed; it is proven in `in_flight` otherwise, until which its ID is
+    /// recorded in `in_flight_ids`.
+    async fn start_task<Id: Send + 'static, R: ResultSink<Id>>(
+        &mut self,
+        task: Task<Id>,
+        sink: &mut R,
+        in_flight: &mut JoinSet<Proven<Id>>,
+        in_flight_ids: &mut InFlightIds<Id>,
+    ) -> Result<bool> {
+        let uuid = &task.name;
+        let tenants = &self.prover.tenants;
+        let tenant = tenants.tenant_of(&task.payload);
+        let audit_log = tenants.audit_log(tenant.as_deref(), &self.prover.audit_log);
+        let span = info_span!("task", task = %uuid, tenant = tracing::field::Empty);
+        if let Some(tenant) = &tenant {
+            span.record("tenant", tenant.as_str());
+        }
+        audit_log.record(AuditEvent::Received {
+            task: uuid,
+            size: task.payload.len(),
         });
-        let routes = readiness_route.or(liveness_route);
-        warp::serve(routes).run(([0, 0, 0, 0], 8080)).await;
-    });
 
-    loop {
-        debug!("Waiting for message...");
-        tokio::select! {
-            Some(inbound_message) = inbound.next() => {
-                let msg = match inbound_message {
-                    Ok(ref msg) => msg,
-                    Err(e) => {
-                        bail!("connection to the gateway ended with status: {e}");
-                    }
-                };
-                let result = process_message_from_gateway(&mut provers_manager, msg, &mut outbound, &mp2_requirement).await;
-                if result.is_ok() {
-                    last_task_processed.store(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(), Ordering::Relaxed);
+        if let Some(rejection) = &task.rejection {
+            span.in_scope(|| tracing::error!("failed to process task {uuid}: {rejection}"));
+            audit_log.record(AuditEvent::Rejected {
+                task: uuid,
+                reason: rejection,
+            });
+            tenants.record(
+                tenant.as_deref(),
+                "rejected",
+                task.received_at.elapsed().as_secs_f64(),
+            );
+            sink.send_result(&task, Err(rejection.clone())).await?;
+            return Ok(true);
+        }
+
+        if let Some(task_output) = self
+            .result_cache
+            .as_mut()
+            .and_then(|result_cache| result_cache.get(uuid))
+        {
+            info!("task {uuid} already completed, resending its reply");
+            counter!("zkmr_worker_result_cache_hits_total").increment(1);
+            audit_log.record(AuditEvent::Completed {
+                task: uuid,
+                reply_size: task_output.len(),
+                duration_ms: task.received_at.elapsed().as_millis() as u64,
+                cached: true,
+            });
+            tenants.record(
+                tenant.as_deref(),
+                "completed",
+                task.received_at.elapsed().as_secs_f64(),
+            );
+            sink.send_result(&task, Ok(task_output)).await?;
+            return Ok(true);
+        }
+
+        in_flight_ids.started(&task);
+        let prover = Arc::clone(&self.prover);
+        in_flight.spawn_blocking(move || prover.prove(task, tenant, span));
+        Ok(false)
+    }
+
+    /// Reply to the task proven in `proven`.
+    async fn complete_task<Id, R: ResultSink<Id>>(
+        &mut self,
+        proven: Proven<Id>,
+        sink: &mut R,
+    ) -> Result<()> {
+        let Proven {
+            task,
+            reply,
+            accepted,
+            unsupported,
+            tenant,
+            span,
+        } = proven;
+        let task = &task;
+        let uuid = &task.name;
+        let tenants = &self.prover.tenants;
+        let audit_log = tenants.audit_log(tenant.as_deref(), &self.prover.audit_log);
+
+        let task_type = reply
+            .as_ref()
+            .ok()
+            .map(|reply| reply.content().to_prover_type().to_string());
+
+        // Lets the gateway tell the time spent in the worker, queueing included, from the
+        // network round-trip.
+        let reply = reply.map(|mut reply| {
+            reply.set_worker_dwell_time(task.received_at.elapsed());
+            reply
+        });
+
+        let task_output = match (reply, &self.prover.proof_store) {
+            (Ok(reply), Some(proof_store)) => {
+                proof_store
+                    .encode_reply(reply)
+                    .await
+                    .map_err(|e| format!("{e:?}"))
+            },
+            (Ok(reply), None) => Ok(serde_json::to_vec(&reply)?),
+            (Err(e), _) => Err(e),
+        };
+
+        if let (Some(task_type), Ok(task_output)) = (task_type, &task_output) {
+            let task_size = task.payload.len();
+            let reply_size = task_output.len();
+            histogram!("zkmr_worker_task_size_bytes", "task_type" => task_type.clone())
+                .record(task_size as f64);
+            histogram!("zkmr_worker_reply_size_bytes", "task_type" => task_type.clone())
+                .record(reply_size as f64);
+            histogram!("zkmr_worker_reply_to_task_size_ratio", "task_type" => task_type)
+                .record(reply_size as f64 / task_size.max(1) as f64);
+        }
+
+        let task_output = task_output
+            .and_then(|task_output| check_reply_size(task_output, self.max_message_size));
+
+        if let (Some(result_cache), Ok(task_output)) = (&mut self.result_cache, &task_output) {
+            result_cache.insert(uuid.clone(), task_output.clone());
+        }
+
+        let duration_ms = task.received_at.elapsed().as_millis() as u64;
+        let outcome = match &task_output {
+            Ok(_) => "completed",
+            Err(_) if !accepted => "rejected",
+            Err(_) => "failed",
+        };
+        if accepted {
+            self.success_ratio.record(task_output.is_ok());
+        }
+        tenants.record(
+            tenant.as_deref(),
+            outcome,
+            task.received_at.elapsed().as_secs_f64(),
+        );
+        audit_log.record(match &task_output {
+            Ok(task_output) => {
+                AuditEvent::Completed {
+                    task: uuid,
+                    reply_size: task_output.len(),
+                    duration_ms,
+                    cached: false,
                 }
-                if let Err(e) = result {
-                    bail!("task processing failed: {e:?}");
+            },
+            Err(error) if !accepted => {
+                AuditEvent::Rejected {
+                    task: uuid,
+                    reason: error,
+                }
+            },
+            Err(error) => {
+                AuditEvent::Failed {
+                    task: uuid,
+                    error_code: classify(error),
+                    error,
+                    duration_ms,
+                }
+            },
+        });
+
+        if let Err(error_str) = &task_output {
+            span.in_scope(|| tracing::error!("failed to process task {uuid}: {error_str}"));
+            if let Some(failed_tasks) = &mut self.failed_tasks {
+                if let Err(e) = failed_tasks.record(uuid, &task.payload, error_str) {
+                    warn!("recording failed task {uuid}: {e:?}");
                 }
             }
-            else => {
-                bail!("inbound connection broken");
+        }
+        match (unsupported, task_output) {
+            // For the gateway to route the task to another worker.
+            (Some(reply), Err(error)) => {
+                sink.send_unsupported(task, serde_json::to_vec(&reply)?, error)
+                    .await?
+            },
+            (_, task_output) => sink.send_result(task, task_output).await?,
+        }
+
+        let late_reply = self
+            .prover
+            .isolated_prover
+            .as_ref()
+            .and_then(|isolated_prover| {
+                tokio::task::block_in_place(|| {
+                    isolated_prover
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .late_reply()
+                })
+            });
+        if let Some(reply) = late_reply {
+            self.send_late_reply(task, sink, reply).await?;
+        }
+        Ok(())
+    }
+
+    /// Send the proof of a task which exceeded its timeout, after the timeout error, for the
+    /// gateway to use it if still relevant.
+    async fn send_late_reply<Id, R: ResultSink<Id>>(
+        &mut self,
+        task: &Task<Id>,
+        sink: &mut R,
+        mut reply: MessageReplyEnvelope<ReplyType>,
+    ) -> Result<()> {
+        let uuid = &task.name;
+        reply.set_worker_dwell_time(task.received_at.elapsed());
+        let task_output = match &self.prover.proof_store {
+            Some(proof_store) => proof_store.encode_reply(reply).await,
+            None => Ok(serde_json::to_vec(&reply)?),
+        }
+        .map_err(|e| format!("{e:?}"))
+        .and_then(|task_output| check_reply_size(task_output, self.max_message_size));
+
+        match task_output {
+            Ok(task_output) => {
+                info!("task {uuid} proven late, sending its proof after its timeout error");
+                counter!("zkmr_worker_late_proofs_delivered_total").increment(1);
+                if let Some(result_cache) = &mut self.result_cache {
+                    result_cache.insert(uuid.clone(), task_output.clone());
+                }
+                sink.send_result(task, Ok(task_output)).await
+            },
+            Err(e) => {
+                warn!("task {uuid} proven late, but its proof can not be sent: {e}");
+                Ok(())
             },
         }
     }
 }
 
+impl TaskProver {
+    /// Check and prove `task`, on a thread of the proving pool.
+    fn prove<Id>(
+        &self,
+        task: Task<Id>,
+        tenant: Option<String>,
+        span: Span,
+    ) -> Proven<Id> {
+        let uuid = &task.name;
+        let audit_log = self.tenants.audit_log(tenant.as_deref(), &self.audit_log);
+        let default_timeout = self.config.worker.task_timeout.map(Duration::from_secs);
+        let late_proof_grace = self.config.worker.late_proof_grace.map(Duration::from_secs);
+        let mut unsupported = None;
+        let mut accepted = false;
+        let reply = span.in_scope(|| -> Result<MessageReplyEnvelope<ReplyType>, String> {
+            // Checked first, for version skews with the gateway not to fail opaquely.
+            check_message_class(&task.payload).map_err(|e| format!("task {uuid}: {e}"))?;
+            // The task payload is the most external-facing input of the worker, make sure that
+            // not even a panic while parsing it may bring the worker down.
+            std::panic::catch_unwind(|| {
+                serde_json::from_slice::<MessageEnvelope<TaskType>>(&task.payload)
+            })
+            .map_err(|_| format!("panic while deserializing envelope for task {uuid}"))
+            .and_then(|envelope| {
+                envelope.map_err(|e| {
+                    format!(
+                        "failed to deserialize envelope for task {} ({}B): {e}",
+                        uuid,
+                        task.payload.len(),
+                    )
+                })
+            })
+            .and_then(|message_envelope| {
+                // Checked before the proof references get resolved into the task.
+                verify_task_checksum(&task.payload, message_envelope.task_checksum.as_deref())
+                    .map_err(|e| format!("{}: {e}", message_envelope.id()))?;
+                record_inter_arrival(
+                    &mut self.last_arrivals.lock().unwrap_or_else(|e| e.into_inner()),
+                    message_envelope.inner().to_prover_type(),
+                    task.received_at,
+                );
+                check_allowed_table(&self.config.worker.allowed_tables, message_envelope.inner())
+                    .map_err(|e| format!("{}: {e}", message_envelope.id()))?;
+                let prover_type = message_envelope.inner().to_prover_type();
+                self.provers_manager
+                    .check_accepted(prover_type)
+                    .map_err(|e| {
+                        counter!("zkmr_worker_tasks_rejected_total", "reason" => "unsupported_class")
+                            .increment(1);
+                        let error = format!("{e} ({})", message_envelope.id());
+                        unsupported = Some(
+                            MessageReplyEnvelope::new(
+                                message_envelope.query_id.clone(),
+                                message_envelope.task_id.clone(),
+                                ReplyType::Unsupported(prover_type),
+                            )
+                            .with_error(WorkerError::GeneralError(error.clone())),
+                        );
+                        error
+                    })?;
+                let timeout = task_timeout(
+                    message_envelope.deadline(),
+                    default_timeout,
+                    SystemTime::now(),
+                )
+                .map_err(|e| format!("{}: {e}", message_envelope.id()))?;
+                audit_log.record(AuditEvent::Accepted {
+                    task: uuid,
+                    id: &message_envelope.id(),
+                    class: message_envelope.inner().to_prover_type().to_string(),
+                    table_id: message_envelope.inner().table_id(),
+                });
+                accepted = true;
+                Ok((message_envelope, timeout))
+            })
+            .and_then(|(mut message_envelope, timeout)| {
+                tokio::runtime::Handle::current()
+                    .block_on(resolve_proof_references(
+                        &mut message_envelope,
+                        self.proof_store.as_ref(),
+                    ))
+                    .map_err(|e| format!("resolving proofs of {}: {e:?}", message_envelope.id()))?;
+                Ok((message_envelope, timeout))
+            })
+            .and_then(|(message_envelope, timeout)| {
+                // Ends with proving, whether the task succeeds, fails or panics.
+                let _in_flight = TaskInFlight::start(
+                    message_envelope.inner().to_prover_type(),
+                    task.received_at,
+                );
+                info!("processing task {uuid} ({})", message_envelope.id());
+                audit_log.record(AuditEvent::Started { task: uuid });
+                match &self.isolated_prover {
+                    Some(isolated_prover) => {
+                        isolated_prover
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .prove(&message_envelope, timeout)
+                    },
+                    None => {
+                        // Proving can not be interrupted in process: a late proof is sent in
+                        // place of the timeout error if it came within the grace, and is useless
+                        // otherwise.
+                        let start = Instant::now();
+                        let capture = self.flamegraphs.as_deref().and_then(Flamegraphs::start);
+                        let reply = process_downstream_payload(
+                            &self.provers_manager,
+                            message_envelope,
+                            &self.mp2_requirement,
+                            &self.panic_policy,
+                            &self.config.proof_encoding,
+                            &self.config.proof_compression,
+                            self.config.worker.resource_usage,
+                            self.config.worker.content_hash,
+                        );
+                        if let Some(capture) = capture {
+                            capture.finish(uuid);
+                        }
+                        match timeout {
+                            Some(timeout) if start.elapsed() > timeout => {
+                                counter!(
+                                    "zkmr_worker_tasks_deadline_exceeded_total",
+                                    "stage" => "proving"
+                                )
+                                .increment(1);
+                                let within_grace = late_proof_grace
+                                    .is_some_and(|grace| start.elapsed() <= timeout + grace);
+                                match reply {
+                                    Ok(reply) if within_grace => {
+                                        info!("task {uuid} proven late, within its grace");
+                                        counter!("zkmr_worker_late_proofs_delivered_total")
+                                            .increment(1);
+                                        Ok(reply)
+                                    },
+                                    _ => {
+                                        Err(format!(
+                                            "proving took {:?}, over its {timeout:?} timeout",
+                                            start.elapsed()
+                                        ))
+                                    },
+                                }
+                            },
+                            _ => reply,
+                        }
+                    },
+                }
+            })
+        });
+
+        Proven {
+            task,
+            reply,
+            accepted,
+            unsupported,
+            tenant,
+            span,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn process_downstream_payload(
     provers_manager: &ProversManager<TaskType, ReplyType>,
     envelope: MessageEnvelope<TaskType>,
     mp2_requirement: &semver::VersionReq,
+    panic_policy: &PanicPolicy,
+    proof_encoding: &ProofEncodingConfig,
+    proof_compression: &ProofCompressionConfig,
+    measure_resources: bool,
+    hash_content: bool,
 ) -> Result<MessageReplyEnvelope<ReplyType>, String> {
     let span = span!(
         Level::INFO,
@@ -333,6 +1889,7 @@ fn process_downstream_payload(
         "query_id" = envelope.query_id,
         "task_id" = envelope.task_id,
         "db_id" = ?envelope.db_task_id,
+        "tenant" = ?envelope.tenant_id,
     );
     let _guard = span.enter();
 
@@ -349,16 +1906,41 @@ fn process_downstream_payload(
         ));
     }
 
-    match std::panic::catch_unwind(|| provers_manager.delegate_proving(&envelope)) {
+    let sampler = measure_resources.then(ResourceSampler::start).flatten();
+    match delegate_proving_retrying(provers_manager, &envelope, panic_policy) {
         Ok(result) => {
             match result {
-                Ok(reply) => {
+                Ok(mut reply) => {
+                    if let Some(usage) = sampler.and_then(ResourceSampler::finish) {
+                        reply.set_resource_usage(usage);
+                    }
+                    if hash_content {
+                        let hash = content_hash(reply.content()).map_err(|e| format!("{e:?}"))?;
+                        reply.set_content_hash(hash);
+                    }
+                    let prover_type = envelope.inner.to_prover_type();
+                    if let Some(worker_reply) = reply.content_mut().worker_reply_mut() {
+                        let compression = proof_compression.get(prover_type);
+                        let ratio = worker_reply
+                            .compress_proof(
+                                compression.algorithm,
+                                compression.level,
+                                compression.min_size,
+                            )
+                            .map_err(|e| format!("{e:?}"))?;
+                        if let Some(ratio) = ratio {
+                            let labels = vec![("task_type", prover_type.to_string())];
+                            histogram!("zkmr_worker_proof_compression_ratio", &labels)
+                                .record(ratio);
+                        }
+                        worker_reply.encode_proof(proof_encoding.get(prover_type));
+                    }
                     trace!("Sending reply: {:?}", reply);
                     counter!("zkmr_worker_tasks_processed_total").increment(1);
                     Ok(reply)
                 },
                 Err(e) => {
-                    error!("Error processing task: {:?}", e);
+                    log_task_error(&e.to_string(), || error!("Error processing task: {:?}", e));
                     counter!("zkmr_worker_error_count", "error_type" =>  "proof processing")
                         .increment(1);
 
@@ -373,105 +1955,352 @@ fn process_downstream_payload(
             )
             .increment(1);
 
-            let msg = match panic.downcast_ref::<&'static str>() {
-                Some(s) => *s,
-                None => {
-                    match panic.downcast_ref::<String>() {
-                        Some(s) => &s[..],
-                        None => "Box<dyn Any>",
-                    }
-                },
-            };
+            let (payload_kind, msg) = panic_message(panic.as_ref());
+            counter!("zkmr_worker_panics_total", "payload_kind" => payload_kind).increment(1);
 
-            error!("panic encountered while proving {} : {msg}", envelope.id());
+            if panic_policy.record(msg) {
+                counter!("zkmr_worker_fatal_panics_total").increment(1);
+                error!(
+                    "fatal panic encountered while proving {} : {msg}",
+                    envelope.id()
+                );
+            } else {
+                log_task_error(&format!("panic encountered while proving: {msg}"), || {
+                    error!("panic encountered while proving {} : {msg}", envelope.id())
+                });
+            }
             Err(format!("{}: {msg}", envelope.id()))
         },
     }
 }
 
-async fn process_message_from_gateway(
-    provers_manager: &mut ProversManager<TaskType, ReplyType>,
-    message: &WorkerToGwResponse,
-    outbound: &mut tokio::sync::mpsc::Sender<WorkerToGwRequest>,
-    mp2_requirement: &semver::VersionReq,
-) -> Result<()> {
-    let uuid = message
-        .task_id
-        .as_ref()
-        .map(|id| uuid::Uuid::from_bytes_le(id.id.clone().try_into().unwrap()).to_string())
-        .unwrap_or_else(|| "UNKNOWN".to_string());
-
-    let reply = {
-        let uuid = uuid.clone();
-        tokio::task::block_in_place(move || -> Result<MessageReplyEnvelope<ReplyType>, String> {
-            serde_json::from_slice::<MessageEnvelope<TaskType>>(&message.task)
-                .map_err(|e| {
-                    format!(
-                        "failed to deserialize envelope for task {} ({}B): {e}",
-                        uuid,
-                        message.task.len(),
-                    )
-                })
-                .and_then(|message_envelope| {
-                    info!("processing task {}", message_envelope.id());
-                    process_downstream_payload(provers_manager, message_envelope, mp2_requirement)
-                })
-        })
+/// Log the error of a task with `log`, unless rate limited as the occurrences of `message`, which
+/// leaves the details of the task out for the similar errors to be counted together.
+fn log_task_error(
+    message: &str,
+    log: impl FnOnce(),
+) {
+    if TASK_LOG_LIMITER
+        .get()
+        .is_none_or(|limiter| limiter.allow(message, Instant::now()))
+    {
+        log();
+    }
+}
+
+/// Prove `envelope`, catching panics, and proving it once more if it panicked in a way the
+/// `panic_policy` deems spurious.
+fn delegate_proving_retrying(
+    provers_manager: &ProversManager<TaskType, ReplyType>,
+    envelope: &MessageEnvelope<TaskType>,
+    panic_policy: &PanicPolicy,
+) -> std::thread::Result<Result<MessageReplyEnvelope<ReplyType>>> {
+    let result = catch_proving_panics(provers_manager, envelope);
+    let Err(panic) = &result else {
+        return result;
     };
+    let (_, msg) = panic_message(panic.as_ref());
+    if !panic_policy.retries(msg) {
+        return result;
+    }
 
-    let outbound_msg = match reply {
-        Ok(reply) => {
-            WorkerToGwRequest {
-                request: Some(lagrange::worker_to_gw_request::Request::WorkerDone(
-                    WorkerDone {
-                        task_id: message.task_id.clone(),
-                        reply: Some(Reply::TaskOutput(serde_json::to_vec(&reply)?)),
-                    },
-                )),
-            }
+    // Loud on purpose: retrying hides a bug in the circuits, which must remain visible.
+    error!(
+        "panic encountered while proving {}, retrying it once: {msg}",
+        envelope.id()
+    );
+    counter!("zkmr_worker_panic_retries_total").increment(1);
+    catch_proving_panics(provers_manager, envelope)
+}
+
+/// Prove `envelope`, catching panics, whose backtraces are logged as `worker.task_panic_backtrace`
+/// directs.
+fn catch_proving_panics(
+    provers_manager: &ProversManager<TaskType, ReplyType>,
+    envelope: &MessageEnvelope<TaskType>,
+) -> std::thread::Result<Result<MessageReplyEnvelope<ReplyType>>> {
+    PROVING.fetch_add(1, Ordering::Relaxed);
+    let result = std::panic::catch_unwind(|| provers_manager.delegate_proving(envelope));
+    PROVING.fetch_sub(1, Ordering::Relaxed);
+    result
+}
+
+/// The backtrace of a panic, as detailed as `verbosity` asks.
+fn capture_backtrace(verbosity: BacktraceVerbosity) -> Option<Backtrace> {
+    match verbosity {
+        BacktraceVerbosity::None => None,
+        BacktraceVerbosity::Short => {
+            // Only the kept frames are resolved, which is the costly part.
+            let frames = Backtrace::new_unresolved()
+                .frames()
+                .iter()
+                .take(SHORT_BACKTRACE_FRAMES)
+                .cloned()
+                .collect::<Vec<_>>();
+            let mut backtrace = Backtrace::from(frames);
+            backtrace.resolve();
+            Some(backtrace)
         },
-        Err(error_str) => {
-            tracing::error!("failed to process task {uuid}: {error_str}");
-            WorkerToGwRequest {
-                request: Some(lagrange::worker_to_gw_request::Request::WorkerDone(
-                    WorkerDone {
-                        task_id: message.task_id.clone(),
-                        reply: Some(Reply::WorkerError(error_str)),
-                    },
-                )),
+        BacktraceVerbosity::Full => Some(Backtrace::new()),
+    }
+}
+
+/// The kind of payload of `panic` and its message.
+///
+/// Unrecognized payload types usually point to a dependency panicking with a custom type, hence
+/// they are tracked separately.
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> (&'static str, &str) {
+    match panic.downcast_ref::<&'static str>() {
+        Some(s) => ("str", *s),
+        None => {
+            match panic.downcast_ref::<String>() {
+                Some(s) => ("string", &s[..]),
+                None => ("other", "Box<dyn Any>"),
             }
         },
+    }
+}
+
+/// Fail the replies too large for the gateway stream with a descriptive error, instead of letting
+/// the transport fail to send them.
+fn check_reply_size(
+    task_output: Vec<u8>,
+    max_message_size: usize,
+) -> Result<Vec<u8>, String> {
+    if task_output.len() <= max_message_size {
+        return Ok(task_output);
+    }
+
+    counter!("zkmr_worker_replies_too_large_total").increment(1);
+    Err(format!(
+        "the reply is {}B, over the {}B gateway message size limit; configure a proof store to \
+         send large proofs out-of-band",
+        task_output.len(),
+        max_message_size
+    ))
+}
+
+/// Record the time since the previous task of the same class was received, returning it.
+///
+/// Keyed by [`ProverType`], the histogram has one series per task class. It is recorded here
+/// rather than while proving, which may happen in a prover process without a metrics exporter.
+fn record_inter_arrival(
+    last_arrivals: &mut HashMap<ProverType, Instant>,
+    prover_type: ProverType,
+    received_at: Instant,
+) -> Option<Duration> {
+    let previous = last_arrivals.insert(prover_type, received_at)?;
+    let inter_arrival = received_at.saturating_duration_since(previous);
+    let labels = vec![("task_type", prover_type.to_string())];
+    histogram!("zkmr_worker_task_inter_arrival_seconds", &labels)
+        .record(inter_arrival.as_secs_f64());
+    Some(inter_arrival)
+}
+
+/// A task being proven, counted by the `zkmr_worker_tasks_in_flight` gauge until dropped.
+///
+/// Recorded here rather than while proving for the same reason as the inter-arrival time.
+struct TaskInFlight {
+    message_class: String,
+}
+
+impl TaskInFlight {
+    /// Start proving a task of class `prover_type`, received from its source at `received_at`.
+    fn start(
+        prover_type: ProverType,
+        received_at: Instant,
+    ) -> Self {
+        let message_class = prover_type.to_string();
+        histogram!("zkmr_worker_task_queue_wait_seconds", "message_class" => message_class.clone())
+            .record(received_at.elapsed().as_secs_f64());
+        gauge!("zkmr_worker_tasks_in_flight", "message_class" => message_class.clone())
+            .increment(1);
+        Self { message_class }
+    }
+}
+
+impl Drop for TaskInFlight {
+    fn drop(&mut self) {
+        gauge!("zkmr_worker_tasks_in_flight", "message_class" => self.message_class.clone())
+            .decrement(1);
+    }
+}
+
+/// The longest a task may take to prove: the tightest of `default_timeout` and of the time left
+/// until the `deadline` set by the gateway, failing if the latter has passed already.
+fn task_timeout(
+    deadline: Option<SystemTime>,
+    default_timeout: Option<Duration>,
+    now: SystemTime,
+) -> Result<Option<Duration>, String> {
+    let remaining = match deadline.map(|deadline| deadline.duration_since(now)) {
+        None => None,
+        Some(Ok(remaining)) if !remaining.is_zero() => Some(remaining),
+        Some(Ok(_)) | Some(Err(_)) => {
+            counter!("zkmr_worker_tasks_deadline_exceeded_total", "stage" => "receipt")
+                .increment(1);
+            return Err("the task deadline has passed already".to_string());
+        },
     };
-    outbound.send(outbound_msg).await?;
 
-    counter!("zkmr_worker_grpc_messages_sent_total",
-                                    "message_type" => "text")
-    .increment(1);
+    Ok(match (remaining, default_timeout) {
+        (Some(remaining), Some(default_timeout)) => Some(remaining.min(default_timeout)),
+        (remaining, default_timeout) => remaining.or(default_timeout),
+    })
+}
+
+/// The task of an envelope, as received.
+#[derive(Deserialize)]
+struct RawTask<'a> {
+    #[serde(borrow)]
+    inner: &'a RawValue,
+}
+
+/// Ensure that the task of the envelope `payload` matches its checksum, if any.
+///
+/// The task is hashed as received: serializing it again would not be byte for byte identical,
+/// e.g. the placeholders of query tasks are serialized in no particular order.
+fn verify_task_checksum(
+    payload: &[u8],
+    expected: Option<&str>,
+) -> Result<(), String> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let task = serde_json::from_slice::<RawTask>(payload)
+        .map_err(|e| format!("reading task to checksum: {e}"))?;
+    let actual = blake3::hash(task.inner.get().as_bytes());
+    if !actual.to_hex().eq_ignore_ascii_case(expected) {
+        counter!("zkmr_worker_task_checksum_mismatches_total").increment(1);
+        return Err(format!(
+            "task checksum mismatch, expected {expected} but computed {actual}"
+        ));
+    }
     Ok(())
 }
 
-fn get_wallet(config: &Config) -> Result<Wallet<SigningKey>> {
-    let res = match (
-        &config.avs.lagr_keystore,
-        &config.avs.lagr_pwd,
-        &config.avs.lagr_private_key,
-    ) {
-        (Some(keystore_path), Some(password), None) => {
-            read_keystore(keystore_path, password.expose_secret())?
+/// Reject the tasks of the tables outside of `worker.allowed_tables`, if set.
+fn check_allowed_table(
+    allowed_tables: &[TableId],
+    task: &TaskType,
+) -> Result<(), String> {
+    match task.table_id() {
+        Some(table_id) if !allowed_tables.is_empty() && !allowed_tables.contains(&table_id) => {
+            counter!("zkmr_worker_tasks_rejected_total", "reason" => "table_not_allowed")
+                .increment(1);
+            Err(format!("table {table_id} is not served by this worker"))
         },
-        (Some(_), None, Some(pkey)) => {
+        _ => Ok(()),
+    }
+}
+
+/// Tells the panics while proving which leave the process unfit to prove further tasks, see
+/// `worker.fatal_panics`, from those which only concern the task at hand, and the latter which
+/// are worth a retry, see `worker.retry_panics`.
+pub(crate) struct PanicPolicy {
+    fatal_patterns: Vec<String>,
+    retry_patterns: Vec<String>,
+    tripped: AtomicBool,
+}
+
+impl PanicPolicy {
+    pub(crate) fn new(
+        fatal_patterns: Vec<String>,
+        retry_patterns: Vec<String>,
+    ) -> Self {
+        Self {
+            fatal_patterns,
+            retry_patterns,
+            tripped: AtomicBool::new(false),
+        }
+    }
+
+    /// Record a panic with message `msg`, returning whether it is fatal.
+    fn record(
+        &self,
+        msg: &str,
+    ) -> bool {
+        let fatal = self.is_fatal(msg);
+        if fatal {
+            self.tripped.store(true, Ordering::Relaxed);
+        }
+        fatal
+    }
+
+    /// Whether the task which panicked with message `msg` should be proven once more.
+    fn retries(
+        &self,
+        msg: &str,
+    ) -> bool {
+        !self.is_fatal(msg) && matches_any(&self.retry_patterns, msg)
+    }
+
+    fn is_fatal(
+        &self,
+        msg: &str,
+    ) -> bool {
+        matches_any(&self.fatal_patterns, msg)
+    }
+
+    /// Whether a fatal panic has been recorded.
+    pub(crate) fn tripped(&self) -> bool {
+        self.tripped.load(Ordering::Relaxed)
+    }
+}
+
+fn matches_any(
+    patterns: &[String],
+    msg: &str,
+) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| msg.contains(pattern.as_str()))
+}
+
+/// Build the TLS configuration of the gateway channel, trusting the configured CA bundle on top of
+/// the bundled roots.
+fn tls_config(config: &Config) -> Result<ClientTlsConfig> {
+    let mut tls_config = ClientTlsConfig::new().with_enabled_roots();
+
+    if let Some(ca_file) = &config.avs.gateway_ca_file {
+        let pem = std::fs::read(ca_file).with_context(|| format!("reading {ca_file}"))?;
+        let certificates = CertificateDer::pem_slice_iter(&pem)
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("parsing {ca_file}"))?;
+        ensure!(
+            !certificates.is_empty(),
+            "no certificate found in {ca_file}"
+        );
+        info!(
+            "trusting {} certificate(s) from {ca_file} for the gateway",
+            certificates.len()
+        );
+        tls_config = tls_config.ca_certificate(Certificate::from_pem(pem));
+    }
+
+    if let Some(domain) = &config.avs.gateway_domain {
+        tls_config = tls_config.domain_name(domain);
+    }
+
+    Ok(tls_config)
+}
+
+fn get_wallet(config: &Config) -> Result<Wallet<SigningKey>> {
+    let wallet = match config.avs.wallet_key().map_err(|e| anyhow!(e))? {
+        WalletKey::Keystore { path, password } => read_keystore(path, password.expose_secret())?,
+        WalletKey::PrivateKey(pkey) => {
             Wallet::from_str(pkey.expose_secret()).context("Failed to create wallet")?
         },
-        _ => bail!("Must specify either keystore path w/ password OR private key"),
     };
 
-    Ok(res)
+    Ok(wallet)
 }
 
 fn get_claims(config: &Config) -> Result<Claims> {
     let registered = RegisteredClaims {
         issuer: Some(config.avs.issuer.clone()),
         subject: Some(config.avs.worker_id.clone()),
+        audience: config.avs.audience.clone(),
         issued_at: Some(
             SystemTime::now()
                 .duration_since(UNIX_EPOCH)
@@ -481,15 +2310,454 @@ fn get_claims(config: &Config) -> Result<Claims> {
         ..Default::default()
     };
 
-    let private = [(
+    let mut private = config.avs.extra_claims.clone();
+    private.insert(
         "worker_class".to_string(),
         serde_json::Value::String(config.worker.instance_type.to_string()),
-    )]
-    .into_iter()
-    .collect::<BTreeMap<String, serde_json::Value>>();
+    );
 
     Ok(Claims {
         registered,
         private,
     })
 }
+
+/// Ensure that `token` decodes back to `claims` and is signed by `wallet`, so that key or
+/// configuration issues are reported before the gateway rejects the connection.
+fn check_token(
+    token: &str,
+    claims: &Claims,
+    wallet: &Wallet<SigningKey>,
+) -> Result<()> {
+    let decoded = JWTAuth::decode(token).context("decoding token")?;
+    ensure!(
+        decoded.claims().registered.issuer == claims.registered.issuer
+            && decoded.claims().registered.subject == claims.registered.subject
+            && decoded.claims().private == claims.private,
+        "decoded claims do not match: {:?}",
+        decoded.claims()
+    );
+    ensure!(
+        decoded.claims().registered.audience == claims.registered.audience,
+        "token audience is {:?}, expected {:?}",
+        decoded.claims().registered.audience,
+        claims.registered.audience
+    );
+
+    let signer = decoded
+        .recover_public_key()
+        .context("recovering token signer")?;
+    let expected = PublicKey::from(wallet.signer().verifying_key()).to_hex();
+    ensure!(
+        signer == expected,
+        "token signed by {signer}, expected {expected}"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use lgn_messages::routing::RoutingKey;
+    use lgn_messages::types::v1::preprocessing::WorkerTask;
+    use lgn_messages::types::v1::preprocessing::WorkerTaskType;
+    use lgn_messages::types::v1::query;
+    use lgn_messages::types::v1::query::tasks::Hydratable;
+    use lgn_messages::types::v1::query::tasks::QueryInput;
+    use lgn_messages::types::v1::query::tasks::QueryStep;
+    use lgn_messages::types::v1::query::tasks::RevelationInput;
+    use lgn_messages::types::ProofCategory;
+    use lgn_messages::types::ProofEncoding;
+    use lgn_messages::types::WorkerReply;
+    use lgn_provers::provers::LgnProver;
+
+    use super::*;
+
+    #[test]
+    fn test_check_token_audience() {
+        let wallet =
+            Wallet::from_str("0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef")
+                .unwrap();
+        let mut config = Config::load(None).unwrap();
+        config.avs.audience = Some("gateway".to_string());
+        let claims = get_claims(&config).unwrap();
+        let token = JWTAuth::new(claims.clone(), &wallet)
+            .unwrap()
+            .encode()
+            .unwrap();
+        check_token(&token, &claims, &wallet).unwrap();
+
+        let mut expected = claims;
+        expected.registered.audience = Some("other-gateway".to_string());
+        let err = check_token(&token, &expected, &wallet).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"token audience is Some("gateway"), expected Some("other-gateway")"#
+        );
+    }
+
+    #[test]
+    fn test_parse_mp2_version() {
+        assert_eq!(
+            parse_mp2_version("1.2.3").unwrap(),
+            semver::Version::new(1, 2, 3)
+        );
+
+        let err = parse_mp2_version("v1.2.3-4-gdeadbeef").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the linked verifiable_db reports an unparseable version: `v1.2.3-4-gdeadbeef`"
+        );
+    }
+
+    #[test]
+    fn test_check_reply_size() {
+        let limit = MAX_GRPC_MESSAGE_SIZE_MB * 1024 * 1024;
+        assert_eq!(
+            check_reply_size(vec![0; limit], limit).unwrap().len(),
+            limit
+        );
+
+        let err = check_reply_size(vec![0; limit + 1], limit).unwrap_err();
+        assert!(
+            err.starts_with(
+                "the reply is 16777217B, over the 16777216B gateway message size limit"
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_check_allowed_table() {
+        let ivc = |table_id| {
+            TaskType::V1Preprocessing(WorkerTask::new(
+                1,
+                2,
+                WorkerTaskType::ivc(table_id, 2, false),
+            ))
+        };
+
+        check_allowed_table(&[], &ivc(7)).unwrap();
+        check_allowed_table(&[3, 7], &ivc(7)).unwrap();
+        assert_eq!(
+            check_allowed_table(&[3, 7], &ivc(8)).unwrap_err(),
+            "table 8 is not served by this worker"
+        );
+
+        // Tasks not tied to a table are not restricted.
+        let block =
+            TaskType::V1Preprocessing(WorkerTask::new(1, 2, WorkerTaskType::ext_block(vec![])));
+        check_allowed_table(&[3], &block).unwrap();
+    }
+
+    #[test]
+    fn test_panic_policy() {
+        let policy = PanicPolicy::new(vec!["memory allocation".to_string()], vec![]);
+        assert!(!policy.record("index out of bounds: the len is 3 but the index is 4"));
+        assert!(!policy.tripped());
+        assert!(policy.record("memory allocation of 1073741824 bytes failed"));
+        assert!(policy.tripped());
+
+        // Every panic is recoverable by default.
+        let policy = PanicPolicy::new(vec![], vec![]);
+        assert!(!policy.record("memory allocation of 1073741824 bytes failed"));
+        assert!(!policy.tripped());
+    }
+
+    /// Panics on its first `panics` tasks.
+    struct FlakyProver {
+        panics: AtomicUsize,
+    }
+
+    impl LgnProver<TaskType, ReplyType> for FlakyProver {
+        fn run(
+            &self,
+            envelope: &MessageEnvelope<TaskType>,
+        ) -> Result<MessageReplyEnvelope<ReplyType>> {
+            let remaining = self.panics.load(Ordering::Relaxed);
+            if remaining > 0 {
+                self.panics.store(remaining - 1, Ordering::Relaxed);
+                panic!("spurious failure in the circuit");
+            }
+            let reply = WorkerReply::new(1, None, ProofCategory::Indexing);
+            Ok(MessageReplyEnvelope::new(
+                envelope.query_id.clone(),
+                envelope.task_id.clone(),
+                ReplyType::V1Preprocessing(reply),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_delegate_proving_retrying() {
+        let envelope = MessageEnvelope::new(
+            "query".to_string(),
+            "task".to_string(),
+            TaskType::V1Preprocessing(WorkerTask::new(1, 2, WorkerTaskType::ivc(7, 2, false))),
+            RoutingKey::combined("domain".to_string(), 0),
+            "1.0.0".to_string(),
+        );
+        let prove = |panics, panic_policy: &PanicPolicy| {
+            let mut provers_manager = ProversManager::new();
+            provers_manager.add_prover(
+                ProverType::V1Preprocessing,
+                Box::new(FlakyProver {
+                    panics: AtomicUsize::new(panics),
+                }),
+            );
+            delegate_proving_retrying(&provers_manager, &envelope, panic_policy)
+        };
+
+        let retrying = PanicPolicy::new(vec![], vec!["spurious failure".to_string()]);
+        assert!(prove(1, &retrying).unwrap().is_ok());
+        // The task is retried only once.
+        assert!(prove(2, &retrying).is_err());
+
+        // Without a matching pattern, panics are not retried.
+        let policy = PanicPolicy::new(vec![], vec!["memory allocation".to_string()]);
+        assert!(prove(1, &policy).is_err());
+
+        // Nor are fatal panics.
+        let fatal = PanicPolicy::new(
+            vec!["circuit".to_string()],
+            vec!["spurious failure".to_string()],
+        );
+        assert!(prove(1, &fatal).is_err());
+    }
+
+    #[test]
+    fn test_capture_backtrace() {
+        assert!(capture_backtrace(BacktraceVerbosity::None).is_none());
+        let short = capture_backtrace(BacktraceVerbosity::Short).unwrap();
+        assert!(!short.frames().is_empty());
+        assert!(short.frames().len() <= SHORT_BACKTRACE_FRAMES);
+        assert!(!capture_backtrace(BacktraceVerbosity::Full)
+            .unwrap()
+            .frames()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_resource_usage() {
+        let envelope = MessageEnvelope::new(
+            "query".to_string(),
+            "task".to_string(),
+            TaskType::V1Preprocessing(WorkerTask::new(1, 2, WorkerTaskType::ivc(7, 2, false))),
+            RoutingKey::combined("domain".to_string(), 0),
+            "1.0.0".to_string(),
+        );
+        let mut provers_manager = ProversManager::new();
+        provers_manager.add_prover(
+            ProverType::V1Preprocessing,
+            Box::new(FlakyProver {
+                panics: AtomicUsize::new(0),
+            }),
+        );
+        let prove = |measure_resources| {
+            process_downstream_payload(
+                &provers_manager,
+                envelope.clone(),
+                &semver::VersionReq::STAR,
+                &PanicPolicy::new(vec![], vec![]),
+                &ProofEncodingConfig::default(),
+                &ProofCompressionConfig::default(),
+                measure_resources,
+                false,
+            )
+            .unwrap()
+        };
+
+        let usage = *prove(true).resource_usage().unwrap();
+        assert!(usage.peak_rss_bytes > 0);
+        // The CPU time covers the whole test process, only a sane bound can be checked.
+        assert!(usage.cpu_time_ms < 60_000);
+
+        assert_eq!(prove(false).resource_usage(), None);
+    }
+
+    /// A deterministic prover, whose proofs are the IDs of their tasks.
+    struct EchoProver;
+
+    impl LgnProver<TaskType, ReplyType> for EchoProver {
+        fn run(
+            &self,
+            envelope: &MessageEnvelope<TaskType>,
+        ) -> Result<MessageReplyEnvelope<ReplyType>> {
+            let proof = ("proof".to_string(), envelope.task_id.as_bytes().to_vec());
+            let reply = WorkerReply::new(1, Some(proof), ProofCategory::Indexing);
+            Ok(MessageReplyEnvelope::new(
+                envelope.query_id.clone(),
+                envelope.task_id.clone(),
+                ReplyType::V1Preprocessing(reply),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let envelope = |task_id: &str| {
+            MessageEnvelope::new(
+                "query".to_string(),
+                task_id.to_string(),
+                TaskType::V1Preprocessing(WorkerTask::new(1, 2, WorkerTaskType::ivc(7, 2, false))),
+                RoutingKey::combined("domain".to_string(), 0),
+                "1.0.0".to_string(),
+            )
+        };
+        let mut provers_manager = ProversManager::new();
+        provers_manager.add_prover(ProverType::V1Preprocessing, Box::new(EchoProver));
+        let prove = |envelope, encoding, hash_content| {
+            process_downstream_payload(
+                &provers_manager,
+                envelope,
+                &semver::VersionReq::STAR,
+                &PanicPolicy::new(vec![], vec![]),
+                &ProofEncodingConfig {
+                    preprocessing: encoding,
+                    ..Default::default()
+                },
+                &ProofCompressionConfig::default(),
+                false,
+                hash_content,
+            )
+            .unwrap()
+        };
+
+        let reply = prove(envelope("task"), ProofEncoding::Raw, true);
+        let hash = reply.content_hash().unwrap();
+        assert_eq!(
+            prove(envelope("task"), ProofEncoding::Raw, true).content_hash(),
+            Some(hash)
+        );
+        // The proof is hashed before being encoded.
+        assert_eq!(
+            prove(envelope("task"), ProofEncoding::Hex, true).content_hash(),
+            Some(hash)
+        );
+        assert_ne!(
+            prove(envelope("other"), ProofEncoding::Raw, true).content_hash(),
+            Some(hash)
+        );
+
+        assert_eq!(
+            prove(envelope("task"), ProofEncoding::Raw, false).content_hash(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_record_inter_arrival() {
+        let mut last_arrivals = HashMap::new();
+        let start = Instant::now();
+        let later = start + Duration::from_secs(3);
+
+        assert_eq!(
+            record_inter_arrival(&mut last_arrivals, ProverType::V1Query, start),
+            None
+        );
+        assert_eq!(
+            record_inter_arrival(&mut last_arrivals, ProverType::V1Groth16, later),
+            None
+        );
+        assert_eq!(
+            record_inter_arrival(&mut last_arrivals, ProverType::V1Query, later),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(last_arrivals.len(), 2);
+    }
+
+    #[test]
+    fn test_task_timeout_past_deadline() {
+        let now = SystemTime::now();
+        let default_timeout = Some(Duration::from_secs(60));
+
+        for deadline in [now, now - Duration::from_secs(1)] {
+            assert_eq!(
+                task_timeout(Some(deadline), default_timeout, now),
+                Err("the task deadline has passed already".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_task_timeout() {
+        let now = SystemTime::now();
+        let deadline = Some(now + Duration::from_secs(10));
+
+        // A deadline tighter than the default timeout takes precedence.
+        assert_eq!(
+            task_timeout(deadline, Some(Duration::from_secs(60)), now),
+            Ok(Some(Duration::from_secs(10)))
+        );
+        assert_eq!(
+            task_timeout(deadline, Some(Duration::from_secs(5)), now),
+            Ok(Some(Duration::from_secs(5)))
+        );
+        assert_eq!(
+            task_timeout(deadline, None, now),
+            Ok(Some(Duration::from_secs(10)))
+        );
+        assert_eq!(
+            task_timeout(None, Some(Duration::from_secs(60)), now),
+            Ok(Some(Duration::from_secs(60)))
+        );
+        assert_eq!(task_timeout(None, None, now), Ok(None));
+    }
+
+    /// Serialize `task` into an envelope, along with its checksum, as the gateway does.
+    fn checksummed_payload(task: TaskType) -> (Vec<u8>, String) {
+        let mut envelope = MessageEnvelope::new(
+            "query".to_string(),
+            "task".to_string(),
+            task,
+            RoutingKey::combined("domain".to_string(), 0),
+            "1.0.0".to_string(),
+        );
+        let checksum = blake3::hash(&serde_json::to_vec(envelope.inner()).unwrap());
+        envelope.task_checksum = Some(checksum.to_hex().to_string());
+        (
+            serde_json::to_vec(&envelope).unwrap(),
+            envelope.task_checksum.unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_verify_task_checksum() {
+        let task =
+            TaskType::V1Preprocessing(WorkerTask::new(1, 2, WorkerTaskType::ivc(7, 2, false)));
+        let (payload, checksum) = checksummed_payload(task);
+        verify_task_checksum(&payload, None).unwrap();
+        verify_task_checksum(&payload, Some(&checksum)).unwrap();
+
+        // Corrupt the chain ID of the task in transit.
+        let payload = String::from_utf8(payload).unwrap();
+        assert_eq!(payload.matches(r#""chain_id":1"#).count(), 1);
+        let corrupted = payload.replace(r#""chain_id":1"#, r#""chain_id":3"#);
+        assert!(verify_task_checksum(corrupted.as_bytes(), Some(&checksum))
+            .unwrap_err()
+            .starts_with(&format!("task checksum mismatch, expected {checksum}")));
+    }
+
+    #[test]
+    fn test_verify_query_task_checksum() {
+        // Deserialized into a map of its own, serialized in another order than received.
+        let placeholders = serde_json::from_str(
+            r#"{"0":"0xa","1":"0x14","2":"0x1","3":"0x2","4":"0x3","5":"0x4","6":"0x5"}"#,
+        )
+        .unwrap();
+        let task = TaskType::V1Query(query::WorkerTask::new(
+            1,
+            query::WorkerTaskType::Query(QueryInput {
+                proof_key: query::keys::ProofKey::Revelation("query".to_string()),
+                query_step: QueryStep::Revelation(RevelationInput::Aggregated {
+                    placeholders,
+                    indexing_proof: Hydratable::Hydrated(Arc::new(vec![1])),
+                    query_proof: Hydratable::Hydrated(Arc::new(vec![2])),
+                }),
+                pis: vec![],
+            }),
+        ));
+        let (payload, checksum) = checksummed_payload(task);
+        verify_task_checksum(&payload, Some(&checksum)).unwrap();
+    }
+}
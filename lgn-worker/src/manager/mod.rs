@@ -1,25 +1,173 @@
 pub(crate) mod v1;
 
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::num::NonZeroUsize;
 use std::panic::RefUnwindSafe;
 use std::panic::UnwindSafe;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::PoisonError;
 
-use anyhow::bail;
 use lgn_messages::types::MessageEnvelope;
 use lgn_messages::types::MessageReplyEnvelope;
 use lgn_messages::types::ProverType;
 use lgn_messages::types::ToProverType;
 use lgn_provers::provers::LgnProver;
+use lgn_provers::provers::WarmHint;
+use lru::LruCache;
 use metrics::counter;
 use metrics::histogram;
+use serde_derive::Serialize;
 use tracing::info;
 
+/// Initialization state of a prover.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ProverState {
+    /// The prover will be initialized once the ones before it are.
+    Pending,
+    /// The prover public parameters are being downloaded and loaded.
+    Loading,
+    Ready,
+    /// The prover will be initialized on its first task.
+    Lazy,
+}
+
+/// Initialization progress of the provers, as reported by the readiness probes.
+#[derive(Default)]
+pub(crate) struct ProversReadiness {
+    provers: Mutex<BTreeMap<String, ProverState>>,
+    initialized: AtomicBool,
+    non_production: AtomicBool,
+    shutting_down: AtomicBool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ReadinessReport {
+    ready: bool,
+    provers: BTreeMap<String, ProverState>,
+    /// Whether the provers run on params unfit for production.
+    non_production: bool,
+}
+
+impl ProversReadiness {
+    pub(crate) fn set(
+        &self,
+        prover_type: ProverType,
+        state: ProverState,
+    ) {
+        self.provers
+            .lock()
+            .unwrap()
+            .insert(prover_type.to_string(), state);
+    }
+
+    /// Mark the initialization of all the provers as done.
+    pub(crate) fn set_initialized(&self) {
+        self.initialized.store(true, Ordering::Relaxed);
+    }
+
+    /// Report the params as unfit for production.
+    pub(crate) fn set_non_production(&self) {
+        self.non_production.store(true, Ordering::Relaxed);
+    }
+
+    /// Report the worker as not ready anymore, for no more tasks to be routed to it.
+    pub(crate) fn set_shutting_down(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_ready(&self) -> bool {
+        self.initialized.load(Ordering::Relaxed) && !self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// The initialization state of every prover.
+    pub(crate) fn provers(&self) -> BTreeMap<String, ProverState> {
+        self.provers.lock().unwrap().clone()
+    }
+
+    pub(crate) fn report(&self) -> ReadinessReport {
+        ReadinessReport {
+            ready: self.is_ready(),
+            provers: self.provers(),
+            non_production: self.non_production.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A prover, shared by the tasks proven concurrently.
+pub(crate) type SharedProver<T, R> = Box<dyn LgnProver<T, R> + Send + Sync>;
+
+type ProverInit<T, R> = Box<dyn Fn() -> anyhow::Result<SharedProver<T, R>> + Send + Sync>;
+
+/// A prover initialized on the first task it is asked to prove.
+struct LazyProver<T, R> {
+    init: ProverInit<T, R>,
+    prover: Mutex<Option<Arc<dyn LgnProver<T, R> + Send + Sync>>>,
+}
+
+impl<T, R> LazyProver<T, R> {
+    /// Initialize the prover if not done yet.
+    fn load(
+        &self,
+        prover_type: ProverType,
+    ) -> anyhow::Result<Arc<dyn LgnProver<T, R> + Send + Sync>> {
+        // The lock is only held while initializing, for the tasks proven concurrently not to wait
+        // for each other.
+        let mut prover = self.prover.lock().unwrap_or_else(PoisonError::into_inner);
+        if prover.is_none() {
+            info!("initializing the {prover_type} prover for its first task");
+            let start_time = std::time::Instant::now();
+            *prover = Some(Arc::from((self.init)()?));
+            let task_type = prover_type.to_string();
+            histogram!("zkmr_worker_lazy_prover_init_seconds", "task_type" => task_type)
+                .record(start_time.elapsed().as_secs_f64());
+        }
+        Ok(Arc::clone(
+            prover.as_ref().expect("the prover has just been loaded"),
+        ))
+    }
+}
+
 /// Manages provers for different proving task types
 pub(crate) struct ProversManager<T, R>
 where
     T: ToProverType + UnwindSafe,
 {
-    provers: HashMap<ProverType, Box<dyn LgnProver<T, R>>>,
+    provers: HashMap<ProverType, SharedProver<T, R>>,
+    lazy_provers: HashMap<ProverType, LazyProver<T, R>>,
+    /// The replies to the last tasks proven, by the hash of their envelope, see
+    /// [`ProversManager::set_proof_cache`].
+    proof_cache: Option<Mutex<LruCache<blake3::Hash, MessageReplyEnvelope<R>>>>,
+    /// The classes of the tasks to prove, all of them if empty.
+    accepted: HashSet<ProverType>,
+}
+
+/// A task of a class the worker does not prove, to be routed to another worker rather than
+/// failed.
+#[derive(Debug)]
+pub(crate) struct UnsupportedTask(pub(crate) ProverType);
+
+impl Display for UnsupportedTask {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(
+            f,
+            "unsupported: tasks of class {} are not proven by this worker",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedTask {
 }
 
 impl<T: ToProverType + UnwindSafe, R> UnwindSafe for ProversManager<T, R> {
@@ -32,8 +180,17 @@ where
     T: ToProverType + UnwindSafe,
 {
     pub(crate) fn new() -> Self {
+        Self::with_provers(HashMap::default())
+    }
+
+    /// A manager routing the tasks to `provers`, e.g. custom or stub ones, more of them being
+    /// registered with [`ProversManager::add_prover`] as the built-in ones are.
+    pub(crate) fn with_provers(provers: HashMap<ProverType, SharedProver<T, R>>) -> Self {
         Self {
-            provers: HashMap::default(),
+            provers,
+            lazy_provers: HashMap::default(),
+            proof_cache: None,
+            accepted: HashSet::default(),
         }
     }
 
@@ -45,11 +202,86 @@ where
     pub(crate) fn add_prover(
         &mut self,
         task_type: ProverType,
-        prover: Box<dyn LgnProver<T, R>>,
+        prover: SharedProver<T, R>,
     ) {
         self.provers.insert(task_type, prover);
     }
 
+    /// Registers a prover to be built by `init` when the first task of `task_type` arrives.
+    pub(crate) fn add_lazy_prover(
+        &mut self,
+        task_type: ProverType,
+        init: impl Fn() -> anyhow::Result<SharedProver<T, R>> + Send + Sync + 'static,
+    ) {
+        self.lazy_provers.insert(
+            task_type,
+            LazyProver {
+                init: Box::new(init),
+                prover: Mutex::new(None),
+            },
+        );
+    }
+
+    /// Only prove the tasks of the `accepted` classes, all of them if empty, the others being
+    /// rejected with [`UnsupportedTask`].
+    pub(crate) fn set_accepted_classes(
+        &mut self,
+        accepted: impl IntoIterator<Item = ProverType>,
+    ) {
+        self.accepted = accepted.into_iter().collect();
+    }
+
+    /// The classes of the tasks proven, among those with a prover.
+    pub(crate) fn supported_task_types(&self) -> Vec<ProverType> {
+        let mut supported = self
+            .provers
+            .keys()
+            .chain(self.lazy_provers.keys())
+            .filter(|prover_type| self.check_accepted(**prover_type).is_ok())
+            .copied()
+            .collect::<Vec<_>>();
+        supported.sort_by_key(ProverType::to_string);
+        supported
+    }
+
+    /// Reject the tasks of `prover_type` if its class is not accepted, whether or not it has a
+    /// prover, e.g. in a prover process.
+    pub(crate) fn check_accepted(
+        &self,
+        prover_type: ProverType,
+    ) -> Result<(), UnsupportedTask> {
+        if self.accepted.is_empty() || self.accepted.contains(&prover_type) {
+            Ok(())
+        } else {
+            Err(UnsupportedTask(prover_type))
+        }
+    }
+
+    /// Reply to the tasks delivered again from a cache of the replies to the last `entries` tasks
+    /// proven, keyed by the hash of their envelope; 0 disables the cache.
+    pub(crate) fn set_proof_cache(
+        &mut self,
+        entries: usize,
+    ) {
+        self.proof_cache =
+            NonZeroUsize::new(entries).map(|entries| Mutex::new(LruCache::new(entries)));
+    }
+
+    /// Prepare the provers for the burst of tasks announced by `hint`, initializing the lazy ones
+    /// beforehand.
+    pub(crate) fn warm(
+        &self,
+        hint: &WarmHint,
+    ) -> anyhow::Result<()> {
+        for prover in self.provers.values() {
+            prover.warm(hint)?;
+        }
+        for (prover_type, lazy) in &self.lazy_provers {
+            lazy.load(*prover_type)?.warm(hint)?;
+        }
+        Ok(())
+    }
+
     /// Sends proving request to a matching prover
     ///
     /// # Arguments
@@ -60,33 +292,216 @@ where
     pub(crate) fn delegate_proving(
         &self,
         envelope: &MessageEnvelope<T>,
-    ) -> anyhow::Result<MessageReplyEnvelope<R>> {
+    ) -> anyhow::Result<MessageReplyEnvelope<R>>
+    where
+        T: serde::Serialize,
+        R: Clone,
+    {
         let prover_type: ProverType = envelope.inner.to_prover_type();
+        self.check_accepted(prover_type)?;
+        let cache_key = match &self.proof_cache {
+            Some(proof_cache) => {
+                let key = blake3::hash(&serde_json::to_vec(envelope)?);
+                let cached = proof_cache
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get(&key)
+                    .cloned();
+                if let Some(reply) = cached {
+                    info!(
+                        "task {} already proven, replying from the proof cache",
+                        envelope.id()
+                    );
+                    counter!("zkmr_worker_proof_cache_hits_total", "task_type" => prover_type.to_string())
+                        .increment(1);
+                    return Ok(reply);
+                }
+                Some(key)
+            },
+            None => None,
+        };
+        let extraction_type = envelope.inner.extraction_type();
+        let mut labels = vec![("task_type", prover_type.to_string())];
+        if let Some(extraction_type) = extraction_type {
+            labels.push(("extraction_type", extraction_type.to_string()));
+        }
 
-        counter!("zkmr_worker_tasks_received_total", "task_type" => prover_type.to_string())
-            .increment(1);
+        counter!("zkmr_worker_tasks_received_total", &labels).increment(1);
 
-        match self.provers.get(&prover_type) {
-            Some(prover) => {
-                info!("Running prover for task type: {prover_type:?}");
+        let lazy_prover;
+        let prover = match (
+            self.provers.get(&prover_type),
+            self.lazy_provers.get(&prover_type),
+        ) {
+            (Some(prover), _) => prover.as_ref(),
+            (None, Some(lazy)) => {
+                lazy_prover = lazy.load(prover_type)?;
+                lazy_prover.as_ref()
+            },
+            (None, None) => {
+                counter!("zkmr_worker_tasks_failed_total", "task_type" => prover_type.to_string())
+                    .increment(1);
 
-                let start_time = std::time::Instant::now();
+                return Err(UnsupportedTask(prover_type).into());
+            },
+        };
 
-                let result = prover.run(envelope)?;
+        match extraction_type {
+            Some(extraction_type) => {
+                info!("Running prover for task type: {prover_type:?} ({extraction_type} final extraction)")
+            },
+            None => info!("Running prover for task type: {prover_type:?}"),
+        }
 
-                counter!("zkmr_worker_tasks_processed_total", "task_type" => prover_type.to_string())
-                    .increment(1);
-                histogram!("zkmr_worker_task_processing_duration_seconds", "task_type" => prover_type.to_string())
+        let start_time = std::time::Instant::now();
+
+        let result = lgn_provers::seed::with_seed(envelope.seed, || prover.run(envelope))?;
+
+        counter!("zkmr_worker_tasks_processed_total", &labels).increment(1);
+        histogram!("zkmr_worker_task_processing_duration_seconds", &labels)
             .record(start_time.elapsed().as_secs_f64());
 
-                Ok(result)
-            },
-            None => {
-                counter!("zkmr_worker_tasks_failed_total", "task_type" => prover_type.to_string())
-                    .increment(1);
+        // Failures are not cached, for the tasks delivered again to be proven again.
+        if let (Some(proof_cache), Some(key)) = (&self.proof_cache, cache_key) {
+            proof_cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .put(key, result.clone());
+        }
+        Ok(result)
+    }
+}
 
-                bail!("No prover found for task type: {:?}", prover_type);
-            },
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use anyhow::bail;
+    use lgn_messages::routing::RoutingKey;
+    use lgn_messages::types::v1::preprocessing::WorkerTask;
+    use lgn_messages::types::v1::preprocessing::WorkerTaskType;
+    use lgn_messages::types::ProofCategory;
+    use lgn_messages::types::ReplyType;
+    use lgn_messages::types::TaskType;
+    use lgn_messages::types::WorkerReply;
+
+    use super::*;
+
+    /// A custom prover, replying with an empty proof.
+    struct StubProver;
+
+    impl LgnProver<TaskType, ReplyType> for StubProver {
+        fn run(
+            &self,
+            envelope: &MessageEnvelope<TaskType>,
+        ) -> anyhow::Result<MessageReplyEnvelope<ReplyType>> {
+            Ok(MessageReplyEnvelope::new(
+                envelope.query_id.clone(),
+                envelope.task_id.clone(),
+                ReplyType::V1Preprocessing(WorkerReply::new(1, None, ProofCategory::Indexing)),
+            ))
         }
     }
+
+    /// A prover counting the tasks it proved, failing those of `fail_task`.
+    struct CountingProver {
+        runs: Arc<AtomicUsize>,
+        fail_task: &'static str,
+    }
+
+    impl LgnProver<TaskType, ReplyType> for CountingProver {
+        fn run(
+            &self,
+            envelope: &MessageEnvelope<TaskType>,
+        ) -> anyhow::Result<MessageReplyEnvelope<ReplyType>> {
+            self.runs.fetch_add(1, Ordering::Relaxed);
+            if envelope.task_id() == self.fail_task {
+                bail!("proving failed");
+            }
+            StubProver.run(envelope)
+        }
+    }
+
+    fn envelope(task_id: &str) -> MessageEnvelope<TaskType> {
+        MessageEnvelope::new(
+            "query".to_string(),
+            task_id.to_string(),
+            TaskType::V1Preprocessing(WorkerTask::new(1, 2, WorkerTaskType::ivc(7, 2, false))),
+            RoutingKey::combined("domain".to_string(), 0),
+            "1.0.0".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_proof_cache() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut manager = ProversManager::<TaskType, ReplyType>::with_provers(HashMap::from([(
+            ProverType::V1Preprocessing,
+            Box::new(CountingProver {
+                runs: Arc::clone(&runs),
+                fail_task: "failing",
+            }) as SharedProver<TaskType, ReplyType>,
+        )]));
+        manager.set_proof_cache(2);
+
+        let first = manager.delegate_proving(&envelope("task")).unwrap();
+        let second = manager.delegate_proving(&envelope("task")).unwrap();
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+        assert_eq!(first, second);
+
+        // Another task is proven.
+        manager.delegate_proving(&envelope("other")).unwrap();
+        assert_eq!(runs.load(Ordering::Relaxed), 2);
+
+        // Failures are not cached.
+        assert!(manager.delegate_proving(&envelope("failing")).is_err());
+        assert!(manager.delegate_proving(&envelope("failing")).is_err());
+        assert_eq!(runs.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn test_accepted_classes() {
+        let mut manager = ProversManager::<TaskType, ReplyType>::with_provers(HashMap::from([(
+            ProverType::V1Preprocessing,
+            Box::new(StubProver) as SharedProver<TaskType, ReplyType>,
+        )]));
+        assert_eq!(
+            manager.supported_task_types(),
+            [ProverType::V1Preprocessing]
+        );
+
+        manager.set_accepted_classes([ProverType::V1Query]);
+        assert!(manager.supported_task_types().is_empty());
+        let error = manager.delegate_proving(&envelope("task")).unwrap_err();
+        assert!(error.is::<UnsupportedTask>());
+        assert_eq!(
+            error.to_string(),
+            "unsupported: tasks of class V1Preprocessing are not proven by this worker"
+        );
+
+        manager.set_accepted_classes([ProverType::V1Preprocessing, ProverType::V1Query]);
+        manager.delegate_proving(&envelope("task")).unwrap();
+    }
+
+    #[test]
+    fn test_custom_prover() {
+        let envelope = MessageEnvelope::new(
+            "query".to_string(),
+            "task".to_string(),
+            TaskType::V1Preprocessing(WorkerTask::new(1, 2, WorkerTaskType::ivc(7, 2, false))),
+            RoutingKey::combined("domain".to_string(), 0),
+            "1.0.0".to_string(),
+        );
+
+        let manager = ProversManager::<TaskType, ReplyType>::with_provers(HashMap::from([(
+            ProverType::V1Preprocessing,
+            Box::new(StubProver) as SharedProver<TaskType, ReplyType>,
+        )]));
+        let reply = manager.delegate_proving(&envelope).unwrap();
+        assert_eq!(reply.task_id(), "task");
+
+        // Tasks of the classes without a prover are not proven.
+        let manager = ProversManager::<TaskType, ReplyType>::new();
+        assert!(manager.delegate_proving(&envelope).is_err());
+    }
 }
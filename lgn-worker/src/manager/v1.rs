@@ -1,54 +1,247 @@
 use std::collections::HashMap;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::*;
 use lgn_messages::types::ProverType;
 use lgn_messages::types::ReplyType;
 use lgn_messages::types::TaskDifficulty;
 use lgn_messages::types::TaskType;
+use lgn_provers::provers::LgnProver;
 
+use crate::checksum::check_checksums;
 use crate::config::Config;
+use crate::config::ProverLoading;
+use crate::manager::ProverState;
 use crate::manager::ProversManager;
+use crate::manager::ProversReadiness;
+use crate::manager::SharedProver;
 
 pub(crate) fn register_v1_provers(
     config: &Config,
     manager: &mut ProversManager<TaskType, ReplyType>,
     checksums: &HashMap<String, blake3::Hash>,
+    readiness: &ProversReadiness,
 ) -> Result<()> {
-    if config.worker.instance_type >= TaskDifficulty::Small {
-        let query_prover = lgn_provers::provers::v1::query::create_prover(
-            &config.public_params.params_base_url(),
-            &config.public_params.dir,
+    for (prover_type, difficulty) in [
+        (ProverType::V1Query, TaskDifficulty::Small),
+        (ProverType::V1Preprocessing, TaskDifficulty::Medium),
+        (ProverType::V1Groth16, TaskDifficulty::Large),
+    ] {
+        if provides(config, prover_type, difficulty) {
+            readiness.set(prover_type, ProverState::Pending);
+        }
+    }
+
+    // Each prover gets the smallest of its own timeout and of what remains of the overall one.
+    let deadline = config
+        .worker
+        .init_timeout
+        .map(|timeout| Instant::now() + Duration::from_secs(timeout));
+    let timeout = || {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match (
+            config.worker.prover_init_timeout.map(Duration::from_secs),
+            remaining,
+        ) {
+            (Some(prover_timeout), Some(remaining)) => Some(prover_timeout.min(remaining)),
+            (prover_timeout, remaining) => prover_timeout.or(remaining),
+        }
+    };
+    // Checked up front, rather than when the prover of a file is initialized, possibly lazily on
+    // its first task.
+    if cfg!(not(feature = "dummy-prover")) {
+        check_checksums(checksums, &required_param_files(config))
+            .context("checking the params checksum file")?;
+    }
+
+    let url = config.public_params.params_base_url();
+    let dir = config.public_params.dir.clone();
+
+    let loading = &config.prover_loading;
+
+    if provides(config, ProverType::V1Query, TaskDifficulty::Small) {
+        let (url, dir, checksums) = (url.clone(), dir.clone(), checksums.clone());
+        let file = config.public_params.query_params.file.clone();
+        let max_matching_rows = config.worker.max_matching_rows;
+        register_prover(
+            manager,
+            readiness,
+            ProverType::V1Query,
+            loading.query,
             &config.public_params.query_params.file,
-            checksums,
+            timeout(),
+            move || {
+                lgn_provers::provers::v1::query::create_prover(&url, &dir, &file, &checksums)
+                    .map(|prover| prover.with_max_matching_rows(max_matching_rows))
+            },
         )?;
-
-        manager.add_prover(ProverType::V1Query, Box::new(query_prover));
     }
 
-    if config.worker.instance_type >= TaskDifficulty::Medium {
-        let preprocessing_prover = lgn_provers::provers::v1::preprocessing::create_prover(
-            &config.public_params.params_base_url(),
-            &config.public_params.dir,
+    if provides(config, ProverType::V1Preprocessing, TaskDifficulty::Medium) {
+        let (url, dir, checksums) = (url.clone(), dir.clone(), checksums.clone());
+        let file = config.public_params.preprocessing_params.file.clone();
+        let verify_children_proofs = config.worker.verify_children_proofs;
+        register_prover(
+            manager,
+            readiness,
+            ProverType::V1Preprocessing,
+            loading.preprocessing,
             &config.public_params.preprocessing_params.file,
-            checksums,
+            timeout(),
+            move || {
+                lgn_provers::provers::v1::preprocessing::create_prover(
+                    &url, &dir, &file, &checksums,
+                )
+                .map(|prover| prover.with_verify_children_proofs(verify_children_proofs))
+            },
         )?;
-
-        manager.add_prover(ProverType::V1Preprocessing, Box::new(preprocessing_prover));
     }
 
-    if config.worker.instance_type >= TaskDifficulty::Large {
-        let groth16_prover = lgn_provers::provers::v1::groth16::create_prover(
-            &config.public_params.params_base_url(),
-            &config.public_params.dir,
+    if provides(config, ProverType::V1Groth16, TaskDifficulty::Large) {
+        let (url, dir, checksums) = (url.clone(), dir.clone(), checksums.clone());
+        let assets = config.public_params.groth16_assets.clone();
+        register_prover(
+            manager,
+            readiness,
+            ProverType::V1Groth16,
+            loading.groth16,
             &config.public_params.groth16_assets.circuit_file,
-            checksums,
-            &config.public_params.groth16_assets.r1cs_file,
-            &config.public_params.groth16_assets.pk_file,
+            timeout(),
+            move || {
+                lgn_provers::provers::v1::groth16::create_prover(
+                    &url,
+                    &dir,
+                    &assets.circuit_file,
+                    &checksums,
+                    &assets.r1cs_file,
+                    &assets.pk_file,
+                )
+            },
         )
         .context("initializing Groth16 prover")?;
+    }
+
+    Ok(())
+}
 
-        manager.add_prover(ProverType::V1Groth16, Box::new(groth16_prover));
+/// Whether the worker proves the tasks of `prover_type`, needing a class of at least
+/// `difficulty` and the class of the tasks to be accepted.
+fn provides(
+    config: &Config,
+    prover_type: ProverType,
+    difficulty: TaskDifficulty,
+) -> bool {
+    let accepted = &config.worker.accepted_classes;
+    config.worker.instance_type >= difficulty
+        && (accepted.is_empty()
+            || accepted
+                .iter()
+                .any(|class| ProverType::from(*class) == prover_type))
+}
+
+/// The param files of the provers of the worker.
+fn required_param_files(config: &Config) -> Vec<&str> {
+    let params = &config.public_params;
+    let mut files = vec![];
+    if provides(config, ProverType::V1Query, TaskDifficulty::Small) {
+        files.push(params.query_params.file.as_str());
+    }
+    if provides(config, ProverType::V1Preprocessing, TaskDifficulty::Medium) {
+        files.push(params.preprocessing_params.file.as_str());
     }
+    if provides(config, ProverType::V1Groth16, TaskDifficulty::Large) {
+        files.extend([
+            params.groth16_assets.circuit_file.as_str(),
+            params.groth16_assets.r1cs_file.as_str(),
+            params.groth16_assets.pk_file.as_str(),
+        ]);
+    }
+    files
+}
 
+/// Register the prover built by `init`, initializing it now unless it is loaded lazily.
+fn register_prover<P: LgnProver<TaskType, ReplyType> + Send + Sync + 'static>(
+    manager: &mut ProversManager<TaskType, ReplyType>,
+    readiness: &ProversReadiness,
+    prover_type: ProverType,
+    loading: ProverLoading,
+    param_file: &str,
+    timeout: Option<Duration>,
+    init: impl Fn() -> Result<P> + Send + Sync + 'static,
+) -> Result<()> {
+    match loading {
+        ProverLoading::Eager => {
+            readiness.set(prover_type, ProverState::Loading);
+            let prover = init_with_timeout(prover_type, param_file, timeout, init)?;
+            manager.add_prover(prover_type, Box::new(prover));
+            readiness.set(prover_type, ProverState::Ready);
+        },
+        ProverLoading::Lazy => {
+            manager.add_lazy_prover(prover_type, move || {
+                Ok(Box::new(init()?) as SharedProver<TaskType, ReplyType>)
+            });
+            readiness.set(prover_type, ProverState::Lazy);
+        },
+    }
     Ok(())
 }
+
+/// Run `init` on a dedicated thread, failing if it does not complete within `timeout`.
+///
+/// On timeout the thread is left behind, as the worker is expected to exit anyway.
+fn init_with_timeout<P: Send + 'static>(
+    prover_type: ProverType,
+    param_file: &str,
+    timeout: Option<Duration>,
+    init: impl FnOnce() -> Result<P> + Send + 'static,
+) -> Result<P> {
+    let Some(timeout) = timeout else {
+        return init();
+    };
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::Builder::new()
+        .name(format!("init-{prover_type}"))
+        .spawn(move || {
+            let _ = tx.send(init());
+        })
+        .context("spawning prover initialization thread")?;
+
+    match rx.recv_timeout(timeout) {
+        Ok(prover) => prover,
+        Err(RecvTimeoutError::Timeout) => {
+            bail!(
+                "initializing the {prover_type} prover timed out after {}s while loading `{param_file}`",
+                timeout.as_secs()
+            )
+        },
+        Err(RecvTimeoutError::Disconnected) => {
+            bail!("initializing the {prover_type} prover panicked while loading `{param_file}`")
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MessageClass;
+
+    #[test]
+    fn test_required_param_files() {
+        let mut config = Config::load(None).unwrap();
+        config.worker.instance_type = TaskDifficulty::Large;
+        assert_eq!(required_param_files(&config).len(), 5);
+
+        config.worker.accepted_classes = vec![MessageClass::V1Query];
+        assert_eq!(
+            required_param_files(&config),
+            [config.public_params.query_params.file.as_str()]
+        );
+
+        config.worker.instance_type = TaskDifficulty::Medium;
+        config.worker.accepted_classes = vec![MessageClass::V1Groth16];
+        assert!(required_param_files(&config).is_empty());
+    }
+}
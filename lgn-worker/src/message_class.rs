@@ -0,0 +1,61 @@
+//! The class of a task, the variant of its inner task, checked before the task is deserialized, for
+//! a class this worker does not support, e.g. one introduced by a newer gateway, to fail cleanly
+//! rather than as an opaque deserialization or dispatch failure.
+
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use anyhow::Result;
+use metrics::counter;
+use serde::de::IgnoredAny;
+use serde_derive::Deserialize;
+use tracing::warn;
+
+/// The classes of the tasks this worker proves.
+const SUPPORTED_CLASSES: [&str; 3] = ["V1Preprocessing", "V1Query", "V1Groth16"];
+
+/// The class of a task, read without deserializing the task itself.
+#[derive(Deserialize)]
+struct ClassProbe {
+    inner: BTreeMap<String, IgnoredAny>,
+}
+
+/// Fail if the class of the task `payload` is not supported. Payloads too malformed for their
+/// class to be read are left to the deserialization of the task to report.
+pub(crate) fn check_message_class(payload: &[u8]) -> Result<()> {
+    let Ok(probe) = serde_json::from_slice::<ClassProbe>(payload) else {
+        return Ok(());
+    };
+    for class in probe.inner.keys() {
+        if !SUPPORTED_CLASSES.contains(&class.as_str()) {
+            warn!("received a task of unknown message class `{class}`");
+            counter!("zkmr_worker_unknown_message_class_total", "class" => class.clone())
+                .increment(1);
+            bail!("unsupported message class `{class}`, the gateway may be newer than the worker");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_message_class() {
+        check_message_class(br#"{"query_id": "q", "inner": {"V1Query": {}}}"#).unwrap();
+
+        let error =
+            check_message_class(br#"{"query_id": "q", "inner": {"V9Future": {"table_id": 7}}}"#)
+                .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("unsupported message class `V9Future`"));
+
+        // Classes still known to the messages but not proven by the worker.
+        assert!(check_message_class(br#"{"inner": {"TxTrie": {}}}"#).is_err());
+
+        // Left to the deserialization of the task.
+        check_message_class(b"not json").unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+//! Point-in-time JSON view of the worker metrics, served on `/metrics/snapshot` for on-call not
+//! to wait for the next Prometheus scrape.
+//!
+//! The snapshot is parsed from the rendering of the Prometheus recorder, so that it reports the
+//! very values a scrape would, histograms included.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub(crate) struct MetricFamily {
+    /// `counter`, `gauge`, `histogram` or `summary`.
+    #[serde(rename = "type")]
+    kind: String,
+    samples: Vec<Sample>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct Sample {
+    /// The name of the sample, which differs from that of its family for the `_sum`, `_count`
+    /// and `_bucket` samples of histograms and summaries.
+    name: String,
+    labels: BTreeMap<String, String>,
+    value: f64,
+}
+
+/// Group the samples of the Prometheus text exposition `text` by metric family.
+pub(crate) fn parse(text: &str) -> BTreeMap<String, MetricFamily> {
+    let mut families = BTreeMap::<String, MetricFamily>::new();
+    let mut family = String::new();
+
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(declaration) = line.strip_prefix("# TYPE ") {
+            let mut parts = declaration.split_whitespace();
+            if let (Some(name), Some(kind)) = (parts.next(), parts.next()) {
+                family = name.to_string();
+                families.entry(family.clone()).or_default().kind = kind.to_string();
+            }
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let Some(sample) = parse_sample(line) else {
+            continue;
+        };
+        // Samples follow the declaration of their family; an undeclared one is a family of its
+        // own.
+        let family = if sample.name.starts_with(family.as_str()) && !family.is_empty() {
+            family.clone()
+        } else {
+            sample.name.clone()
+        };
+        families.entry(family).or_default().samples.push(sample);
+    }
+
+    families
+}
+
+/// Parse a `name{label="value",...} value` line.
+fn parse_sample(line: &str) -> Option<Sample> {
+    let (series, value) = line.rsplit_once(' ')?;
+    let value = value.parse().ok()?;
+
+    let (name, labels) = match series.split_once('{') {
+        Some((name, labels)) => (name, parse_labels(labels.strip_suffix('}')?)?),
+        None => (series, BTreeMap::new()),
+    };
+
+    Some(Sample {
+        name: name.to_string(),
+        labels,
+        value,
+    })
+}
+
+/// Parse the comma-separated `label="value"` pairs, with `\\`, `\"` and `\n` escapes in values.
+fn parse_labels(text: &str) -> Option<BTreeMap<String, String>> {
+    let mut labels = BTreeMap::new();
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let (label, after) = rest.split_once("=\"")?;
+        let mut value = String::new();
+        let mut chars = after.char_indices();
+        let end = loop {
+            match chars.next()? {
+                (i, '"') => break i,
+                (_, '\\') => {
+                    match chars.next()?.1 {
+                        'n' => value.push('\n'),
+                        c => value.push(c),
+                    }
+                },
+                (_, c) => value.push(c),
+            }
+        };
+        labels.insert(label.trim_start_matches(',').to_string(), value);
+        rest = &after[end + 1..];
+    }
+
+    Some(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r#"
+# TYPE zkmr_worker_tasks_received_total counter
+zkmr_worker_tasks_received_total 3
+
+# TYPE zkmr_worker_proof_compression_ratio summary
+zkmr_worker_proof_compression_ratio{task_type="V1Query",quantile="0.5"} 0.25
+zkmr_worker_proof_compression_ratio_sum{task_type="V1Query"} 0.5
+zkmr_worker_proof_compression_ratio_count{task_type="V1Query"} 2
+
+# TYPE zkmr_worker_gateway_stream_ends_total counter
+zkmr_worker_gateway_stream_ends_total{reason="a \"quoted\", \\ reason"} 1
+"#;
+        let families = parse(text);
+
+        assert_eq!(
+            families.keys().collect::<Vec<_>>(),
+            vec![
+                "zkmr_worker_gateway_stream_ends_total",
+                "zkmr_worker_proof_compression_ratio",
+                "zkmr_worker_tasks_received_total",
+            ]
+        );
+        assert_eq!(
+            families["zkmr_worker_tasks_received_total"],
+            MetricFamily {
+                kind: "counter".to_string(),
+                samples: vec![Sample {
+                    name: "zkmr_worker_tasks_received_total".to_string(),
+                    labels: BTreeMap::new(),
+                    value: 3.0,
+                }],
+            }
+        );
+
+        let summary = &families["zkmr_worker_proof_compression_ratio"];
+        assert_eq!(summary.kind, "summary");
+        assert_eq!(summary.samples.len(), 3);
+        assert_eq!(summary.samples[0].labels["quantile"], "0.5");
+        assert_eq!(
+            summary.samples[2].name,
+            "zkmr_worker_proof_compression_ratio_count"
+        );
+        assert_eq!(summary.samples[2].value, 2.0);
+
+        assert_eq!(
+            families["zkmr_worker_gateway_stream_ends_total"].samples[0].labels["reason"],
+            "a \"quoted\", \\ reason"
+        );
+    }
+}
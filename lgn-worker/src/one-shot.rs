@@ -6,6 +6,7 @@ use lgn_messages::types::ReplyType;
 use lgn_messages::types::TaskType;
 use manager::v1::register_v1_provers;
 use manager::ProversManager;
+use tracing::debug;
 use tracing::error;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::EnvFilter;
@@ -72,15 +73,21 @@ async fn main() -> Result<()> {
 
     let cli = Cli::parse();
 
-    let config = config::Config::load(Some(cli.config));
-    config.validate();
+    debug!("Loading local configuration from {}", cli.config);
+    let config = config::Config::load(Some(cli.config)).context("loading the configuration")?;
+    config.validate().context("invalid configuration")?;
     let checksums = fetch_checksums(config.public_params.checksum_file_url()).await?;
 
     let provers_manager =
         tokio::task::block_in_place(move || -> Result<ProversManager<TaskType, ReplyType>> {
             let mut provers_manager = ProversManager::<TaskType, ReplyType>::new();
-            register_v1_provers(&config, &mut provers_manager, &checksums)
-                .context("while registering provers")?;
+            register_v1_provers(
+                &config,
+                &mut provers_manager,
+                &checksums,
+                &Default::default(),
+            )
+            .context("while registering provers")?;
             Ok(provers_manager)
         })
         .context("creating prover managers")?;
@@ -0,0 +1,97 @@
+//! The `verifiable_db` version each param file has been generated by, served on
+//! `/status/params`, for a mismatch with the linked version to be noticed before it surfaces as a
+//! cryptic proving failure.
+
+use std::collections::BTreeMap;
+
+use lgn_provers::params::params_mp2_version;
+use metrics::counter;
+use serde::Serialize;
+use tracing::info;
+use tracing::warn;
+
+use crate::config::PublicParamsConfig;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct ParamsStatus {
+    /// The version of the linked `verifiable_db`.
+    mp2_version: String,
+    files: BTreeMap<String, ParamFileStatus>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct ParamFileStatus {
+    /// The version the file has been generated by, if published along with it.
+    mp2_version: Option<String>,
+    /// Whether that version is the linked one, unknown if it was not published.
+    matches: Option<bool>,
+}
+
+impl ParamsStatus {
+    /// Read the version of every configured param file, warning about those generated by another
+    /// version than the linked one.
+    pub(crate) fn read(config: &PublicParamsConfig) -> Self {
+        let linked = verifiable_db::version().to_string();
+        let base_url = config.params_base_url();
+
+        let files = config
+            .files()
+            .into_iter()
+            .map(|file| {
+                let mp2_version =
+                    params_mp2_version(&base_url, &config.dir, file).unwrap_or_else(|e| {
+                        warn!("reading the mp2 version of `{file}`: {e:?}");
+                        None
+                    });
+                let status = ParamFileStatus::new(mp2_version, &linked);
+                match (&status.mp2_version, status.matches) {
+                    (Some(version), Some(false)) => {
+                        let labels = vec![("file", file.to_string())];
+                        counter!("zkmr_worker_params_version_mismatches_total", &labels)
+                            .increment(1);
+                        warn!("`{file}` targets mp2 {version}, but the worker links {linked}");
+                    },
+                    (Some(version), _) => info!("`{file}` targets mp2 {version}"),
+                    (None, _) => info!("`{file}` does not tell which mp2 version it targets"),
+                }
+                (file.to_string(), status)
+            })
+            .collect();
+
+        Self {
+            mp2_version: linked,
+            files,
+        }
+    }
+}
+
+impl ParamFileStatus {
+    fn new(
+        mp2_version: Option<String>,
+        linked: &str,
+    ) -> Self {
+        let matches = mp2_version.as_deref().map(|version| version == linked);
+        Self {
+            mp2_version,
+            matches,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_param_file_status() {
+        assert_eq!(
+            ParamFileStatus::new(Some("1.2.3".to_string()), "1.2.3").matches,
+            Some(true)
+        );
+        assert_eq!(
+            ParamFileStatus::new(Some("1.1.0".to_string()), "1.2.3").matches,
+            Some(false)
+        );
+        assert_eq!(ParamFileStatus::new(None, "1.2.3").matches, None);
+    }
+}
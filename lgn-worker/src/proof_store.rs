@@ -0,0 +1,271 @@
+use anyhow::ensure;
+use anyhow::Context;
+use lgn_messages::types::v1::preprocessing::ext_tasks::ExtractionType;
+use lgn_messages::types::v1::preprocessing::WorkerTask;
+use lgn_messages::types::v1::preprocessing::WorkerTaskType;
+use lgn_messages::types::MessageEnvelope;
+use lgn_messages::types::MessageReplyEnvelope;
+use lgn_messages::types::ProofReference;
+use lgn_messages::types::ReplyType;
+use lgn_messages::types::TaskType;
+use metrics::counter;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use reqwest::Url;
+use tracing::info;
+
+use crate::config::ProofStoreConfig;
+
+/// Object store holding the proofs too large to be sent over the gateway stream.
+pub(crate) struct ProofStore {
+    store: Box<dyn ObjectStore>,
+    /// The store URL, used to build the references sent to the gateway.
+    url: Url,
+    /// Where to put the proofs within the store.
+    prefix: Path,
+    /// Serialized replies larger than this many bytes are offloaded.
+    threshold: usize,
+}
+
+impl ProofStore {
+    pub(crate) fn new(config: &ProofStoreConfig) -> anyhow::Result<Self> {
+        let url = Url::parse(&config.url).context("parsing proof store URL")?;
+
+        // Credentials are read from the usual `AWS_*`/`GOOGLE_*` environment variables.
+        let (store, prefix) = open_store(&url)?;
+
+        Ok(Self {
+            store,
+            url,
+            prefix,
+            threshold: config.threshold_mb * 1024 * 1024,
+        })
+    }
+
+    /// Serialize `reply`, moving its proof to the store first if the reply would otherwise be
+    /// larger than the configured threshold.
+    ///
+    /// An encoded proof is decoded first, the store always holding the proof bytes.
+    pub(crate) async fn encode_reply(
+        &self,
+        mut reply: MessageReplyEnvelope<ReplyType>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let task_output = serde_json::to_vec(&reply).context("serializing reply")?;
+        if task_output.len() <= self.threshold {
+            return Ok(task_output);
+        }
+
+        let Some(worker_reply) = reply.content_mut().worker_reply_mut() else {
+            return Ok(task_output);
+        };
+        worker_reply.decode_proof()?;
+        let Some((key, proof)) = worker_reply.proof.take() else {
+            return Ok(task_output);
+        };
+
+        let hash = blake3::hash(&proof).to_hex().to_string();
+        let location = Path::from_iter(self.prefix.parts().chain(Path::from(key.as_str()).parts()));
+        let size = proof.len();
+        self.store
+            .put(&location, proof.into())
+            .await
+            .with_context(|| format!("uploading proof to {location}"))?;
+
+        let url = format!(
+            "{}://{}/{location}",
+            self.url.scheme(),
+            self.url.host_str().unwrap_or_default()
+        );
+        info!("proof {key} ({size}B) offloaded to {url}");
+        counter!("zkmr_worker_proofs_offloaded_total").increment(1);
+
+        worker_reply.proof_reference = Some(ProofReference { key, url, hash });
+        serde_json::to_vec(&reply).context("serializing reply")
+    }
+
+    /// Download the proof behind `reference` and check its hash.
+    ///
+    /// Only the proofs under the store URL are fetched, for a task not to make the worker read
+    /// anything else its credentials give access to.
+    async fn fetch_proof(
+        &self,
+        reference: &ProofReference,
+    ) -> anyhow::Result<Vec<u8>> {
+        let url = Url::parse(&reference.url)
+            .with_context(|| format!("parsing proof URL {}", reference.url))?;
+        let location = Path::from_url_path(url.path())
+            .with_context(|| format!("parsing proof URL {}", reference.url))?;
+        ensure!(
+            url.scheme() == self.url.scheme()
+                && url.host_str() == self.url.host_str()
+                && location.prefix_matches(&self.prefix),
+            "proof {} at {} is not under the proof store {}",
+            reference.key,
+            reference.url,
+            self.url
+        );
+
+        let proof = self
+            .store
+            .get(&location)
+            .await
+            .with_context(|| format!("fetching proof {}", reference.key))?
+            .bytes()
+            .await
+            .with_context(|| format!("fetching proof {}", reference.key))?;
+
+        let hash = blake3::hash(&proof).to_hex();
+        ensure!(
+            hash.as_str() == reference.hash,
+            "hash mismatch for proof {}: expected {}, found {hash}",
+            reference.key,
+            reference.hash
+        );
+
+        Ok(proof.to_vec())
+    }
+}
+
+/// Fetch the children proofs of `envelope`, or the contract proof of a final extraction, which
+/// are referenced rather than sent inline, from `proof_store`.
+pub(crate) async fn resolve_proof_references(
+    envelope: &mut MessageEnvelope<TaskType>,
+    proof_store: Option<&ProofStore>,
+) -> anyhow::Result<()> {
+    let TaskType::V1Preprocessing(WorkerTask {
+        task_type: WorkerTaskType::Extraction(extraction),
+        ..
+    }) = envelope.inner_mut()
+    else {
+        return Ok(());
+    };
+
+    match extraction {
+        ExtractionType::MptExtraction(mpt) => {
+            let Some((references, proofs)) = mpt.mpt_type.children_proofs_refs_mut() else {
+                return Ok(());
+            };
+            if references.is_empty() || !proofs.is_empty() {
+                return Ok(());
+            }
+
+            for reference in references {
+                proofs.push(
+                    require_store(proof_store, reference)?
+                        .fetch_proof(reference)
+                        .await?,
+                );
+            }
+            counter!("zkmr_worker_proofs_fetched_total").increment(references.len() as u64);
+        },
+        ExtractionType::FinalExtraction(final_extraction) => {
+            let (Some(reference), proof) = final_extraction.contract_proof_ref_mut() else {
+                return Ok(());
+            };
+            if proof.is_empty() {
+                *proof = require_store(proof_store, reference)?
+                    .fetch_proof(reference)
+                    .await?;
+                counter!("zkmr_worker_proofs_fetched_total").increment(1);
+            }
+        },
+        _ => {},
+    }
+
+    Ok(())
+}
+
+/// The store to fetch `reference` from, the references being only resolved with one configured.
+fn require_store<'a>(
+    proof_store: Option<&'a ProofStore>,
+    reference: &ProofReference,
+) -> anyhow::Result<&'a ProofStore> {
+    proof_store.with_context(|| {
+        format!(
+            "proof {} is referenced, but no proof store is configured",
+            reference.key
+        )
+    })
+}
+
+/// Open the object store behind `url`, returning it along with the path `url` points to within it.
+fn open_store(url: &Url) -> anyhow::Result<(Box<dyn ObjectStore>, Path)> {
+    // Credentials are read from the usual `AWS_*`/`GOOGLE_*` environment variables.
+    object_store::parse_url_opts(
+        url,
+        std::env::vars().map(|(k, v)| (k.to_ascii_lowercase(), v)),
+    )
+    .with_context(|| format!("opening object store {url}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use lgn_messages::types::ProofCategory;
+    use lgn_messages::types::ProofEncoding;
+    use lgn_messages::types::WorkerReply;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_offload_encoded_proof() {
+        let dir = std::env::temp_dir().join(format!("proof-store-{}", std::process::id()));
+        let store = ProofStore::new(&ProofStoreConfig {
+            url: format!("file://{}", dir.display()),
+            threshold_mb: 0,
+        })
+        .unwrap();
+
+        let proof = vec![1, 2, 3];
+        let mut worker_reply = WorkerReply::new(
+            1,
+            Some(("proof_key".to_string(), proof.clone())),
+            ProofCategory::Indexing,
+        );
+        worker_reply.encode_proof(ProofEncoding::Hex);
+        let reply = MessageReplyEnvelope::new(
+            "query".to_string(),
+            "task".to_string(),
+            ReplyType::V1Preprocessing(worker_reply),
+        );
+
+        let task_output = store.encode_reply(reply).await.unwrap();
+        let mut reply: MessageReplyEnvelope<ReplyType> =
+            serde_json::from_slice(&task_output).unwrap();
+        let worker_reply = reply.content_mut().worker_reply_mut().unwrap();
+        assert_eq!(worker_reply.proof, None);
+        assert_eq!(worker_reply.encoded_proof, None);
+        let reference = worker_reply.proof_reference.as_ref().unwrap();
+        assert_eq!(reference.hash, blake3::hash(&proof).to_hex().as_str());
+        assert_eq!(std::fs::read(dir.join("proof_key")).unwrap(), proof);
+        assert_eq!(store.fetch_proof(reference).await.unwrap(), proof);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_proof_outside_store() {
+        let dir = std::env::temp_dir().join(format!("proof-store-out-{}", std::process::id()));
+        let store = ProofStore::new(&ProofStoreConfig {
+            url: format!("file://{}", dir.display()),
+            threshold_mb: 0,
+        })
+        .unwrap();
+
+        for url in [
+            "file:///etc/passwd".to_string(),
+            format!("file://{}-sibling/proof_key", dir.display()),
+            "s3://bucket/proof_key".to_string(),
+        ] {
+            let reference = ProofReference {
+                key: "proof_key".to_string(),
+                url: url.clone(),
+                hash: String::new(),
+            };
+            let err = store.fetch_proof(&reference).await.unwrap_err();
+            assert!(
+                err.to_string().contains("is not under the proof store"),
+                "{url}: {err:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Measurement of the resources consumed by proving a task, see `worker.resource_usage`.
+//!
+//! The figures are read from `/proc`, costing a few file reads per task: where it is not
+//! available, nothing is measured. They cover the whole process, which only proves one task at a
+//! time; with isolated proving, that is the prover process alone.
+
+use lgn_messages::types::ResourceUsage;
+
+/// The length of a clock tick of `/proc/self/stat`, which is 1/100s on every Linux platform.
+const MS_PER_TICK: u64 = 10;
+
+/// The resource counters of the process when proving started.
+pub(crate) struct ResourceSampler {
+    cpu_ticks: u64,
+}
+
+impl ResourceSampler {
+    /// Start measuring, resetting the peak resident memory of the process.
+    pub(crate) fn start() -> Option<Self> {
+        // Resets `VmHWM` to the current resident memory, see proc(5).
+        std::fs::write("/proc/self/clear_refs", "5").ok()?;
+        Some(Self {
+            cpu_ticks: cpu_ticks(&std::fs::read_to_string("/proc/self/stat").ok()?)?,
+        })
+    }
+
+    /// The resources consumed since [`ResourceSampler::start`].
+    pub(crate) fn finish(self) -> Option<ResourceUsage> {
+        let cpu_ticks = cpu_ticks(&std::fs::read_to_string("/proc/self/stat").ok()?)?;
+        let peak_rss_kb = status_kb(&std::fs::read_to_string("/proc/self/status").ok()?, "VmHWM")?;
+        Some(ResourceUsage {
+            cpu_time_ms: cpu_ticks.saturating_sub(self.cpu_ticks) * MS_PER_TICK,
+            peak_rss_bytes: peak_rss_kb * 1024,
+        })
+    }
+}
+
+/// The user and system CPU time of the process, in clock ticks, from `/proc/self/stat`.
+fn cpu_ticks(stat: &str) -> Option<u64> {
+    // The command name may contain spaces and parentheses, the fields are counted after it.
+    let (_, fields) = stat.rsplit_once(')')?;
+    let mut fields = fields.split_whitespace().skip(11);
+    let utime = fields.next()?.parse::<u64>().ok()?;
+    let stime = fields.next()?.parse::<u64>().ok()?;
+    Some(utime + stime)
+}
+
+/// The value of the `field` of `/proc/self/status`, in kB.
+fn status_kb(
+    status: &str,
+    field: &str,
+) -> Option<u64> {
+    status.lines().find_map(|line| {
+        let value = line.strip_prefix(field)?.strip_prefix(':')?;
+        value.trim().strip_suffix("kB")?.trim().parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc() {
+        let stat = "4242 (lgn (worker)) S 1 4242 4242 0 -1 4194560 1200 0 3 0 1500 230 0 0 20 0 \
+                    24 0 1000 2000000 5000";
+        assert_eq!(cpu_ticks(stat), Some(1730));
+        assert_eq!(cpu_ticks("4242 (lgn"), None);
+
+        let status = "Name:\tlgn-worker\nVmPeak:\t  900000 kB\nVmHWM:\t  123456 kB\nVmRSS:\t  \
+                      100000 kB\n";
+        assert_eq!(status_kb(status, "VmHWM"), Some(123456));
+        assert_eq!(status_kb(status, "VmSwap"), None);
+    }
+}
@@ -0,0 +1,51 @@
+use std::num::NonZeroUsize;
+use std::time::Duration;
+use std::time::Instant;
+
+use lru::LruCache;
+
+/// Bounded cache of the replies to recently completed tasks, so that a task re-sent by the
+/// gateway after a missed reply is answered without being proven again.
+pub(crate) struct ResultCache {
+    /// Serialized replies indexed by task ID, along with when they were computed.
+    entries: LruCache<String, (Instant, Vec<u8>)>,
+    /// How long a reply is retained.
+    ttl: Duration,
+}
+
+impl ResultCache {
+    /// Returns `None` if `capacity` is 0, i.e. the cache is disabled.
+    pub(crate) fn new(
+        capacity: usize,
+        ttl: Duration,
+    ) -> Option<Self> {
+        NonZeroUsize::new(capacity).map(|capacity| {
+            Self {
+                entries: LruCache::new(capacity),
+                ttl,
+            }
+        })
+    }
+
+    /// Returns the reply to the given task if it completed less than the TTL ago.
+    pub(crate) fn get(
+        &mut self,
+        task_id: &str,
+    ) -> Option<Vec<u8>> {
+        let (completed_at, reply) = self.entries.get(task_id)?;
+        if completed_at.elapsed() <= self.ttl {
+            return Some(reply.clone());
+        }
+
+        self.entries.pop(task_id);
+        None
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        task_id: String,
+        reply: Vec<u8>,
+    ) {
+        self.entries.put(task_id, (Instant::now(), reply));
+    }
+}
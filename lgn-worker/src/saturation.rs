@@ -0,0 +1,144 @@
+//! How saturated the worker is with tasks, for the liveness probe, see `saturation`.
+//!
+//! The worker is saturated when its proving pool, of `max_concurrent_tasks` tasks, was full for
+//! at least `threshold` of the last `duration`, i.e. when tasks keep arriving faster than it proves
+//! them.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use metrics::gauge;
+
+use crate::config::SaturationConfig;
+use crate::config::SaturationLiveness;
+
+pub(crate) struct Saturation {
+    liveness: SaturationLiveness,
+    threshold: f64,
+    duration: Duration,
+    /// When the worker started, saturation being unknown until it has run for `duration`.
+    started: Instant,
+    /// The periods the proving pool was full within the last `duration`, the ongoing one having no
+    /// end yet.
+    busy: Mutex<VecDeque<(Instant, Option<Instant>)>>,
+}
+
+impl Saturation {
+    pub(crate) fn new(
+        config: &SaturationConfig,
+        now: Instant,
+    ) -> Self {
+        Self {
+            liveness: config.liveness,
+            threshold: config.threshold,
+            duration: Duration::from_secs(config.duration),
+            started: now,
+            busy: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Record that the proving pool became full at `now`.
+    pub(crate) fn start_busy(
+        &self,
+        now: Instant,
+    ) {
+        let mut busy = self.busy.lock().unwrap_or_else(|e| e.into_inner());
+        busy.push_back((now, None));
+    }
+
+    /// Record that the proving pool stopped being full at `now`.
+    pub(crate) fn end_busy(
+        &self,
+        now: Instant,
+    ) {
+        {
+            let mut busy = self.busy.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((_, end @ None)) = busy.back_mut() {
+                *end = Some(now);
+            }
+        }
+        gauge!("zkmr_worker_saturation").set(self.utilization(now));
+    }
+
+    /// The share of the last `duration` before `now` the proving pool was full.
+    pub(crate) fn utilization(
+        &self,
+        now: Instant,
+    ) -> f64 {
+        let from = now.checked_sub(self.duration).unwrap_or(self.started);
+        let mut busy = self.busy.lock().unwrap_or_else(|e| e.into_inner());
+        while busy
+            .front()
+            .is_some_and(|(_, end)| end.is_some_and(|end| end <= from))
+        {
+            busy.pop_front();
+        }
+        let busy_time = busy
+            .iter()
+            .map(|(start, end)| {
+                end.unwrap_or(now)
+                    .saturating_duration_since((*start).max(from))
+            })
+            .sum::<Duration>();
+        busy_time.as_secs_f64() / self.duration.as_secs_f64()
+    }
+
+    /// Whether the liveness probe is to report the worker as degraded at `now`, which it only does
+    /// if configured to, once the worker has been saturated for the whole last `duration`.
+    pub(crate) fn degraded(
+        &self,
+        now: Instant,
+    ) -> bool {
+        self.liveness == SaturationLiveness::Degraded
+            && now.duration_since(self.started) >= self.duration
+            && self.utilization(now) >= self.threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_degraded_when_saturated() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let saturation = Saturation::new(
+            &SaturationConfig {
+                liveness: SaturationLiveness::Degraded,
+                threshold: 0.9,
+                duration: 100,
+            },
+            start,
+        );
+
+        // Busy for 91 of the first 100s, with short gaps between the tasks.
+        for task in 0..9 {
+            saturation.start_busy(at(task * 10));
+            saturation.end_busy(at(task * 10 + 9));
+        }
+        saturation.start_busy(at(90));
+        assert!(!saturation.degraded(at(95)), "running for less than 100s");
+        assert!(saturation.degraded(at(100)));
+
+        // Idle for long enough to get below the threshold.
+        saturation.end_busy(at(100));
+        assert!(!saturation.degraded(at(105)));
+        assert_eq!(saturation.utilization(at(300)), 0.0);
+
+        // Saturation is normal under load, and only reported if configured to be.
+        let live = Saturation::new(
+            &SaturationConfig {
+                liveness: SaturationLiveness::Live,
+                threshold: 0.9,
+                duration: 100,
+            },
+            start,
+        );
+        live.start_busy(at(0));
+        assert_eq!(live.utilization(at(200)), 1.0);
+        assert!(!live.degraded(at(200)));
+    }
+}
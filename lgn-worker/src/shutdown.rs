@@ -0,0 +1,70 @@
+//! Graceful shutdown on SIGTERM or SIGINT, see `shutdown_grace_secs`.
+//!
+//! Once signaled, the worker reports itself as not ready, takes no new task, and exits once the
+//! task in flight, if any, is replied to; it is forcibly exited if that takes longer than the
+//! grace period.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+use metrics::counter;
+use tokio::signal::unix::signal;
+use tokio::signal::unix::SignalKind;
+use tokio::sync::watch;
+use tracing::error;
+use tracing::warn;
+
+use crate::manager::ProversReadiness;
+
+/// Whether the worker was signaled to shut down.
+#[derive(Clone)]
+pub(crate) struct Shutdown {
+    requested: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Listen for SIGTERM and SIGINT, which then stop the worker within `grace`.
+    pub(crate) fn listen(
+        grace: Duration,
+        readiness: Arc<ProversReadiness>,
+    ) -> Result<Self> {
+        let mut terminate = signal(SignalKind::terminate()).context("listening for SIGTERM")?;
+        let mut interrupt = signal(SignalKind::interrupt()).context("listening for SIGINT")?;
+        let (sender, requested) = watch::channel(false);
+        tokio::spawn(async move {
+            let name = tokio::select! {
+                _ = terminate.recv() => "SIGTERM",
+                _ = interrupt.recv() => "SIGINT",
+            };
+            warn!(
+                "received {name}, shutting down once the task in flight is done, within {}s",
+                grace.as_secs()
+            );
+            counter!("zkmr_worker_shutdowns_total", "signal" => name).increment(1);
+            // The orchestrator is to stop routing tasks to the worker right away.
+            readiness.set_shutting_down();
+            let _ = sender.send(true);
+
+            tokio::time::sleep(grace).await;
+            error!(
+                "the task in flight did not complete within {}s, exiting",
+                grace.as_secs()
+            );
+            std::process::exit(1);
+        });
+
+        Ok(Self { requested })
+    }
+
+    pub(crate) fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Resolves once the shutdown is requested.
+    pub(crate) async fn requested(&mut self) {
+        // The sender is only dropped along with the runtime.
+        let _ = self.requested.wait_for(|requested| *requested).await;
+    }
+}
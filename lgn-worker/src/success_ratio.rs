@@ -0,0 +1,72 @@
+//! The share of the last tasks which were proven successfully, see `worker.success_ratio_window`.
+//!
+//! Rejected tasks are left out: they tell about the tasks sent to the worker, not its health.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use metrics::gauge;
+
+pub(crate) struct SuccessRatio {
+    /// How many of the last tasks are accounted for.
+    window: usize,
+    /// Whether each of the last tasks succeeded, the most recent last.
+    outcomes: Mutex<VecDeque<bool>>,
+}
+
+impl SuccessRatio {
+    pub(crate) fn new(window: usize) -> Self {
+        Self {
+            window,
+            outcomes: Mutex::new(VecDeque::with_capacity(window)),
+        }
+    }
+
+    /// Record whether a task succeeded.
+    pub(crate) fn record(
+        &self,
+        success: bool,
+    ) {
+        let mut outcomes = self.outcomes.lock().unwrap_or_else(|e| e.into_inner());
+        if outcomes.len() == self.window {
+            outcomes.pop_front();
+        }
+        outcomes.push_back(success);
+        gauge!("zkmr_worker_task_success_ratio").set(Self::ratio_of(&outcomes).unwrap_or(1.0));
+    }
+
+    /// The share of the last tasks which succeeded, unknown until a task is done.
+    pub(crate) fn ratio(&self) -> Option<f64> {
+        Self::ratio_of(&self.outcomes.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn ratio_of(outcomes: &VecDeque<bool>) -> Option<f64> {
+        if outcomes.is_empty() {
+            return None;
+        }
+        let successes = outcomes.iter().filter(|success| **success).count();
+        Some(successes as f64 / outcomes.len() as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_success_ratio() {
+        let success_ratio = SuccessRatio::new(4);
+        assert_eq!(success_ratio.ratio(), None);
+
+        success_ratio.record(true);
+        success_ratio.record(false);
+        assert_eq!(success_ratio.ratio(), Some(0.5));
+
+        // Only the last 4 tasks are accounted for.
+        success_ratio.record(false);
+        success_ratio.record(true);
+        success_ratio.record(true);
+        success_ratio.record(true);
+        assert_eq!(success_ratio.ratio(), Some(0.75));
+    }
+}
@@ -0,0 +1,159 @@
+//! Separation of the tasks of the tenants sharing the worker in its observability, see `tenants`.
+//!
+//! Tenant ids come from the gateway: only the allowlisted ones become metric label values, the
+//! others being folded into [`OTHER_TENANT`], for no tenant to blow up the cardinality of the
+//! metrics.
+
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+
+use anyhow::Context;
+use anyhow::Result;
+use metrics::counter;
+use metrics::histogram;
+use serde::Deserialize;
+
+use crate::audit::AuditLog;
+use crate::config::TenantsConfig;
+
+/// The label of the tenants not allowlisted in the metrics.
+pub(crate) const OTHER_TENANT: &str = "other";
+
+/// The label of the tasks without tenant.
+pub(crate) const NO_TENANT: &str = "none";
+
+/// The most tenants which may label the metrics.
+pub(crate) const MAX_TENANT_METRIC_LABELS: usize = 32;
+
+/// The tenant of a task, read without deserializing the task itself.
+#[derive(Deserialize)]
+struct TenantProbe {
+    #[serde(default)]
+    tenant_id: Option<String>,
+}
+
+pub(crate) struct Tenants {
+    /// Whether tenants are told apart at all.
+    enabled: bool,
+    metric_labels: HashSet<String>,
+    audit_logs: BTreeMap<String, AuditLog>,
+}
+
+impl Tenants {
+    pub(crate) fn new(config: Option<&TenantsConfig>) -> Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self {
+                enabled: false,
+                metric_labels: HashSet::new(),
+                audit_logs: BTreeMap::new(),
+            });
+        };
+
+        let audit_logs = config
+            .audit_logs
+            .iter()
+            .map(|(tenant, audit_log)| {
+                let audit_log = AuditLog::new(Some(audit_log))
+                    .with_context(|| format!("setting up the audit log of tenant {tenant}"))?;
+                Ok((tenant.clone(), audit_log))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            enabled: true,
+            metric_labels: config.metric_labels.iter().cloned().collect(),
+            audit_logs,
+        })
+    }
+
+    /// The tenant of the task `payload`, if tenants are told apart.
+    ///
+    /// Read upfront, for the events of a task to all go to the audit log of its tenant.
+    pub(crate) fn tenant_of(
+        &self,
+        payload: &[u8],
+    ) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+        serde_json::from_slice::<TenantProbe>(payload)
+            .ok()
+            .and_then(|probe| probe.tenant_id)
+    }
+
+    /// The audit log of `tenant`, `default` unless it has one of its own.
+    pub(crate) fn audit_log<'a>(
+        &'a self,
+        tenant: Option<&str>,
+        default: &'a AuditLog,
+    ) -> &'a AuditLog {
+        tenant
+            .and_then(|tenant| self.audit_logs.get(tenant))
+            .unwrap_or(default)
+    }
+
+    /// The value of the `tenant` label of `tenant`.
+    fn label<'a>(
+        &'a self,
+        tenant: Option<&'a str>,
+    ) -> &'a str {
+        match tenant {
+            None => NO_TENANT,
+            Some(tenant) if self.metric_labels.contains(tenant) => tenant,
+            Some(_) => OTHER_TENANT,
+        }
+    }
+
+    /// Record the `outcome` of a task of `tenant`, which took `duration_secs`.
+    pub(crate) fn record(
+        &self,
+        tenant: Option<&str>,
+        outcome: &'static str,
+        duration_secs: f64,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let tenant = self.label(tenant).to_string();
+        counter!(
+            "zkmr_worker_tenant_tasks_total",
+            "tenant" => tenant.clone(),
+            "outcome" => outcome
+        )
+        .increment(1);
+        histogram!("zkmr_worker_tenant_task_duration_seconds", "tenant" => tenant)
+            .record(duration_secs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenants() {
+        let config = TenantsConfig {
+            metric_labels: vec!["acme".to_string()],
+            audit_logs: BTreeMap::new(),
+        };
+        let tenants = Tenants::new(Some(&config)).unwrap();
+
+        assert_eq!(
+            tenants.tenant_of(br#"{"query_id":"q","tenant_id":"acme"}"#),
+            Some("acme".to_string())
+        );
+        assert_eq!(tenants.tenant_of(br#"{"query_id":"q"}"#), None);
+        assert_eq!(tenants.tenant_of(b"not json"), None);
+
+        assert_eq!(tenants.label(Some("acme")), "acme");
+        assert_eq!(tenants.label(Some("initech")), OTHER_TENANT);
+        assert_eq!(tenants.label(None), NO_TENANT);
+
+        // Without configuration, tenants are not told apart.
+        let tenants = Tenants::new(None).unwrap();
+        assert_eq!(
+            tenants.tenant_of(br#"{"query_id":"q","tenant_id":"acme"}"#),
+            None
+        );
+    }
+}
@@ -0,0 +1,787 @@
+//! Where the tasks to prove come from, and where their results go.
+
+use std::cmp::Ordering;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::task::Wake;
+use std::task::Waker;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use lgn_messages::types::WorkerError;
+use metrics::counter;
+use metrics::gauge;
+use metrics::histogram;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::mpsc::Sender;
+use tokio_stream::Stream;
+use tokio_stream::StreamExt;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::config::DuplicateTaskPolicy;
+use crate::failed_tasks::classify;
+use crate::isolated::read_frame;
+use crate::isolated::write_frame;
+use crate::lagrange;
+use crate::lagrange::worker_done::Reply;
+use crate::lagrange::WorkerDone;
+use crate::lagrange::WorkerToGwRequest;
+use crate::lagrange::WorkerToGwResponse;
+
+/// A task to prove.
+pub(crate) struct Task<Id> {
+    /// Identifies the task towards the [`ResultSink`].
+    pub(crate) id: Id,
+    /// Human-readable ID of the task, for logs.
+    pub(crate) name: String,
+    /// The serialized `MessageEnvelope<TaskType>`.
+    pub(crate) payload: Vec<u8>,
+    /// When the task was received from the source.
+    pub(crate) received_at: Instant,
+    /// Tells the task apart from the other tasks in flight, if the source identifies its tasks,
+    /// see [`InFlightIds`].
+    pub(crate) key: Option<String>,
+    /// Why the task is to be rejected without being proven, if it is.
+    pub(crate) rejection: Option<String>,
+}
+
+pub(crate) trait TaskSource {
+    type Id;
+
+    /// Returns the next task to prove, or `None` once the source is exhausted.
+    async fn next_task(&mut self) -> Result<Option<Task<Self::Id>>>;
+}
+
+pub(crate) trait ResultSink<Id> {
+    /// Deliver the serialized reply to `task`, or the error which prevented proving it.
+    async fn send_result(
+        &mut self,
+        task: &Task<Id>,
+        result: Result<Vec<u8>, String>,
+    ) -> Result<()>;
+
+    /// Deliver `reply`, the serialized reply telling that `task` is of a class the worker does not
+    /// prove, or the `error` saying so if the sink can not tell it apart from a failure.
+    async fn send_unsupported(
+        &mut self,
+        task: &Task<Id>,
+        _reply: Vec<u8>,
+        error: String,
+    ) -> Result<()> {
+        self.send_result(task, Err(error)).await
+    }
+
+    /// Wait for the results sent so far to be delivered.
+    async fn flush(&mut self) {
+    }
+}
+
+/// A waker for the polls which do not wait, see [`GatewaySource::drain_batch`].
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {
+    }
+}
+
+/// The part of a task envelope the intake orders the tasks by.
+#[derive(Deserialize)]
+struct TaskPriority {
+    #[serde(default)]
+    priority: Option<u8>,
+}
+
+/// A task waiting in a [`TaskQueue`].
+struct Queued<Id> {
+    /// The priority of the task, with the earlier arrivals first among equal priorities.
+    key: (Option<u8>, Reverse<u64>),
+    task: Task<Id>,
+}
+
+impl<Id> PartialEq for Queued<Id> {
+    fn eq(
+        &self,
+        other: &Self,
+    ) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<Id> Eq for Queued<Id> {
+}
+
+impl<Id> PartialOrd for Queued<Id> {
+    fn partial_cmp(
+        &self,
+        other: &Self,
+    ) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<Id> Ord for Queued<Id> {
+    fn cmp(
+        &self,
+        other: &Self,
+    ) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+/// The tasks taken from a source but not proven yet, the ones of higher priority first, then in
+/// arrival order. Tasks without a priority come after the ones with one.
+struct TaskQueue<Id> {
+    heap: BinaryHeap<Queued<Id>>,
+    /// How many tasks were ever pushed, to order the ones of equal priority.
+    arrivals: u64,
+}
+
+impl<Id> TaskQueue<Id> {
+    fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            arrivals: 0,
+        }
+    }
+
+    fn push(
+        &mut self,
+        task: Task<Id>,
+    ) {
+        // A malformed envelope is rejected once popped, its priority does not matter.
+        let priority = serde_json::from_slice::<TaskPriority>(&task.payload)
+            .ok()
+            .and_then(|envelope| envelope.priority);
+        gauge!("zkmr_worker_queue_depth", "priority" => priority_label(priority)).increment(1);
+        self.heap.push(Queued {
+            key: (priority, Reverse(self.arrivals)),
+            task,
+        });
+        self.arrivals += 1;
+    }
+
+    fn pop(&mut self) -> Option<Task<Id>> {
+        let Queued {
+            key: (priority, _),
+            task,
+        } = self.heap.pop()?;
+        gauge!("zkmr_worker_queue_depth", "priority" => priority_label(priority)).decrement(1);
+        Some(task)
+    }
+
+    fn len(&self) -> usize {
+        self.heap.len()
+    }
+}
+
+fn priority_label(priority: Option<u8>) -> String {
+    priority.map_or_else(|| "none".to_string(), |priority| priority.to_string())
+}
+
+/// Tasks pushed by the gateway over the bidirectional stream.
+pub(crate) struct GatewaySource {
+    inbound: tonic::Streaming<WorkerToGwResponse>,
+    /// The most tasks taken from the stream at once, see `worker.task_batch_size`.
+    batch_size: usize,
+    /// The tasks taken from the stream but not proven yet.
+    batch: TaskQueue<Option<lagrange::TaskId>>,
+    /// The end of the stream, if met while taking a batch, to be reported once it is proven.
+    stream_end: Option<Option<Result<WorkerToGwResponse, tonic::Status>>>,
+}
+
+impl GatewaySource {
+    pub(crate) fn new(
+        inbound: tonic::Streaming<WorkerToGwResponse>,
+        batch_size: usize,
+    ) -> Self {
+        Self {
+            inbound,
+            batch_size,
+            batch: TaskQueue::new(),
+            stream_end: None,
+        }
+    }
+
+    /// Take the tasks which already arrived, up to a full batch, without waiting for more.
+    fn drain_batch(&mut self) {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = std::task::Context::from_waker(&waker);
+        while self.batch.len() < self.batch_size {
+            match Pin::new(&mut self.inbound).poll_next(&mut cx) {
+                Poll::Ready(Some(Ok(message))) => self.batch.push(Self::task(message)),
+                Poll::Ready(end) => {
+                    self.stream_end = Some(end);
+                    break;
+                },
+                Poll::Pending => break,
+            }
+        }
+    }
+
+    fn task(message: WorkerToGwResponse) -> Task<Option<lagrange::TaskId>> {
+        let name = match message.task_id.as_ref().map(parse_uuid) {
+            Some(Ok(uuid)) => uuid.to_string(),
+            Some(Err(e)) => {
+                warn!("{e:?}");
+                "UNKNOWN".to_string()
+            },
+            None => "UNKNOWN".to_string(),
+        };
+
+        Task {
+            key: message
+                .task_id
+                .as_ref()
+                .map(|task_id| hex::encode(&task_id.id)),
+            id: message.task_id,
+            name,
+            payload: message.task,
+            received_at: Instant::now(),
+            rejection: None,
+        }
+    }
+}
+
+impl TaskSource for GatewaySource {
+    type Id = Option<lagrange::TaskId>;
+
+    async fn next_task(&mut self) -> Result<Option<Task<Self::Id>>> {
+        if let Some(task) = self.batch.pop() {
+            return Ok(Some(task));
+        }
+
+        let next = match self.stream_end.take() {
+            Some(end) => end,
+            None => self.inbound.next().await,
+        };
+        // The gateway closing the stream, e.g. to recycle it, is routine; a transport failure or
+        // an error status is not.
+        let message = match next {
+            Some(Ok(message)) => message,
+            Some(Err(status)) => {
+                counter!("zkmr_worker_gateway_stream_ends_total", "reason" => "error").increment(1);
+                bail!("connection to the gateway ended with status: {status}");
+            },
+            None => {
+                counter!("zkmr_worker_gateway_stream_ends_total", "reason" => "eof").increment(1);
+                info!("the gateway closed the inbound stream");
+                return Ok(None);
+            },
+        };
+
+        // Amortizes the per-message overhead of small tasks arriving in bursts.
+        self.batch.push(Self::task(message));
+        if self.batch_size > 1 {
+            self.drain_batch();
+            histogram!("zkmr_worker_task_batch_size").record(self.batch.len() as f64);
+        }
+        Ok(self.batch.pop())
+    }
+}
+
+/// The keys of the tasks in flight, from the moment they are started to the one they are replied
+/// to, and the tasks queued behind them, for a task received while another one with the same ID
+/// is in flight to be handled as `worker.duplicate_task_ids` directs: both replies would be
+/// ambiguous to the gateway.
+pub(crate) struct InFlightIds<Id> {
+    /// The tasks queued behind the one in flight, by key.
+    queued: HashMap<String, VecDeque<Task<Id>>>,
+}
+
+impl<Id> InFlightIds<Id> {
+    pub(crate) fn new() -> Self {
+        Self {
+            queued: HashMap::new(),
+        }
+    }
+
+    /// Returns `task` if it is to be started, rejected if another task with the same key is in
+    /// flight and the `policy` says so; it is queued behind that task otherwise.
+    pub(crate) fn admit(
+        &mut self,
+        mut task: Task<Id>,
+        policy: DuplicateTaskPolicy,
+    ) -> Option<Task<Id>> {
+        let Some(queued) = task.key.as_ref().and_then(|key| self.queued.get_mut(key)) else {
+            return Some(task);
+        };
+        counter!("zkmr_worker_duplicate_task_id_total").increment(1);
+        match policy {
+            DuplicateTaskPolicy::Reject => {
+                warn!(
+                    "task {} received while already in flight, rejecting it",
+                    task.name
+                );
+                task.rejection = Some(format!("task {} is already in flight", task.name));
+                Some(task)
+            },
+            DuplicateTaskPolicy::Queue => {
+                warn!(
+                    "task {} received while already in flight, queueing it",
+                    task.name
+                );
+                queued.push_back(task);
+                None
+            },
+        }
+    }
+
+    /// Record that `task` is in flight.
+    pub(crate) fn started(
+        &mut self,
+        task: &Task<Id>,
+    ) {
+        if let Some(key) = &task.key {
+            self.queued.entry(key.clone()).or_default();
+        }
+    }
+
+    /// Record that the task with `key` in flight was replied to, returning the next task queued
+    /// behind it, which is then in flight in its place until it is replied to in turn.
+    pub(crate) fn completed(
+        &mut self,
+        key: Option<&str>,
+    ) -> Option<Task<Id>> {
+        let key = key?;
+        let next = self.queued.get_mut(key)?.pop_front();
+        if next.is_none() {
+            self.queued.remove(key);
+        }
+        next
+    }
+
+    /// How many tasks are queued behind the ones in flight.
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.values().map(VecDeque::len).sum()
+    }
+}
+
+/// Decode a gateway task ID into the UUID identifying the task in logs and metrics, which is
+/// always rendered hyphenated.
+pub(crate) fn parse_uuid(task_id: &lagrange::TaskId) -> Result<Uuid> {
+    let bytes = task_id
+        .id
+        .as_slice()
+        .try_into()
+        .with_context(|| format!("invalid task ID: 0x{}", hex::encode(&task_id.id)))?;
+    Ok(Uuid::from_bytes_le(bytes))
+}
+
+/// Replies sent back to the gateway over the bidirectional stream.
+pub(crate) struct GatewaySink {
+    pub(crate) outbound: Sender<WorkerToGwRequest>,
+}
+
+impl GatewaySink {
+    /// Send `reply` to `task` over the stream.
+    async fn send_reply(
+        &mut self,
+        task: &Task<Option<lagrange::TaskId>>,
+        reply: Reply,
+    ) -> Result<()> {
+        // With a single sender shared by all the replies, the time spent waiting for room in the
+        // channel tells whether the gateway stream is a bottleneck.
+        let send_start = Instant::now();
+        self.outbound
+            .send(WorkerToGwRequest {
+                request: Some(lagrange::worker_to_gw_request::Request::WorkerDone(
+                    WorkerDone {
+                        task_id: task.id.clone(),
+                        reply: Some(reply),
+                    },
+                )),
+            })
+            .await?;
+        histogram!("zkmr_worker_outbound_send_wait_seconds")
+            .record(send_start.elapsed().as_secs_f64());
+
+        counter!("zkmr_worker_grpc_messages_sent_total", "message_type" => "text").increment(1);
+        Ok(())
+    }
+}
+
+impl ResultSink<Option<lagrange::TaskId>> for GatewaySink {
+    async fn send_result(
+        &mut self,
+        task: &Task<Option<lagrange::TaskId>>,
+        result: Result<Vec<u8>, String>,
+    ) -> Result<()> {
+        let reply = match result {
+            Ok(task_output) => Reply::TaskOutput(task_output),
+            Err(error_str) => Reply::WorkerError(error_str),
+        };
+        self.send_reply(task, reply).await
+    }
+
+    /// Sent as a task output, which the gateway can route on, unlike an error.
+    async fn send_unsupported(
+        &mut self,
+        task: &Task<Option<lagrange::TaskId>>,
+        reply: Vec<u8>,
+        _error: String,
+    ) -> Result<()> {
+        self.send_reply(task, Reply::TaskOutput(reply)).await
+    }
+
+    /// Wait for the queued messages to be picked up by the gateway stream, so that the last
+    /// replies are not lost when the worker exits.
+    async fn flush(&mut self) {
+        let flushed = async {
+            while self.outbound.capacity() < self.outbound.max_capacity() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        };
+        if tokio::time::timeout(Duration::from_secs(30), flushed)
+            .await
+            .is_err()
+        {
+            warn!("timed out while waiting for the outbound messages to be sent");
+        }
+    }
+}
+
+/// Tasks read from the JSON files of a directory, in lexicographic order.
+pub(crate) struct DirectorySource {
+    files: VecDeque<PathBuf>,
+}
+
+impl DirectorySource {
+    pub(crate) fn new(dir: &str) -> Result<Self> {
+        let mut files = std::fs::read_dir(dir)
+            .with_context(|| format!("listing tasks in {dir}"))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("listing tasks in {dir}"))?;
+        files.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+        files.sort();
+        info!("found {} tasks in {dir}", files.len());
+
+        Ok(Self {
+            files: files.into(),
+        })
+    }
+}
+
+impl TaskSource for DirectorySource {
+    type Id = PathBuf;
+
+    async fn next_task(&mut self) -> Result<Option<Task<Self::Id>>> {
+        let Some(path) = self.files.pop_front() else {
+            return Ok(None);
+        };
+        let payload =
+            std::fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+
+        Ok(Some(Task {
+            name: path.display().to_string(),
+            id: path,
+            key: None,
+            payload,
+            received_at: Instant::now(),
+            rejection: None,
+        }))
+    }
+}
+
+/// Replies logged, and written to a directory if any.
+pub(crate) struct DirectorySink {
+    pub(crate) dir: Option<PathBuf>,
+}
+
+impl ResultSink<PathBuf> for DirectorySink {
+    async fn send_result(
+        &mut self,
+        task: &Task<PathBuf>,
+        result: Result<Vec<u8>, String>,
+    ) -> Result<()> {
+        let task_output = match result {
+            Ok(task_output) => {
+                info!("{}: proven, {}B reply", task.name, task_output.len());
+                task_output
+            },
+            Err(error_str) => {
+                error!("{}: {error_str}", task.name);
+                return Ok(());
+            },
+        };
+
+        if let Some(dir) = &self.dir {
+            let file_name = task.id.file_name().context("task file without a name")?;
+            let path = dir.join(file_name);
+            std::fs::write(&path, task_output)
+                .with_context(|| format!("writing {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The IDs of a task read from stdin, from its envelope if it can be deserialized that far.
+#[derive(Deserialize, Default)]
+pub(crate) struct StdioTaskId {
+    query_id: String,
+    task_id: String,
+}
+
+/// Tasks read from stdin as length-prefixed frames, see `--stdio`.
+pub(crate) struct StdioSource {
+    /// The largest task accepted, as over the gateway stream.
+    max_task_size: usize,
+}
+
+impl StdioSource {
+    pub(crate) fn new(max_task_size: usize) -> Self {
+        Self { max_task_size }
+    }
+}
+
+impl TaskSource for StdioSource {
+    type Id = StdioTaskId;
+
+    async fn next_task(&mut self) -> Result<Option<Task<Self::Id>>> {
+        let payload = match tokio::task::block_in_place(|| {
+            read_frame(&mut std::io::stdin().lock(), self.max_task_size)
+        }) {
+            Ok(payload) => payload,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e).context("reading task from stdin"),
+        };
+        let id: StdioTaskId = serde_json::from_slice(&payload).unwrap_or_default();
+
+        Ok(Some(Task {
+            name: format!("{}/{}", id.query_id, id.task_id),
+            id,
+            key: None,
+            payload,
+            received_at: Instant::now(),
+            rejection: None,
+        }))
+    }
+}
+
+/// The reply written in place of a `MessageReplyEnvelope` for the tasks which failed.
+#[derive(Serialize)]
+struct ErrorReply<'a> {
+    query_id: &'a str,
+    task_id: &'a str,
+    error: WorkerError,
+    /// The class of the error, as in the failed tasks records.
+    error_code: &'static str,
+}
+
+/// Replies written to stdout as length-prefixed frames, see `--stdio`.
+pub(crate) struct StdioSink;
+
+impl ResultSink<StdioTaskId> for StdioSink {
+    async fn send_result(
+        &mut self,
+        task: &Task<StdioTaskId>,
+        result: Result<Vec<u8>, String>,
+    ) -> Result<()> {
+        let reply = match result {
+            Ok(task_output) => task_output,
+            Err(error_str) => {
+                serde_json::to_vec(&ErrorReply {
+                    query_id: &task.id.query_id,
+                    task_id: &task.id.task_id,
+                    error_code: classify(&error_str),
+                    error: WorkerError::GeneralError(error_str),
+                })?
+            },
+        };
+        tokio::task::block_in_place(|| write_frame(&mut std::io::stdout().lock(), &reply))
+            .context("writing reply to stdout")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lgn_messages::types::MessageReplyEnvelope;
+    use lgn_messages::types::ProverType;
+    use lgn_messages::types::ReplyType;
+
+    use super::*;
+
+    /// Encode `uuid` as a gateway task ID, the inverse of [`parse_uuid`].
+    fn to_task_id(uuid: Uuid) -> lagrange::TaskId {
+        lagrange::TaskId {
+            id: uuid.to_bytes_le().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_task_id_round_trip() {
+        let uuid = Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+        let task_id = to_task_id(uuid);
+        // The gateway sends the first fields of the UUID little-endian.
+        assert_eq!(&task_id.id[..4], &[0x44, 0x50, 0xE5, 0x67]);
+        assert_eq!(parse_uuid(&task_id).unwrap(), uuid);
+        assert_eq!(
+            parse_uuid(&task_id).unwrap().to_string(),
+            "67e55044-10b1-426f-9247-bb680e5fe0c8"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_task_ids() {
+        let task = |uuid, attempt: u8| {
+            let task_id = to_task_id(Uuid::from_u128(uuid));
+            Task {
+                key: Some(hex::encode(&task_id.id)),
+                id: Some(task_id),
+                name: Uuid::from_u128(uuid).to_string(),
+                payload: vec![attempt],
+                received_at: Instant::now(),
+                rejection: None,
+            }
+        };
+
+        // Task 1 and task 2 are being proven at the same time when task 1 comes again.
+        let mut in_flight = InFlightIds::new();
+        for uuid in [1, 2] {
+            let task = in_flight
+                .admit(task(uuid, 0), DuplicateTaskPolicy::Reject)
+                .unwrap();
+            in_flight.started(&task);
+        }
+        let duplicate = in_flight
+            .admit(task(1, 1), DuplicateTaskPolicy::Reject)
+            .unwrap();
+        assert_eq!(
+            duplicate.rejection.as_deref(),
+            Some("task 00000000-0000-0000-0000-000000000001 is already in flight")
+        );
+        // The rejected duplicate is not in flight, its reply does not end the first one.
+        assert!(in_flight.completed(None).is_none());
+        let task_2 = task(2, 0);
+        assert!(in_flight.completed(task_2.key.as_deref()).is_none());
+        assert!(in_flight
+            .admit(task(2, 1), DuplicateTaskPolicy::Reject)
+            .unwrap()
+            .rejection
+            .is_none());
+
+        // Queued duplicates wait for the task in flight, then for each other.
+        let mut in_flight = InFlightIds::new();
+        let first = in_flight
+            .admit(task(1, 0), DuplicateTaskPolicy::Queue)
+            .unwrap();
+        in_flight.started(&first);
+        assert!(in_flight
+            .admit(task(1, 1), DuplicateTaskPolicy::Queue)
+            .is_none());
+        assert!(in_flight
+            .admit(task(1, 2), DuplicateTaskPolicy::Queue)
+            .is_none());
+        assert_eq!(in_flight.queued(), 2);
+
+        let key = first.key.as_deref();
+        let second = in_flight.completed(key).unwrap();
+        assert_eq!(second.payload, [1]);
+        assert!(second.rejection.is_none());
+        assert!(in_flight
+            .admit(task(1, 3), DuplicateTaskPolicy::Queue)
+            .is_none());
+        assert_eq!(in_flight.completed(key).unwrap().payload, [2]);
+        assert_eq!(in_flight.completed(key).unwrap().payload, [3]);
+        assert!(in_flight.completed(key).is_none());
+        assert!(in_flight
+            .admit(task(1, 4), DuplicateTaskPolicy::Queue)
+            .is_some());
+    }
+
+    #[test]
+    fn test_task_queue_order() {
+        let task = |name: &str, priority: Option<u8>| {
+            Task {
+                id: (),
+                key: None,
+                name: name.to_string(),
+                payload: serde_json::to_vec(&serde_json::json!({ "priority": priority })).unwrap(),
+                received_at: Instant::now(),
+                rejection: None,
+            }
+        };
+
+        let mut queue = TaskQueue::new();
+        queue.push(task("a", None));
+        queue.push(task("b", Some(1)));
+        queue.push(task("c", Some(5)));
+        queue.push(task("d", Some(1)));
+        queue.push(Task {
+            payload: b"not JSON".to_vec(),
+            ..task("e", None)
+        });
+        queue.push(task("f", Some(0)));
+
+        let order = std::iter::from_fn(|| queue.pop()).map(|task| task.name);
+        assert_eq!(order.collect::<Vec<_>>(), ["c", "b", "d", "f", "a", "e"]);
+    }
+
+    #[tokio::test]
+    async fn test_gateway_sink_unsupported() {
+        let (outbound, mut outbound_rx) = tokio::sync::mpsc::channel(1);
+        let mut sink = GatewaySink { outbound };
+        let task = Task {
+            id: Some(to_task_id(Uuid::from_u128(1))),
+            key: None,
+            name: Uuid::from_u128(1).to_string(),
+            payload: vec![],
+            received_at: Instant::now(),
+            rejection: None,
+        };
+        let reply = MessageReplyEnvelope::new(
+            "query".to_string(),
+            "task".to_string(),
+            ReplyType::Unsupported(ProverType::V1Groth16),
+        );
+        sink.send_unsupported(
+            &task,
+            serde_json::to_vec(&reply).unwrap(),
+            "unsupported".to_string(),
+        )
+        .await
+        .unwrap();
+
+        let request = outbound_rx.recv().await.unwrap().request;
+        let Some(lagrange::worker_to_gw_request::Request::WorkerDone(WorkerDone {
+            task_id,
+            reply: Some(Reply::TaskOutput(task_output)),
+        })) = request
+        else {
+            panic!("expected a task output, got {request:?}");
+        };
+        assert_eq!(task_id, task.id);
+        let task_output: MessageReplyEnvelope<ReplyType> =
+            serde_json::from_slice(&task_output).unwrap();
+        assert_eq!(
+            task_output.content(),
+            &ReplyType::Unsupported(ProverType::V1Groth16)
+        );
+    }
+
+    #[test]
+    fn test_parse_uuid_invalid_length() {
+        let task_id = lagrange::TaskId {
+            id: vec![0xDE, 0xAD],
+        };
+        assert_eq!(
+            parse_uuid(&task_id).unwrap_err().to_string(),
+            "invalid task ID: 0xdead"
+        );
+    }
+}